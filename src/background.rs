@@ -0,0 +1,110 @@
+use crate::Color;
+use crate::Pattern;
+use crate::Tuple;
+use std::rc::Rc;
+
+// What a ray sees when it does not hit any object in the world
+// It only depends on the direction of the ray, so, it looks infinitely far away
+#[derive(Clone, Debug)]
+pub enum Background {
+    Solid(Color),
+    // Blend from `bottom` (ray pointing straight down) to `top` (ray pointing straight up)
+    Gradient { bottom: Color, top: Color },
+    // Image around the scene, eg.- `TextureMap` with `UVMapping::Spherical` for an equirectangular image
+    // or `CubeMap` for a skybox. The pattern's transformation can be used to rotate the environment
+    Environment(Rc<dyn Pattern>),
+}
+
+impl Background {
+    // Color seen in the `direction`
+    pub fn color_at(&self, direction: Tuple) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                *bottom * (1.0 - t) + *top * t
+            }
+            Background::Environment(pattern) => {
+                // Direction is a vector, so, any translation of the pattern is ignored
+                let pattern_direction = pattern.get_transform().inverse() * direction;
+                pattern.local_pattern_at(pattern_direction)
+            }
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid(Color::black())
+    }
+}
+
+#[cfg(test)]
+mod background_tests {
+    use super::*;
+    use crate::vector;
+    use crate::{Canvas, Matrix, TextureFilter, TextureMap, UVImage, UVMapping};
+    use std::f64::consts::PI;
+
+    // Equirectangular image with the left half red and the right half blue
+    fn environment() -> TextureMap {
+        let mut canvas = Canvas::new(4, 2);
+        for row in 0..2 {
+            for col in 0..4 {
+                let color = if col < 2 { Color::red() } else { Color::blue() };
+                canvas.write_pixel(col, row, color);
+            }
+        }
+        TextureMap::new(
+            Box::new(UVImage::new(canvas, TextureFilter::Nearest)),
+            UVMapping::Spherical,
+        )
+    }
+
+    #[test]
+    fn default_background_is_black() {
+        let b = Background::default();
+        assert_eq!(b.color_at(vector(0.0, 0.0, 1.0)), Color::black());
+    }
+
+    #[test]
+    fn solid_background_ignores_direction() {
+        let b = Background::Solid(Color::new(0.2, 0.4, 0.6));
+        assert_eq!(b.color_at(vector(0.0, 1.0, 0.0)), Color::new(0.2, 0.4, 0.6));
+        assert_eq!(
+            b.color_at(vector(1.0, -1.0, 0.0)),
+            Color::new(0.2, 0.4, 0.6)
+        );
+    }
+
+    #[test]
+    fn gradient_background_blends_vertically() {
+        let b = Background::Gradient {
+            bottom: Color::white(),
+            top: Color::blue(),
+        };
+        assert_eq!(b.color_at(vector(0.0, 1.0, 0.0)), Color::blue());
+        assert_eq!(b.color_at(vector(0.0, -1.0, 0.0)), Color::white());
+        assert_eq!(b.color_at(vector(0.0, 0.0, 2.0)), Color::new(0.5, 0.5, 1.0));
+    }
+
+    #[test]
+    fn environment_background_is_sampled_by_direction() {
+        let b = Background::Environment(Rc::new(environment()));
+        // u = 0.25 is on the left half and u = 0.75 is on the right half of the image
+        assert_eq!(b.color_at(vector(1.0, 0.0, 0.0)), Color::red());
+        assert_eq!(b.color_at(vector(-1.0, 0.0, 0.0)), Color::blue());
+    }
+
+    #[test]
+    fn environment_background_can_be_rotated() {
+        let mut pattern = environment();
+        pattern.set_transform(
+            Matrix::get_rotation_y_matrix(PI) * Matrix::get_translation_matrix(5.0, 0.0, 0.0),
+        );
+        let b = Background::Environment(Rc::new(pattern));
+        // Half turn swaps the sides of the environment, translation has no effect
+        assert_eq!(b.color_at(vector(1.0, 0.0, 0.0)), Color::blue());
+        assert_eq!(b.color_at(vector(-1.0, 0.0, 0.0)), Color::red());
+    }
+}
@@ -1,4 +1,4 @@
-use ray_tracer::{point, vector, Canvas, Color, Environment, Projectile};
+use ray_tracer::{point, vector, Canvas, Color, Environment, Projectile, ToneMap};
 
 fn main() {
     let starting_position = point(0.0, 1.0, 0.0);
@@ -41,6 +41,7 @@ fn main() {
         environment.tick();
     }
 
-    let ppm_string = canvas.get_ppm();
-    canvas.write_ppm(&ppm_string, "chapter2_end.ppm");
+    canvas
+        .save_ppm("chapter2_end.ppm", ToneMap::default())
+        .expect("Unable to write ppm");
 }
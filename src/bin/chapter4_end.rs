@@ -1,4 +1,4 @@
-use ray_tracer::{point, Canvas, Color, Matrix};
+use ray_tracer::{point, Canvas, Color, Matrix, ToneMap};
 use std::f64::consts::PI;
 
 // Draw pixels around a circle where hours are present in a clock, center of clock is center of canvas
@@ -28,6 +28,7 @@ fn main() {
     }
 
     // Save the canvas to a file
-    let ppm_string = canvas.get_ppm();
-    canvas.write_ppm(&ppm_string, "chapter4_end.ppm");
+    canvas
+        .save_ppm("chapter4_end.ppm", ToneMap::default())
+        .expect("Unable to write ppm");
 }
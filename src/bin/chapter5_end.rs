@@ -1,5 +1,5 @@
 use ray_tracer::Shape;
-use ray_tracer::{point, Canvas, Color, Ray, Sphere};
+use ray_tracer::{point, Canvas, Color, Ray, Sphere, ToneMap};
 // use std::f64::consts::PI;
 
 // Cast a shadow of a sphere on a canvas located at `canvas_z` parallel to the `xy` axis
@@ -40,6 +40,7 @@ fn main() {
         }
     }
 
-    let ppm_string = canvas.get_ppm();
-    canvas.write_ppm(&ppm_string, "chapter5_end.ppm");
+    canvas
+        .save_ppm("chapter5_end.ppm", ToneMap::default())
+        .expect("Unable to write ppm");
 }
@@ -1,5 +1,5 @@
 use ray_tracer::Shape;
-use ray_tracer::{point, Canvas, Color, Light, Material, Ray, Sphere};
+use ray_tracer::{point, Canvas, Color, Light, Material, Ray, Sphere, ToneMap};
 // use std::f64::consts::PI;
 
 // shading routine of a sphere on a canvas located at `canvas_z` parallel to the `xy` axis
@@ -41,10 +41,15 @@ fn main() {
                 let point_normal = hit.object.normal_at(hit_point);
                 let eye_vector = -r.direction;
 
-                let calculated_color =
-                    hit.object
-                        .material
-                        .lighting(light, hit_point, eye_vector, point_normal, false);
+                let calculated_color = hit.object.get_material().lighting(
+                    hit.object,
+                    light,
+                    hit_point,
+                    eye_vector,
+                    point_normal,
+                    false,
+                    r.time,
+                );
 
                 // Translate with respect to cavas coordinate space
                 let translated_x = (x + half_width) as usize;
@@ -54,6 +59,7 @@ fn main() {
         }
     }
 
-    let ppm_string = canvas.get_ppm();
-    canvas.write_ppm(&ppm_string, "chapter6_end.ppm");
+    canvas
+        .save_ppm("chapter6_end.ppm", ToneMap::default())
+        .expect("Unable to write ppm");
 }
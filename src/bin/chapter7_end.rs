@@ -1,5 +1,5 @@
 use ray_tracer::Shape;
-use ray_tracer::{point, vector, Camera, Color, Light, Matrix, Sphere, World};
+use ray_tracer::{point, vector, Camera, Color, Light, Matrix, Sphere, ToneMap, World};
 use std::f64::consts::{FRAC_PI_2, FRAC_PI_3, FRAC_PI_4};
 
 fn main() {
@@ -15,7 +15,7 @@ fn main() {
             * Matrix::get_rotation_x_matrix(FRAC_PI_2)
             * Matrix::get_scaling_matrix(10.0, 0.01, 10.0),
     );
-    left_wall.material = floor.material;
+    left_wall.material = floor.material.clone();
 
     let mut right_wall = Sphere::default();
     right_wall.set_transform(
@@ -24,7 +24,7 @@ fn main() {
             * Matrix::get_rotation_x_matrix(FRAC_PI_2)
             * Matrix::get_scaling_matrix(10.0, 0.01, 10.0),
     );
-    right_wall.material = floor.material;
+    right_wall.material = floor.material.clone();
 
     let mut middle = Sphere::default();
     middle.set_transform(Matrix::get_translation_matrix(-0.5, 1.0, 0.5));
@@ -54,12 +54,12 @@ fn main() {
         ..Default::default()
     };
     world.objects.clear();
-    world.objects.push(floor);
-    world.objects.push(left_wall);
-    world.objects.push(right_wall);
-    world.objects.push(middle);
-    world.objects.push(right);
-    world.objects.push(left);
+    world.objects.push(Box::new(floor));
+    world.objects.push(Box::new(left_wall));
+    world.objects.push(Box::new(right_wall));
+    world.objects.push(Box::new(middle));
+    world.objects.push(Box::new(right));
+    world.objects.push(Box::new(left));
 
     // let mut camera = Camera::new(1000, 500, FRAC_PI_3);
     let mut camera = Camera::new(100, 50, FRAC_PI_3);
@@ -70,6 +70,7 @@ fn main() {
     );
 
     let canvas = camera.render(&world);
-    let ppm_string = canvas.get_ppm();
-    canvas.write_ppm(&ppm_string, "chapter7_end.ppm");
+    canvas
+        .save_ppm("chapter7_end.ppm", ToneMap::default())
+        .expect("Unable to write ppm");
 }
@@ -0,0 +1,81 @@
+use ray_tracer::Shape;
+use ray_tracer::{point, vector, Camera, Color, Light, Matrix, RenderJob, Sphere, ToneMap, World};
+use std::env;
+use std::f64::consts::FRAC_PI_3;
+use std::path::Path;
+use std::process;
+
+// Renders a scene split into tiles, possibly over many machines sharing the job directory
+//
+// render_farm split <dir> [tile_size]    writes the jobs
+// render_farm work <dir>                 renders jobs until none are left, run as many as you like
+// render_farm merge <dir> <output.ppm>   stitches the tiles into the image
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("split"), Some(dir)) => {
+            let tile_size = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(64);
+            let jobs = RenderJob::split("spheres", &camera(), None, tile_size);
+            RenderJob::write_all(Path::new(dir), &jobs)
+                .map(|paths| println!("{} jobs written", paths.len()))
+        }
+        (Some("work"), Some(dir)) => RenderJob::work(Path::new(dir), scene)
+            .map(|rendered| println!("{} tiles rendered", rendered)),
+        (Some("merge"), Some(dir)) if args.len() > 3 => RenderJob::merge(Path::new(dir))
+            .and_then(|canvas| canvas.save_ppm(&args[3], ToneMap::default())),
+        _ => {
+            eprintln!("usage: render_farm split <dir> [tile_size] | work <dir> | merge <dir> <output.ppm>");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("render_farm: {}", e);
+        process::exit(1);
+    }
+}
+
+fn camera() -> Camera {
+    Camera::builder(1000, 500)
+        .look_at(
+            point(0.0, 1.5, -5.0),
+            point(0.0, 1.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+        )
+        .field_of_view(FRAC_PI_3)
+        .build()
+}
+
+// Worlds the jobs can name
+fn scene(name: &str) -> Option<World> {
+    match name {
+        "spheres" => Some(spheres()),
+        _ => None,
+    }
+}
+
+fn spheres() -> World {
+    let mut floor = Sphere::default();
+    floor.set_transform(Matrix::get_scaling_matrix(10.0, 0.01, 10.0));
+    floor.material.color = Color::new(1.0, 0.9, 0.9);
+    floor.material.specular = 0.0;
+
+    let mut middle = Sphere::default();
+    middle.set_transform(Matrix::get_translation_matrix(-0.5, 1.0, 0.5));
+    middle.material.color = Color::new(0.1, 1.0, 0.5);
+    middle.material.diffuse = 0.7;
+    middle.material.specular = 0.3;
+
+    let mut right = Sphere::default();
+    right.set_transform(
+        Matrix::get_translation_matrix(1.5, 0.5, -0.5) * Matrix::get_scaling_matrix(0.5, 0.5, 0.5),
+    );
+    right.material.color = Color::new(0.5, 1.0, 0.1);
+    right.material.diffuse = 0.7;
+    right.material.specular = 0.3;
+
+    World {
+        light: Light::new(point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)),
+        objects: vec![Box::new(floor), Box::new(middle), Box::new(right)],
+        ..Default::default()
+    }
+}
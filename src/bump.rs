@@ -0,0 +1,158 @@
+use crate::noise::fractal_noise;
+use crate::vector;
+use crate::Pattern;
+use crate::Shape;
+use crate::Tuple;
+use std::rc::Rc;
+
+// Distance used for the finite differences when finding the slope of the height
+const DELTA: f64 = 1.0e-4;
+
+// Fakes small bumps on a surface by tilting its normal, without adding any geometry
+// The surface is treated as if it was moved along the normal by a height function,
+// and the normal is tilted against the slope (gradient) of the height
+#[derive(Clone, Debug)]
+pub enum BumpMap {
+    // Perlin noise in the object space, eg.- ripples on water or rough stone
+    // `scale` is about the size of a bump, more `octaves` add finer details
+    Noise {
+        scale: f64,
+        octaves: u32,
+        strength: f64,
+    },
+    // Luminance of the pattern is the height, eg.- a height texture with `TextureFilter::Bilinear`
+    Height {
+        pattern: Rc<dyn Pattern>,
+        strength: f64,
+    },
+}
+
+impl BumpMap {
+    // Height of the surface at the world point `p` of the `object`, seen at the `time`
+    fn height_at(&self, object: &dyn Shape, p: Tuple, time: f64) -> f64 {
+        match self {
+            BumpMap::Noise { scale, octaves, .. } => {
+                let object_point = object.transform_at(time).inverse() * p;
+                fractal_noise(object_point * (1.0 / scale), *octaves)
+            }
+            BumpMap::Height { pattern, .. } => {
+                pattern.pattern_at_shape(object, p, time).luminance()
+            }
+        }
+    }
+
+    fn strength(&self) -> f64 {
+        match self {
+            BumpMap::Noise { strength, .. } | BumpMap::Height { strength, .. } => *strength,
+        }
+    }
+
+    // Tilt the unit normal `normalv` at the world point `p` of the `object`, seen at the `time`
+    pub fn perturb_normal(&self, object: &dyn Shape, p: Tuple, normalv: Tuple, time: f64) -> Tuple {
+        // Central differences along the world axes
+        let slope = |axis: Tuple| {
+            (self.height_at(object, p + axis * DELTA, time)
+                - self.height_at(object, p - axis * DELTA, time))
+                / (2.0 * DELTA)
+        };
+        let gradient = vector(
+            slope(vector(1.0, 0.0, 0.0)),
+            slope(vector(0.0, 1.0, 0.0)),
+            slope(vector(0.0, 0.0, 1.0)),
+        );
+        // Only the part of the slope along the surface tilts the normal
+        let surface_gradient = gradient - normalv * gradient.dot(&normalv);
+        (normalv - surface_gradient * self.strength()).normalize()
+    }
+}
+
+// Patterns are compared by identity as they can be arbitrary trait objects
+impl PartialEq for BumpMap {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                BumpMap::Noise {
+                    scale,
+                    octaves,
+                    strength,
+                },
+                BumpMap::Noise {
+                    scale: other_scale,
+                    octaves: other_octaves,
+                    strength: other_strength,
+                },
+            ) => scale == other_scale && octaves == other_octaves && strength == other_strength,
+            (
+                BumpMap::Height { pattern, strength },
+                BumpMap::Height {
+                    pattern: other_pattern,
+                    strength: other_strength,
+                },
+            ) => Rc::ptr_eq(pattern, other_pattern) && strength == other_strength,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod bump_tests {
+    use super::*;
+    use crate::pattern::TestPattern;
+    use crate::{point, Compare, Matrix, Sphere};
+
+    #[test]
+    fn zero_strength_keeps_normal() {
+        let bump = BumpMap::Noise {
+            scale: 0.3,
+            octaves: 3,
+            strength: 0.0,
+        };
+        let n = vector(0.0, 0.0, -1.0);
+        let p = point(0.13, 0.21, -0.97);
+        assert_eq!(bump.perturb_normal(&Sphere::default(), p, n, 0.0), n);
+    }
+
+    #[test]
+    fn noise_tilts_normal() {
+        let bump = BumpMap::Noise {
+            scale: 0.3,
+            octaves: 3,
+            strength: 0.5,
+        };
+        let n = vector(0.0, 0.0, -1.0);
+        let p = point(0.13, 0.21, -0.97);
+        let perturbed = bump.perturb_normal(&Sphere::default(), p, n, 0.0);
+        assert_ne!(perturbed, n);
+        assert!(perturbed.magnitude().eq(1.0));
+        assert!(perturbed.dot(&n) > 0.0);
+    }
+
+    #[test]
+    fn height_pattern_tilts_normal_against_slope() {
+        // Height is the luminance of the point, 0.2126x + 0.7152y + 0.0722z
+        let bump = BumpMap::Height {
+            pattern: Rc::new(TestPattern::default()),
+            strength: 1.0,
+        };
+        let n = vector(0.0, 0.0, -1.0);
+        let perturbed = bump.perturb_normal(&Sphere::default(), point(0.0, 0.0, -1.0), n, 0.0);
+        assert_eq!(perturbed, vector(-0.2126, -0.7152, -1.0).normalize());
+    }
+
+    #[test]
+    fn noise_moves_with_object() {
+        let bump = BumpMap::Noise {
+            scale: 0.3,
+            octaves: 2,
+            strength: 0.5,
+        };
+        let n = vector(0.0, 0.0, -1.0);
+        let mut moved = Sphere::default();
+        moved.set_transform(Matrix::get_translation_matrix(5.0, 0.0, 0.0));
+        let p = point(0.13, 0.21, -0.97);
+        assert_eq!(
+            bump.perturb_normal(&Sphere::default(), p, n, 0.0),
+            bump.perturb_normal(&moved, p + vector(5.0, 0.0, 0.0), n, 0.0)
+        );
+    }
+}
@@ -1,92 +1,576 @@
-use crate::point;
+use crate::random::Rng;
 use crate::Canvas;
+use crate::Color;
 use crate::Matrix;
+use crate::Motion;
+use crate::PathTracer;
 use crate::Ray;
+use crate::Region;
+use crate::Tuple;
 use crate::World;
+use crate::{point, vector};
+use std::f64::consts::PI;
 
 // Lets us take pictures of the scene
 // Main responsibility is to map the 3D scene to a 2D canvas, by projecting rays through the camera to the canvas
 // The camera's canvas will always be exactly one unit in front of the camera
+// Build it with `Camera::new` or, to set up the view and the lens at once, with `Camera::builder`
+#[derive(Clone, Debug)]
 pub struct Camera {
-    hsize: u16,            // Horizontal size in pixels of the canvas
-    vsize: u16,            // Vertical size in pixels of the canvas
-    field_of_view: f64,    // An angle that describes how much the camera can see
+    hsize: u32,            // Horizontal size in pixels of the canvas
+    vsize: u32,            // Vertical size in pixels of the canvas
+    field_of_view: f64, // An angle that describes how much the camera can see, across the longer side of the canvas
     pub transform: Matrix, // Transformation matrix that describes how the world is moved relative to the camera (is a view transform)
     aspect: f64,           // Ascpect ratio of the canvas
     half_width: f64,       // Just half of the width of the canvas
     half_height: f64,      // Just half of the height of the canvas
     pixel_size: f64,       // Size of a single pixel
+    // Thin lens for depth of field. Rays of a pixel leave from random points of the lens and meet at the focal plane,
+    // so, only the objects at `focal_distance` are sharp, and the bigger the aperture, the blurrier the rest
+    pub aperture: f64, // Radius of the lens, 0.0 is a pinhole camera where everything is sharp
+    pub focal_distance: f64, // Distance in front of the camera which is in focus
+    pub aperture_blades: u32, // Below 3 the lens is round, otherwise a polygon, which shapes the out of focus highlights (bokeh)
+    pub samples: usize, // Rays averaged for each pixel by `render`, more give smoother blur and edges
+    pub seed: u64,      // Seed of the random positions in the pixels and on the lens
+    // Motion blur, rays are shot at random times while the shutter is open, when objects may be in different places
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    pub motion: Option<Motion>, // Overrides `transform` for a moving camera
+    pub projection: Projection, // How the rays are spread over the canvas
 }
 
-impl Camera {
-    pub fn new(hsize: u16, vsize: u16, field_of_view: f64) -> Camera {
-        let half_view = f64::tan(field_of_view / 2.0);
-        let aspect = hsize as f64 / vsize as f64;
-        let (mut half_width, mut half_height) = (half_view, half_view);
-        if aspect >= 1.0 {
-            half_height = half_view / aspect;
-        } else {
-            half_width = half_view * aspect;
-        }
-        let pixel_size = (half_width * 2.0) / hsize as f64;
+// How the camera maps the directions around it to the canvas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    // Like a photo, uses `field_of_view` of the camera. The only projection with depth of field
+    Perspective,
+    // Parallel rays, objects keep their size at any distance, eg.- for technical drawings
+    // `width` is the size of the view in world units
+    Orthographic { width: f64 },
+    // Whole sphere around the camera in latitude and longitude, for 360° images
+    // The canvas should be twice as wide as it is high
+    Equirectangular,
+    // Equidistant fisheye lens, `field_of_view` can be above PI, up to 2 * PI
+    Fisheye { field_of_view: f64 },
+}
 
-        Camera {
+impl Camera {
+    pub fn new(hsize: u32, vsize: u32, field_of_view: f64) -> Camera {
+        let mut camera = Camera {
             hsize,
             vsize,
             field_of_view,
             transform: Matrix::I(),
-            aspect,
-            half_width,
-            half_height,
-            pixel_size,
+            aspect: 0.0,
+            half_width: 0.0,
+            half_height: 0.0,
+            pixel_size: 0.0,
+            aperture: 0.0,
+            focal_distance: 1.0,
+            aperture_blades: 0,
+            samples: 1,
+            seed: 0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: None,
+            projection: Projection::Perspective,
+        };
+        camera.update_pixel_size();
+        camera
+    }
+
+    pub fn builder(hsize: u32, vsize: u32) -> CameraBuilder {
+        CameraBuilder::new(hsize, vsize)
+    }
+
+    // Works out the size of the canvas one unit in front of the camera, and of its pixels
+    fn update_pixel_size(&mut self) {
+        let half_view = f64::tan(self.field_of_view / 2.0);
+        self.aspect = self.hsize as f64 / self.vsize as f64;
+        if self.aspect >= 1.0 {
+            self.half_width = half_view;
+            self.half_height = half_view / self.aspect;
+        } else {
+            self.half_width = half_view * self.aspect;
+            self.half_height = half_view;
         }
+        self.pixel_size = (self.half_width * 2.0) / self.hsize as f64;
+    }
+
+    pub fn get_hsize(&self) -> u32 {
+        self.hsize
+    }
+
+    pub fn get_vsize(&self) -> u32 {
+        self.vsize
+    }
+
+    pub fn set_size(&mut self, hsize: u32, vsize: u32) {
+        self.hsize = hsize;
+        self.vsize = vsize;
+        self.update_pixel_size();
+    }
+
+    pub fn get_field_of_view(&self) -> f64 {
+        self.field_of_view
+    }
+
+    pub fn set_field_of_view(&mut self, field_of_view: f64) {
+        self.field_of_view = field_of_view;
+        self.update_pixel_size();
+    }
+
+    // Field of view across the width of the canvas
+    pub fn get_horizontal_field_of_view(&self) -> f64 {
+        2.0 * self.half_width.atan()
+    }
+
+    // Field of view across the height of the canvas
+    pub fn get_vertical_field_of_view(&self) -> f64 {
+        2.0 * self.half_height.atan()
+    }
+
+    pub fn get_aspect(&self) -> f64 {
+        self.aspect
+    }
+
+    pub fn get_half_width(&self) -> f64 {
+        self.half_width
+    }
+
+    pub fn get_half_height(&self) -> f64 {
+        self.half_height
+    }
+
+    pub fn get_pixel_size(&self) -> f64 {
+        self.pixel_size
     }
 
     /// Returns a ray that starts at the camera and passes through the (x,y) pixel on the canvas
     /// Camera is at origin and canvas is at (0, 0, -1)
-    pub fn ray_for_pixel(&self, x: u16, y: u16) -> Ray {
+    pub fn ray_for_pixel(&self, x: u32, y: u32) -> Ray {
         // Get the pixel center
-        let xoffset = (x as f64 + 0.5) * self.pixel_size;
-        let yoffset = (y as f64 + 0.5) * self.pixel_size;
+        self.ray_for_position(x as f64 + 0.5, y as f64 + 0.5)
+    }
+
+    // Returns a ray through any position of the canvas, measured in pixels from its top left corner
+    // eg.- (0.5, 0.5) is the center of the first pixel
+    // The ray is shot when the shutter opens
+    pub fn ray_for_position(&self, x: f64, y: f64) -> Ray {
+        self.ray_from_lens(x, y, 0.0, 0.0, self.focal_distance, self.shutter_open)
+    }
+
+    // Returns a ray through the position (x, y) of the canvas, which leaves from a random point of the lens
+    // at a random time while the shutter is open
+    pub fn ray_through_lens(&self, x: f64, y: f64, rng: &mut Rng) -> Ray {
+        let time = self.sample_time(rng);
+        if self.aperture <= 0.0 {
+            return self.ray_from_lens(x, y, 0.0, 0.0, self.focal_distance, time);
+        }
+        let (lens_x, lens_y) = sample_aperture(self.aperture_blades, rng);
+        let (lens_x, lens_y) = (lens_x * self.aperture, lens_y * self.aperture);
+        self.ray_from_lens(x, y, lens_x, lens_y, self.focal_distance, time)
+    }
+
+    // Random time while the shutter is open
+    pub(crate) fn sample_time(&self, rng: &mut Rng) -> f64 {
+        if self.shutter_close > self.shutter_open {
+            self.shutter_open + rng.next_f64() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        }
+    }
 
-        // Change pixel coordinates to world coordinates
-        let world_x = self.half_width - xoffset;
-        let world_y = self.half_height - yoffset;
+    // View transformation at the `time`
+    pub fn transform_at(&self, time: f64) -> Matrix {
+        match &self.motion {
+            Some(motion) => motion.transform_at(time),
+            None => self.transform,
+        }
+    }
+
+    // Ray through the position (x, y) of the canvas, leaving from (lens_x, lens_y) of the lens in camera space,
+    // towards where the ray from the center of the lens is at `focal_distance`
+    pub(crate) fn ray_from_lens(
+        &self,
+        x: f64,
+        y: f64,
+        lens_x: f64,
+        lens_y: f64,
+        focal_distance: f64,
+        time: f64,
+    ) -> Ray {
+        // In camera space, the camera looks towards -z, with +y up and +x on the left
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => {
+                let xoffset = x * self.pixel_size;
+                let yoffset = y * self.pixel_size;
+
+                // Change pixel coordinates to world coordinates
+                let world_x = self.half_width - xoffset;
+                let world_y = self.half_height - yoffset;
+
+                // The canvas point seen from the center of the lens, moved out to the focal plane,
+                // where all the rays through the pixel meet
+                let focus = point(
+                    world_x * focal_distance,
+                    world_y * focal_distance,
+                    -focal_distance,
+                );
+                let origin = point(lens_x, lens_y, 0.0);
+                (origin, focus - origin)
+            }
+            Projection::Orthographic { width } => {
+                let scale = width / self.hsize as f64;
+                let origin = point(
+                    (self.hsize as f64 / 2.0 - x) * scale,
+                    (self.vsize as f64 / 2.0 - y) * scale,
+                    0.0,
+                );
+                (origin, vector(0.0, 0.0, -1.0))
+            }
+            Projection::Equirectangular => {
+                // Longitude from -PI (left edge) to PI (right edge), latitude from PI / 2 (top) to -PI / 2 (bottom)
+                let longitude = (x / self.hsize as f64 - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - y / self.vsize as f64) * PI;
+                let direction = vector(
+                    -latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                (point(0.0, 0.0, 0.0), direction)
+            }
+            Projection::Fisheye { field_of_view } => {
+                // Equidistant fisheye, the angle from the center of the view grows with the distance from
+                // the center of the canvas, `field_of_view` spans the width
+                let right = x - self.hsize as f64 / 2.0;
+                let up = self.vsize as f64 / 2.0 - y;
+                let distance = (right * right + up * up).sqrt();
+                let angle = distance * field_of_view / self.hsize as f64;
+                let direction = if distance > 0.0 {
+                    vector(
+                        -angle.sin() * right / distance,
+                        angle.sin() * up / distance,
+                        -angle.cos(),
+                    )
+                } else {
+                    vector(0.0, 0.0, -1.0)
+                };
+                (point(0.0, 0.0, 0.0), direction)
+            }
+        };
 
         // Transform the point in the canvas
         // Basically, move the camera relative to the world
-        let camera_transform_inv = self.transform.inverse();
-        let pixel = camera_transform_inv * point(world_x, world_y, -1.0);
-        let origin = camera_transform_inv * point(0.0, 0.0, 0.0);
-        let direction = (pixel - origin).normalize();
-        Ray::new(origin, direction)
+        let camera_transform_inv = self.transform_at(time).inverse();
+        let origin = camera_transform_inv * origin;
+        let direction = (camera_transform_inv * direction).normalize();
+        Ray::new(origin, direction).with_time(time)
+    }
+
+    // Average color of `samples` rays through random positions of the pixel (x, y)
+    // `ray_at(x, y, rng)` gives the ray through a position of the canvas, eg.- `ray_through_lens`
+    pub(crate) fn sample_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        samples: usize,
+        seed: u64,
+        ray_at: impl Fn(f64, f64, &mut Rng) -> Ray,
+        mut color_at: impl FnMut(&Ray, &mut Rng) -> Color,
+    ) -> Color {
+        let mut rng = Rng::from_pixel(seed, x, y);
+        let mut total = Color::black();
+        for _ in 0..samples {
+            let ray = ray_at(
+                x as f64 + rng.next_f64(),
+                y as f64 + rng.next_f64(),
+                &mut rng,
+            );
+            total = total + color_at(&ray, &mut rng);
+        }
+        total * (1.0 / samples.max(1) as f64)
     }
 
     /// Renders the world with the camera and returns the canvas
     /// A ray is casted through the pixel and the pixel is colored with the corresponding intersection
+    /// With more than one sample, an aperture or an open shutter, the pixel averages rays through the pixel
+    /// and the lens shot at different times
     pub fn render(&self, world: &World) -> Canvas {
-        let mut canvas = Canvas::new(self.hsize as usize, self.vsize as usize);
-        for y in 0..self.vsize {
-            for x in 0..self.hsize {
-                let ray = self.ray_for_pixel(x, y);
-                let color = world.color_at(&ray);
-                canvas.write_pixel(x as usize, y as usize, color);
+        self.render_region(world, self.full_region())
+    }
+
+    // Renders only the pixels of the region of the image into a canvas of its size
+    // Pixels get the same rays as in a full render, so, the canvas matches the same part of `render`
+    // The region is clipped to the image, so, the canvas is smaller when the region goes past its edges
+    pub fn render_region(&self, world: &World, region: Region) -> Canvas {
+        let pinhole =
+            self.samples <= 1 && self.aperture <= 0.0 && self.shutter_close <= self.shutter_open;
+        self.render_pixels(region, |x, y| {
+            if pinhole {
+                world.color_at(&self.ray_for_pixel(x as u32, y as u32))
+            } else {
+                self.sample_pixel(
+                    x,
+                    y,
+                    self.samples,
+                    self.seed,
+                    |x, y, rng| self.ray_through_lens(x, y, rng),
+                    |ray, _| world.color_at(ray),
+                )
+            }
+        })
+    }
+
+    // Renders the world with the Monte Carlo path tracer instead of `World::color_at`
+    // Every pixel averages `samples_per_pixel` rays through random positions of the pixel and of the lens,
+    // which also smooths the edges. `samples` and `seed` of the camera are not used
+    pub fn render_path_traced(&self, world: &World, tracer: &PathTracer) -> Canvas {
+        self.render_path_traced_region(world, tracer, self.full_region())
+    }
+
+    // Like `render_region`, with the path tracer
+    pub fn render_path_traced_region(
+        &self,
+        world: &World,
+        tracer: &PathTracer,
+        region: Region,
+    ) -> Canvas {
+        self.render_pixels(region, |x, y| {
+            self.sample_pixel(
+                x,
+                y,
+                tracer.samples_per_pixel,
+                tracer.seed,
+                |x, y, rng| self.ray_through_lens(x, y, rng),
+                |ray, rng| tracer.color_at(world, ray, rng),
+            )
+        })
+    }
+
+    pub fn full_region(&self) -> Region {
+        Region::full(self.hsize as usize, self.vsize as usize)
+    }
+
+    // Canvas of the region clipped to the image, with the color of each pixel given by `color_at(x, y)`
+    pub(crate) fn render_pixels(
+        &self,
+        region: Region,
+        mut color_at: impl FnMut(usize, usize) -> Color,
+    ) -> Canvas {
+        let region = region.clip(self.hsize as usize, self.vsize as usize);
+        let mut canvas = Canvas::new(region.width, region.height);
+        for row in 0..region.height {
+            for col in 0..region.width {
+                canvas.write_pixel(col, row, color_at(region.x + col, region.y + row));
             }
         }
         canvas
     }
 }
 
+// How wide the view of a `CameraBuilder` is given
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum FieldOfView {
+    LongerSide(f64),
+    Horizontal(f64),
+    Vertical(f64),
+    // Like a real camera, in any units, as long as both are the same, eg.- millimeters
+    FocalLength {
+        focal_length: f64,
+        sensor_width: f64,
+    },
+}
+
+// Sets up a `Camera` in one go, eg.-
+// Camera::builder(1920, 1080).look_at(from, to, up).focal_length(50.0, 36.0).aperture(0.1).build()
+// The field of view is worked out when building, once the aspect ratio of the canvas is known
+#[derive(Clone, Debug)]
+pub struct CameraBuilder {
+    hsize: u32,
+    vsize: u32,
+    field_of_view: FieldOfView,
+    transform: Matrix,
+    aperture: f64,
+    focal_distance: f64,
+    aperture_blades: u32,
+    samples: usize,
+    seed: u64,
+    shutter_open: f64,
+    shutter_close: f64,
+    motion: Option<Motion>,
+    projection: Projection,
+}
+
+impl CameraBuilder {
+    // Same defaults as `Camera::new`, with a field of view of PI / 3 across the longer side
+    pub fn new(hsize: u32, vsize: u32) -> Self {
+        Self {
+            hsize,
+            vsize,
+            field_of_view: FieldOfView::LongerSide(PI / 3.0),
+            transform: Matrix::I(),
+            aperture: 0.0,
+            focal_distance: 1.0,
+            aperture_blades: 0,
+            samples: 1,
+            seed: 0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: None,
+            projection: Projection::Perspective,
+        }
+    }
+
+    // Camera at `from` looking towards `to`, `up` is roughly the up direction of the view
+    pub fn look_at(mut self, from: Tuple, to: Tuple, up: Tuple) -> Self {
+        self.transform = Matrix::get_view_transform(from, to, up);
+        self
+    }
+
+    pub fn transform(mut self, transform: Matrix) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    // Field of view across the longer side of the canvas, like `Camera::new`
+    pub fn field_of_view(mut self, field_of_view: f64) -> Self {
+        self.field_of_view = FieldOfView::LongerSide(field_of_view);
+        self
+    }
+
+    pub fn horizontal_field_of_view(mut self, field_of_view: f64) -> Self {
+        self.field_of_view = FieldOfView::Horizontal(field_of_view);
+        self
+    }
+
+    pub fn vertical_field_of_view(mut self, field_of_view: f64) -> Self {
+        self.field_of_view = FieldOfView::Vertical(field_of_view);
+        self
+    }
+
+    // Field of view of a lens with `focal_length` on a sensor `sensor_width` wide, eg.- 50.0 and 36.0 (full frame)
+    pub fn focal_length(mut self, focal_length: f64, sensor_width: f64) -> Self {
+        self.field_of_view = FieldOfView::FocalLength {
+            focal_length,
+            sensor_width,
+        };
+        self
+    }
+
+    pub fn aperture(mut self, aperture: f64) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn focal_distance(mut self, focal_distance: f64) -> Self {
+        self.focal_distance = focal_distance;
+        self
+    }
+
+    pub fn aperture_blades(mut self, aperture_blades: u32) -> Self {
+        self.aperture_blades = aperture_blades;
+        self
+    }
+
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn motion(mut self, motion: Motion) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    pub fn build(self) -> Camera {
+        let aspect = self.hsize as f64 / self.vsize as f64;
+        // Half of the width and of the height of the canvas one unit in front of the camera
+        let (half_width, half_height) = match self.field_of_view {
+            FieldOfView::LongerSide(angle) => {
+                let half_view = (angle / 2.0).tan();
+                (half_view, half_view)
+            }
+            FieldOfView::Horizontal(angle) => {
+                let half_width = (angle / 2.0).tan();
+                (half_width, half_width / aspect)
+            }
+            FieldOfView::Vertical(angle) => {
+                let half_height = (angle / 2.0).tan();
+                (half_height * aspect, half_height)
+            }
+            FieldOfView::FocalLength {
+                focal_length,
+                sensor_width,
+            } => {
+                let half_width = sensor_width / (2.0 * focal_length);
+                (half_width, half_width / aspect)
+            }
+        };
+        let field_of_view = 2.0 * half_width.max(half_height).atan();
+
+        let mut camera = Camera::new(self.hsize, self.vsize, field_of_view);
+        camera.transform = self.transform;
+        camera.aperture = self.aperture;
+        camera.focal_distance = self.focal_distance;
+        camera.aperture_blades = self.aperture_blades;
+        camera.samples = self.samples;
+        camera.seed = self.seed;
+        camera.shutter_open = self.shutter_open;
+        camera.shutter_close = self.shutter_close;
+        camera.motion = self.motion;
+        camera.projection = self.projection;
+        camera
+    }
+}
+
+// Uniformly distributed point of the lens with a radius of 1, round or a regular polygon with `blades` corners
+fn sample_aperture(blades: u32, rng: &mut Rng) -> (f64, f64) {
+    if blades < 3 {
+        let r = rng.next_f64().sqrt();
+        let angle = 2.0 * PI * rng.next_f64();
+        return (r * angle.cos(), r * angle.sin());
+    }
+    // All the triangles between the center and a side of the polygon have the same area, pick one of them
+    let side = ((rng.next_f64() * blades as f64) as u32).min(blades - 1);
+    let a0 = 2.0 * PI * side as f64 / blades as f64;
+    let a1 = 2.0 * PI * (side + 1) as f64 / blades as f64;
+    // Uniform point in the triangle, folding the points of the other half of the parallelogram back in
+    let (mut s, mut t) = (rng.next_f64(), rng.next_f64());
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    (s * a0.cos() + t * a1.cos(), s * a0.sin() + t * a1.sin())
+}
+
 #[cfg(test)]
 mod camera_test {
     use super::*;
-    use crate::{vector, Color};
+    use crate::test_fixtures::camera_facing_origin;
+    use crate::{Compare, Light, Shape, Sphere};
     use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
 
     #[test]
     fn constructing_camera() {
-        let hsize: u16 = 160;
-        let vsize: u16 = 120;
+        let hsize: u32 = 160;
+        let vsize: u32 = 120;
         let field_of_view = FRAC_PI_2;
         let c = Camera::new(hsize, vsize, field_of_view);
 
@@ -121,6 +605,124 @@ mod camera_test {
         assert_eq!(r.direction, vector(FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2));
     }
 
+    #[test]
+    fn pixel_size_for_horizontal_canvas() {
+        let c = Camera::new(200, 125, FRAC_PI_2);
+        assert!(c.get_pixel_size().eq(0.01));
+    }
+
+    #[test]
+    fn pixel_size_for_vertical_canvas() {
+        let c = Camera::new(125, 200, FRAC_PI_2);
+        assert!(c.get_pixel_size().eq(0.01));
+    }
+
+    #[test]
+    fn changing_size_and_field_of_view_updates_pixel_size() {
+        let mut c = Camera::new(200, 125, FRAC_PI_2);
+        c.set_size(400, 250);
+        assert!(c.get_pixel_size().eq(0.005));
+        assert!(c.get_aspect().eq(1.6));
+        c.set_field_of_view(2.0 * 0.5_f64.atan());
+        assert!(c.get_pixel_size().eq(0.0025));
+        assert!(c.get_half_width().eq(0.5));
+        assert!(c.get_half_height().eq(0.3125));
+    }
+
+    #[test]
+    fn resolution_beyond_u16() {
+        let c = Camera::new(100_000, 2, FRAC_PI_2);
+        assert_eq!(c.get_hsize(), 100_000);
+        let r = c.ray_for_pixel(99_999, 0);
+        assert!(r.direction.x < 0.0);
+    }
+
+    #[test]
+    fn builder_looks_at_target() {
+        let from = point(1.0, 3.0, 2.0);
+        let to = point(4.0, -2.0, 8.0);
+        let up = vector(1.0, 1.0, 0.0);
+        let c = Camera::builder(160, 120)
+            .look_at(from, to, up)
+            .field_of_view(FRAC_PI_2)
+            .samples(4)
+            .seed(7)
+            .build();
+        assert_eq!(c.transform, Matrix::get_view_transform(from, to, up));
+        assert_eq!(c.get_field_of_view(), FRAC_PI_2);
+        assert_eq!((c.samples, c.seed), (4, 7));
+        let center = c.ray_for_position(80.0, 60.0);
+        assert_eq!(center.origin, from);
+        assert_eq!(center.direction, (to - from).normalize());
+    }
+
+    #[test]
+    fn builder_with_horizontal_or_vertical_field_of_view() {
+        let c = Camera::builder(200, 100)
+            .vertical_field_of_view(FRAC_PI_2)
+            .build();
+        assert!(c.get_vertical_field_of_view().eq(FRAC_PI_2));
+        assert!(c.get_half_width().eq(2.0));
+
+        let c = Camera::builder(100, 200)
+            .horizontal_field_of_view(FRAC_PI_2)
+            .build();
+        assert!(c.get_horizontal_field_of_view().eq(FRAC_PI_2));
+        assert!(c.get_half_height().eq(2.0));
+        assert!(c.get_field_of_view().eq(2.0 * 2.0_f64.atan()));
+    }
+
+    #[test]
+    fn builder_with_focal_length_and_sensor() {
+        // 50mm lens on a full frame sensor, 36mm wide
+        let c = Camera::builder(300, 200).focal_length(50.0, 36.0).build();
+        assert!(c
+            .get_horizontal_field_of_view()
+            .eq(2.0 * (18.0_f64 / 50.0).atan()));
+        assert!(c.get_half_height().eq(0.24));
+        // Longer lens, narrower view
+        let tele = Camera::builder(300, 200).focal_length(200.0, 36.0).build();
+        assert!(tele.get_field_of_view() < c.get_field_of_view());
+    }
+
+    #[test]
+    fn region_matches_full_render() {
+        let w = World::default();
+        let mut c = focused_camera(0.3, 5.0);
+        c.samples = 3;
+        let full = c.render(&w);
+        let region = Region::new(3, 4, 5, 2);
+        let part = c.render_region(&w, region);
+        assert_eq!((part.width, part.height), (5, 2));
+        assert_eq!(part.data, full.crop(region).data);
+
+        let tracer = PathTracer::new(2);
+        let full = c.render_path_traced(&w, &tracer);
+        let part = c.render_path_traced_region(&w, &tracer, region);
+        assert_eq!(part.data, full.crop(region).data);
+    }
+
+    #[test]
+    fn rendering_region_past_the_edge_of_image() {
+        let w = World::default();
+        let c = focused_camera(0.0, 5.0);
+        let part = c.render_path_traced_region(&w, &PathTracer::new(1), Region::new(8, 0, 4, 1));
+        assert_eq!((part.width, part.height), (3, 1));
+        let full = c.render_path_traced(&w, &PathTracer::new(1));
+        assert_eq!(part.data, full.crop(Region::new(8, 0, 3, 1)).data);
+    }
+
+    #[test]
+    fn merged_tiles_match_full_render() {
+        let w = World::default();
+        let c = focused_camera(0.0, 1.0);
+        let parts: Vec<(Region, Canvas)> = Region::tiles(11, 11, 4)
+            .into_iter()
+            .map(|region| (region, c.render_region(&w, region)))
+            .collect();
+        assert_eq!(Canvas::merge(11, 11, &parts).data, c.render(&w).data);
+    }
+
     #[test]
     fn render_a_world() {
         let w = World::default();
@@ -132,4 +734,224 @@ mod camera_test {
         let canvas = c.render(&w);
         assert_eq!(canvas.pixel_at(5, 5), Color::new(0.38066, 0.47583, 0.2855));
     }
+
+    #[test]
+    fn ray_for_position_at_pixel_center() {
+        let c = Camera::new(201, 101, FRAC_PI_2);
+        let a = c.ray_for_position(100.5, 50.5);
+        let b = c.ray_for_pixel(100, 50);
+        assert_eq!(a.origin, b.origin);
+        assert_eq!(a.direction, b.direction);
+    }
+
+    #[test]
+    fn path_traced_render_is_reproducible() {
+        let w = World::default();
+        let c = camera_facing_origin(5, 5).field_of_view(FRAC_PI_2).build();
+        let tracer = PathTracer::new(4);
+        let a = c.render_path_traced(&w, &tracer);
+        let b = c.render_path_traced(&w, &tracer);
+        let other_seed = PathTracer { seed: 1, ..tracer };
+        let d = c.render_path_traced(&w, &other_seed);
+        assert_eq!(a.pixel_at(2, 2), b.pixel_at(2, 2));
+        assert_ne!(a.pixel_at(2, 2), d.pixel_at(2, 2));
+        // Rays missing the spheres see the black background
+        assert_eq!(a.pixel_at(0, 0), Color::black());
+    }
+
+    fn focused_camera(aperture: f64, focal_distance: f64) -> Camera {
+        camera_facing_origin(11, 11)
+            .field_of_view(FRAC_PI_2)
+            .aperture(aperture)
+            .focal_distance(focal_distance)
+            .build()
+    }
+
+    #[test]
+    fn pinhole_camera_by_default() {
+        let c = Camera::new(201, 101, FRAC_PI_2);
+        assert_eq!(c.aperture, 0.0);
+        assert_eq!(c.samples, 1);
+        let mut rng = Rng::new(0);
+        let r = c.ray_through_lens(0.5, 0.5, &mut rng);
+        assert_eq!(r.origin, point(0.0, 0.0, 0.0));
+        assert_eq!(r.direction, vector(0.66519, 0.33259, -0.66851));
+    }
+
+    #[test]
+    fn lens_rays_meet_at_focal_plane() {
+        let c = focused_camera(0.5, 4.0);
+        let pinhole = c.ray_for_position(3.2, 7.9);
+        // Camera looks along +z from z = -5, the focal plane is z = -1
+        let focus = pinhole.position((4.0 - pinhole.origin.z - 5.0) / pinhole.direction.z);
+        let mut rng = Rng::new(4);
+        let mut spread = false;
+        for _ in 0..20 {
+            let r = c.ray_through_lens(3.2, 7.9, &mut rng);
+            assert!((r.origin - point(0.0, 0.0, -5.0)).magnitude() <= 0.5);
+            spread |= r.origin != pinhole.origin;
+            let p = r.position((focus.z - r.origin.z) / r.direction.z);
+            assert_eq!(p, focus);
+        }
+        assert!(spread);
+    }
+
+    #[test]
+    fn polygonal_aperture_samples_stay_inside_polygon() {
+        let mut rng = Rng::new(5);
+        let blades = 6;
+        let apothem = (PI / blades as f64).cos();
+        for _ in 0..1000 {
+            let (x, y) = sample_aperture(blades, &mut rng);
+            for side in 0..blades {
+                let angle = 2.0 * PI * (side as f64 + 0.5) / blades as f64;
+                assert!(x * angle.cos() + y * angle.sin() <= apothem + 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn round_aperture_samples_fill_disk() {
+        let mut rng = Rng::new(6);
+        let mut mean_r2 = 0.0;
+        for _ in 0..10000 {
+            let (x, y) = sample_aperture(0, &mut rng);
+            assert!(x * x + y * y <= 1.0);
+            mean_r2 += (x * x + y * y) / 10000.0;
+        }
+        // Uniform over the disk, half of the area is within r^2 = 0.5
+        assert!((mean_r2 - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn objects_out_of_focus_are_blurred() {
+        let w = World::default();
+        let sharp = focused_camera(0.0, 4.0);
+        let mut blurred = focused_camera(2.0, 10.0);
+        blurred.samples = 32;
+        let sharp_canvas = sharp.render(&w);
+        let blurred_canvas = blurred.render(&w);
+        // Pixel next to the sphere misses it when sharp, but gets some of its light when blurred
+        assert_eq!(sharp_canvas.pixel_at(3, 5), Color::black());
+        assert!(blurred_canvas.pixel_at(3, 5).red > 0.0);
+        assert_eq!(
+            blurred.render(&w).pixel_at(3, 5),
+            blurred_canvas.pixel_at(3, 5)
+        );
+    }
+
+    #[test]
+    fn rays_are_shot_while_shutter_is_open() {
+        let mut c = Camera::new(11, 11, FRAC_PI_2);
+        c.shutter_open = 0.25;
+        c.shutter_close = 0.75;
+        assert_eq!(c.ray_for_pixel(5, 5).time, 0.25);
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let time = c.ray_through_lens(5.5, 5.5, &mut rng).time;
+            assert!((0.25..0.75).contains(&time));
+        }
+    }
+
+    #[test]
+    fn moving_camera() {
+        let mut c = Camera::new(201, 101, FRAC_PI_2);
+        c.motion = Motion::new(Matrix::I(), Matrix::get_translation_matrix(0.0, -2.0, 0.0));
+        c.shutter_close = 1.0;
+        let mut rng = Rng::new(8);
+        let r = c.ray_through_lens(100.5, 50.5, &mut rng);
+        assert_eq!(r.origin, point(0.0, 2.0 * r.time, 0.0));
+    }
+
+    #[test]
+    fn moving_objects_are_blurred() {
+        let mut s = Sphere::default();
+        s.set_motion(
+            Motion::new(Matrix::I(), Matrix::get_translation_matrix(2.0, 0.0, 0.0)).unwrap(),
+        );
+        let w = World {
+            light: Light::new(point(-10.0, 10.0, -10.0), Color::white()),
+            objects: vec![Box::new(s)],
+            ..Default::default()
+        };
+        let mut c = focused_camera(0.0, 1.0);
+        let still = c.render(&w);
+        c.shutter_close = 1.0;
+        c.samples = 32;
+        let blurred = c.render(&w);
+        // The pixel on the right of the sphere is covered later, while it moves
+        assert_eq!(still.pixel_at(8, 5), Color::black());
+        assert!(blurred.pixel_at(8, 5).red > 0.0);
+        // The center is covered only part of the time, so it gets darker
+        assert!(blurred.pixel_at(5, 5).red < still.pixel_at(5, 5).red);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut c = Camera::new(200, 100, FRAC_PI_2);
+        c.projection = Projection::Orthographic { width: 10.0 };
+        let center = c.ray_for_position(100.0, 50.0);
+        assert_eq!(center.origin, point(0.0, 0.0, 0.0));
+        assert_eq!(center.direction, vector(0.0, 0.0, -1.0));
+        let corner = c.ray_for_position(0.0, 0.0);
+        assert_eq!(corner.origin, point(5.0, 2.5, 0.0));
+        assert_eq!(corner.direction, vector(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn equirectangular_rays_cover_sphere() {
+        let mut c = Camera::new(200, 100, FRAC_PI_2);
+        c.projection = Projection::Equirectangular;
+        assert_eq!(
+            c.ray_for_position(100.0, 50.0).direction,
+            vector(0.0, 0.0, -1.0)
+        );
+        // Right is -x in camera space
+        assert_eq!(
+            c.ray_for_position(150.0, 50.0).direction,
+            vector(-1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            c.ray_for_position(0.0, 50.0).direction,
+            vector(0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            c.ray_for_position(100.0, 0.0).direction,
+            vector(0.0, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn fisheye_angle_grows_with_distance_from_center() {
+        let mut c = Camera::new(100, 100, FRAC_PI_2);
+        c.projection = Projection::Fisheye { field_of_view: PI };
+        assert_eq!(
+            c.ray_for_position(50.0, 50.0).direction,
+            vector(0.0, 0.0, -1.0)
+        );
+        // Edge of the canvas is at half the field of view
+        assert_eq!(
+            c.ray_for_position(100.0, 50.0).direction,
+            vector(-1.0, 0.0, 0.0)
+        );
+        let d = c.ray_for_position(50.0, 25.0).direction;
+        assert_eq!(d, vector(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2));
+    }
+
+    #[test]
+    fn render_with_every_projection() {
+        // The ray through the center is the same in every projection, so the same color is seen
+        let w = World::default();
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic { width: 4.0 },
+            Projection::Equirectangular,
+            Projection::Fisheye { field_of_view: PI },
+        ] {
+            let mut c = focused_camera(0.0, 1.0);
+            c.projection = projection;
+            let canvas = c.render(&w);
+            assert_eq!(canvas.pixel_at(5, 5), Color::new(0.38066, 0.47583, 0.2855));
+        }
+    }
 }
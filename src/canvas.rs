@@ -1,6 +1,65 @@
+use crate::utils::{invalid_data, save_with};
 use crate::Color;
+use crate::ToneMap;
 use std::fs;
+use std::io;
+use std::io::Write;
 
+// Rectangle of pixels of an image, from the column `x` and the row `y` of its top left corner
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // The whole image
+    pub fn full(width: usize, height: usize) -> Self {
+        Self::new(0, 0, width, height)
+    }
+
+    pub fn contains(&self, col: usize, row: usize) -> bool {
+        col >= self.x && col - self.x < self.width && row >= self.y && row - self.y < self.height
+    }
+
+    // Part of the region inside an image of `width` x `height` pixels, which may be empty
+    pub fn clip(&self, width: usize, height: usize) -> Region {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Region::new(x, y, self.width.min(width - x), self.height.min(height - y))
+    }
+
+    // Splits an image into tiles of `tile_size` pixels, row by row, the last ones of each row and column
+    // are smaller when the image isn't a multiple of the tile size
+    pub fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Region> {
+        let tile_size = tile_size.max(1);
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(tile_size) {
+            for x in (0..width).step_by(tile_size) {
+                tiles.push(Region::new(
+                    x,
+                    y,
+                    tile_size.min(width - x),
+                    tile_size.min(height - y),
+                ));
+            }
+        }
+        tiles
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
@@ -30,56 +89,213 @@ impl Canvas {
         self[row][col]
     }
 
-    // Convert canvas to ppm format
+    // Copy of the pixels of the region, clipped to the canvas
+    pub fn crop(&self, region: Region) -> Canvas {
+        let region = region.clip(self.width, self.height);
+        let mut canvas = Canvas::new(region.width, region.height);
+        for row in 0..region.height {
+            for col in 0..region.width {
+                canvas.write_pixel(col, row, self.pixel_at(region.x + col, region.y + row));
+            }
+        }
+        canvas
+    }
+
+    // Copies `canvas` over this one with its top left corner at (`x`, `y`), the part outside is dropped
+    pub fn paste(&mut self, canvas: &Canvas, x: usize, y: usize) {
+        for row in 0..canvas.height.min(self.height.saturating_sub(y)) {
+            for col in 0..canvas.width.min(self.width.saturating_sub(x)) {
+                self.write_pixel(x + col, y + row, canvas.pixel_at(col, row));
+            }
+        }
+    }
+
+    // Puts the canvases of the regions rendered on their own back together into one image
+    // Pixels not covered by any region are left black
+    pub fn merge(width: usize, height: usize, parts: &[(Region, Canvas)]) -> Canvas {
+        let mut canvas = Canvas::new(width, height);
+        for (region, part) in parts {
+            canvas.paste(part, region.x, region.y);
+        }
+        canvas
+    }
+
+    // Convert canvas to ppm format, with color values clipped between 0.0 and 1.0
+    // Builds the whole file in memory, use `save_ppm` or `write_ppm_to` for big canvases
     pub fn get_ppm(&self) -> String {
-        let header = self.get_ppm_header();
-        let pixel_values = self.get_ppm_pixel_values();
-        header + &pixel_values
+        let mut ppm = Vec::new();
+        self.write_ppm_to(&mut ppm, ToneMap::default())
+            .expect("Writing to a vector doesn't fail");
+        String::from_utf8(ppm).expect("PPM is ASCII")
     }
 
     // Write the string ppm to the `file_path`
-    pub fn write_ppm(&self, ppm_string: &str, file_path: &str) {
-        fs::write(file_path, ppm_string).expect("Unable to write ppm");
+    pub fn write_ppm(&self, ppm_string: &str, file_path: &str) -> io::Result<()> {
+        fs::write(file_path, ppm_string)
+    }
+
+    // Streams the canvas as an ASCII (P3) ppm into the writer, a row at a time
+    // Colors go through `tone_map` before they are clipped, `ToneMap::default()` leaves them as they are
+    pub fn write_ppm_to<W: Write>(&self, writer: &mut W, tone_map: ToneMap) -> io::Result<()> {
+        tone_map.check()?;
+        writer.write_all(self.get_ppm_header("P3").as_bytes())?;
+        for row in 0..self.height {
+            writer.write_all(self.get_ppm_row(row, tone_map).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Streams the canvas as a binary (P6) ppm into the writer, a byte per color value
+    // About a quarter of the size of the ASCII flavour, and much faster to write and read
+    pub fn write_p6_to<W: Write>(&self, writer: &mut W, tone_map: ToneMap) -> io::Result<()> {
+        tone_map.check()?;
+        writer.write_all(self.get_ppm_header("P6").as_bytes())?;
+        let mut bytes = Vec::with_capacity(self.width * 3);
+        for row in 0..self.height {
+            bytes.clear();
+            for color in &self[row] {
+                let color = tone_map.apply_color(*color);
+                for value in [color.red, color.green, color.blue] {
+                    bytes.push(self.scale_and_clip_color(value));
+                }
+            }
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    // Writes the canvas to the `file_path` as an ASCII (P3) ppm, without building it in memory
+    // A `tone_map` which doesn't `check` is an error before the file is created
+    pub fn save_ppm(&self, file_path: &str, tone_map: ToneMap) -> io::Result<()> {
+        tone_map.check()?;
+        save_with(file_path, |writer| self.write_ppm_to(writer, tone_map))
+    }
+
+    // Writes the canvas to the `file_path` as a binary (P6) ppm
+    pub fn save_p6(&self, file_path: &str, tone_map: ToneMap) -> io::Result<()> {
+        tone_map.check()?;
+        save_with(file_path, |writer| self.write_p6_to(writer, tone_map))
+    }
+
+    // Read an image file from the `file_path`, in any of the formats `from_image` knows
+    pub fn read_image(file_path: &str) -> io::Result<Canvas> {
+        Canvas::from_image(&fs::read(file_path)?)
+    }
+
+    // Convert ppm, png or Radiance hdr data to canvas, the format is found from the first bytes
+    pub fn from_image(data: &[u8]) -> io::Result<Canvas> {
+        if data.starts_with(b"P3") || data.starts_with(b"P6") {
+            Canvas::from_ppm(data)
+        } else if data.starts_with(&[137, b'P', b'N', b'G']) {
+            Canvas::from_png(data)
+        } else if data.starts_with(b"#?") {
+            Canvas::from_hdr(data)
+        } else {
+            Err(invalid_data("unknown image format"))
+        }
+    }
+
+    // Read a PPM file from the `file_path` into a canvas
+    pub fn read_ppm(file_path: &str) -> io::Result<Canvas> {
+        Canvas::from_ppm(&fs::read(file_path)?)
+    }
+
+    // Convert ppm data to canvas, inverse of `get_ppm`
+    // Supports both ASCII (P3) and binary (P6) flavours with comments in the header
+    // Color values are scaled from 0..=MAXIMUM_COLOR_VALUE to 0.0..=1.0
+    pub fn from_ppm(data: &[u8]) -> io::Result<Canvas> {
+        let mut parser = PpmParser { data, pos: 0 };
+        let flavour = parser.next_token()?;
+        if flavour != "P3" && flavour != "P6" {
+            return Err(invalid_data("unsupported PPM flavour"));
+        }
+        let width = parser.next_number()?;
+        let height = parser.next_number()?;
+        let max_value = parser.next_number()?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data(
+                "maximum color value should be between 1 and 65535",
+            ));
+        }
+
+        if flavour == "P6" {
+            // Exactly one whitespace separates the header from the binary pixel data
+            parser.pos += 1;
+        }
+        // Sizes are checked before anything of the size of the image is allocated: binary samples
+        // take 1 or 2 bytes, and ASCII ones at least a digit each
+        let bytes_per_sample = if flavour == "P6" && max_value > 255 {
+            2
+        } else {
+            1
+        };
+        let sample_bytes = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3 * bytes_per_sample))
+            .ok_or_else(|| invalid_data("image is too large"))?;
+        if sample_bytes > data.len().saturating_sub(parser.pos) {
+            return Err(invalid_data(
+                "pixel data is too short for the size of the image",
+            ));
+        }
+        let mut canvas = Canvas::new(width, height);
+        for row in 0..height {
+            for col in 0..width {
+                let mut rgb = [0.0; 3];
+                for value in &mut rgb {
+                    let sample = if flavour == "P3" {
+                        parser.next_number()?
+                    } else {
+                        parser.next_binary_sample(max_value > 255)?
+                    };
+                    if sample > max_value {
+                        return Err(invalid_data("color value above the maximum color value"));
+                    }
+                    *value = sample as f64 / max_value as f64;
+                }
+                canvas.write_pixel(col, row, Color::new(rgb[0], rgb[1], rgb[2]));
+            }
+        }
+        Ok(canvas)
     }
 
     // PPM format-
     // PPM FLAVOUR (eg.- P3)
     // IMAGE_WIDTH IMAGE_HEIGHT (both are in pixels)
     // MAXIMUM_COLOR_VALUE (eg.- 255)
-    fn get_ppm_header(&self) -> String {
+    fn get_ppm_header(&self, flavour: &str) -> String {
         let mut header: String = String::from("");
-        header += "P3\n";
+        header += flavour;
+        header += "\n";
         header += &(self.width.to_string() + " " + &self.height.to_string() + "\n");
         header += "255\n";
         header
     }
 
-    fn get_ppm_pixel_values(&self) -> String {
+    // Color values of a row of the canvas, split into lines of at most 70 characters
+    fn get_ppm_row(&self, row: usize, tone_map: ToneMap) -> String {
         let mut pixels: String = String::from("");
-        for row in 0..self.height {
-            let mut pixels_row: String = String::from("");
+        let mut pixels_row: String = String::from("");
 
-            for col in 0..self.width {
-                let _color = self.pixel_at(col, row);
+        for col in 0..self.width {
+            let _color = tone_map.apply_color(self.pixel_at(col, row));
 
-                for pixel_value in [_color.red, _color.green, _color.blue] {
-                    let pixel_value_string = self.scale_and_clip_color(pixel_value).to_string();
+            for pixel_value in [_color.red, _color.green, _color.blue] {
+                let pixel_value_string = self.scale_and_clip_color(pixel_value).to_string();
 
-                    // Ensure that each row is at max 70 characters long
-                    if pixels_row.len() + pixel_value_string.len() > 69 {
-                        pixels_row.pop();
-                        pixels_row += "\n";
-                        pixels += &pixels_row;
-                        pixels_row.clear();
-                    }
-                    pixels_row += &(pixel_value_string + " ");
+                // Ensure that each row is at max 70 characters long
+                if pixels_row.len() + pixel_value_string.len() > 69 {
+                    pixels_row.pop();
+                    pixels_row += "\n";
+                    pixels += &pixels_row;
+                    pixels_row.clear();
                 }
+                pixels_row += &(pixel_value_string + " ");
             }
-            pixels += &pixels_row;
-            // pixels = pixels.trim_end().to_string();
-            pixels.pop(); // above might be slow as it creates a new string
-            pixels += "\n";
         }
+        pixels += &pixels_row;
+        pixels.pop();
+        pixels += "\n";
         pixels
     }
 
@@ -89,6 +305,58 @@ impl Canvas {
     }
 }
 
+// Reads the PPM data token by token
+struct PpmParser<'a> {
+    data: &'a [u8],
+    pos: usize, // Index of the next unread byte
+}
+
+impl PpmParser<'_> {
+    // Whitespace separated token, comments (from `#` till the end of the line) are skipped
+    fn next_token(&mut self) -> io::Result<String> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(b'#') => {
+                    while self.data.get(self.pos).is_some_and(|&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(_) => break,
+                None => return Err(invalid_data("unexpected end of PPM data")),
+            }
+        }
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        Ok(String::from_utf8_lossy(&self.data[start..self.pos]).into_owned())
+    }
+
+    fn next_number(&mut self) -> io::Result<usize> {
+        self.next_token()?
+            .parse()
+            .map_err(|_| invalid_data("expected a number in PPM data"))
+    }
+
+    // Binary samples are 1 byte, or 2 bytes in big endian order when the maximum value is above 255
+    fn next_binary_sample(&mut self, wide: bool) -> io::Result<usize> {
+        let size = if wide { 2 } else { 1 };
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + size)
+            .ok_or_else(|| invalid_data("unexpected end of PPM data"))?;
+        self.pos += size;
+        Ok(bytes
+            .iter()
+            .fold(0, |sample, &b| (sample << 8) | b as usize))
+    }
+}
+
 // For indexing a row in a Canvas
 impl std::ops::Index<usize> for Canvas {
     type Output = [Color];
@@ -108,6 +376,77 @@ impl std::ops::IndexMut<usize> for Canvas {
 #[cfg(test)]
 mod canvas_tests {
     use super::*;
+    use crate::test_fixtures::numbered_canvas;
+    use crate::{Encoding, PngOptions, ToneMapping};
+
+    #[test]
+    fn splitting_into_tiles() {
+        let tiles = Region::tiles(5, 3, 2);
+        assert_eq!(
+            tiles,
+            vec![
+                Region::new(0, 0, 2, 2),
+                Region::new(2, 0, 2, 2),
+                Region::new(4, 0, 1, 2),
+                Region::new(0, 2, 2, 1),
+                Region::new(2, 2, 2, 1),
+                Region::new(4, 2, 1, 1),
+            ]
+        );
+        assert!(tiles[2].contains(4, 1));
+        assert!(!tiles[2].contains(3, 1));
+    }
+
+    #[test]
+    fn cropping_a_canvas() {
+        let c = numbered_canvas(5, 4);
+        let crop = c.crop(Region::new(1, 2, 3, 2));
+        assert_eq!((crop.width, crop.height), (3, 2));
+        assert_eq!(crop.pixel_at(0, 0), Color::new(1.0, 2.0, 0.0));
+        assert_eq!(crop.pixel_at(2, 1), Color::new(3.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn cropping_past_the_edges_of_canvas() {
+        let c = numbered_canvas(5, 4);
+        let crop = c.crop(Region::new(3, 2, 4, 4));
+        assert_eq!((crop.width, crop.height), (2, 2));
+        assert_eq!(crop.pixel_at(1, 1), Color::new(4.0, 3.0, 0.0));
+        let outside = c.crop(Region::new(9, 0, 2, 2));
+        assert_eq!((outside.width, outside.height), (0, 2));
+    }
+
+    #[test]
+    fn clipping_regions_to_an_image() {
+        assert_eq!(Region::new(1, 2, 4, 2).clip(5, 4), Region::new(1, 2, 4, 2));
+        assert_eq!(Region::new(3, 1, 4, 9).clip(5, 4), Region::new(3, 1, 2, 3));
+        assert_eq!(
+            Region::new(usize::MAX, 0, usize::MAX, 1).clip(5, 4),
+            Region::new(5, 0, 0, 1)
+        );
+        assert!(!Region::new(usize::MAX, 0, 2, 1).contains(0, 0));
+    }
+
+    #[test]
+    fn merging_tiles_gives_back_the_canvas() {
+        let c = numbered_canvas(7, 5);
+        let parts: Vec<(Region, Canvas)> = Region::tiles(7, 5, 3)
+            .into_iter()
+            .map(|region| (region, c.crop(region)))
+            .collect();
+        let merged = Canvas::merge(7, 5, &parts);
+        assert_eq!(merged.data, c.data);
+    }
+
+    #[test]
+    fn pasting_drops_the_part_outside() {
+        let mut c = Canvas::new(3, 3);
+        let mut part = Canvas::new(2, 2);
+        part.fill(Color::white());
+        c.paste(&part, 2, 2);
+        assert_eq!(c.pixel_at(2, 2), Color::white());
+        assert_eq!(c.pixel_at(1, 1), Color::black());
+    }
 
     #[test]
     fn canvas_init_with_black() {
@@ -191,4 +530,235 @@ mod canvas_tests {
         let ppm = c.get_ppm();
         assert_eq!(ppm.chars().last().unwrap(), '\n');
     }
+
+    #[test]
+    fn reading_any_image_format() {
+        let mut c = Canvas::new(3, 2);
+        c.write_pixel(1, 1, Color::new(1.0, 0.0, 0.5));
+        let mut hdr = Vec::new();
+        c.write_hdr_to(&mut hdr).unwrap();
+        let images = [
+            c.get_ppm().into_bytes(),
+            c.get_png(PngOptions::default()),
+            hdr,
+        ];
+        for image in images {
+            let read = Canvas::from_image(&image).unwrap();
+            assert_eq!((read.width, read.height), (3, 2));
+            // Radiance files round the color up a little
+            assert!((read.pixel_at(1, 1).red - 1.0).abs() < 0.01);
+            assert_eq!(read.pixel_at(0, 0), Color::black());
+        }
+        assert!(Canvas::from_image(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn reading_ppm_with_wrong_flavour() {
+        let ppm = "P32\n1 1\n255\n0 0 0\n";
+        assert!(Canvas::from_ppm(ppm.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reading_ppm_returns_canvas_of_right_size() {
+        let ppm = "P3\n10 2\n255\n".to_string() + &"0 0 0 ".repeat(20);
+        let canvas = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!(canvas.width, 10);
+        assert_eq!(canvas.height, 2);
+    }
+
+    #[test]
+    fn reading_ppm_pixel_data() {
+        let ppm = "P3\n4 3\n255\n\
+                   255 127 0  0 127 255  127 255 0  255 255 255\n\
+                   0 0 0  255 0 0  0 255 0  0 0 255\n\
+                   255 255 0  0 255 255  255 0 255  127 127 127\n";
+        let canvas = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!(canvas.pixel_at(0, 0), Color::new(1.0, 0.49804, 0.0));
+        assert_eq!(canvas.pixel_at(1, 0), Color::new(0.0, 0.49804, 1.0));
+        assert_eq!(canvas.pixel_at(3, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(canvas.pixel_at(1, 1), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(canvas.pixel_at(2, 2), Color::new(1.0, 0.0, 1.0));
+        assert_eq!(canvas.pixel_at(3, 2), Color::new(0.49804, 0.49804, 0.49804));
+    }
+
+    #[test]
+    fn reading_ppm_ignores_comments() {
+        let ppm = "P3\n# this is a comment\n2 1\n# this, too\n255\n# another comment\n\
+                   255 255 255\n# oh, no, comments in the pixel data!\n255 0 255\n";
+        let canvas = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!(canvas.pixel_at(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(canvas.pixel_at(1, 0), Color::new(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn reading_ppm_allows_rgb_to_span_lines() {
+        let ppm = "P3\n1 1\n255\n51\n153\n\n204\n";
+        let canvas = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!(canvas.pixel_at(0, 0), Color::new(0.2, 0.6, 0.8));
+    }
+
+    #[test]
+    fn reading_ppm_respects_maximum_color_value() {
+        let ppm = "P3\n2 2\n100\n100 100 100  50 50 50\n75 50 25  0 0 0\n";
+        let canvas = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!(canvas.pixel_at(0, 1), Color::new(0.75, 0.5, 0.25));
+    }
+
+    #[test]
+    fn reading_ppm_with_missing_pixel_data() {
+        let ppm = "P3\n2 1\n255\n255 255 255\n";
+        assert!(Canvas::from_ppm(ppm.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reading_ppm_of_huge_size() {
+        // Rejected before anything of their size is allocated
+        for ppm in [
+            "P3\n100000 100000\n255\n0 0 0\n",
+            "P6\n100000 100000\n255\n\0\0\0",
+            "P6\n18446744073709551615 2\n255\n\0\0\0",
+        ] {
+            assert!(Canvas::from_ppm(ppm.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn reading_ppm_with_sample_above_maximum_color_value() {
+        let ppm = "P3\n1 1\n100\n100 101 0\n";
+        assert!(Canvas::from_ppm(ppm.as_bytes()).is_err());
+        let mut ppm = b"P6\n1 1\n200\n".to_vec();
+        ppm.extend([0, 201, 0]);
+        assert!(Canvas::from_ppm(&ppm).is_err());
+    }
+
+    #[test]
+    fn reading_binary_ppm() {
+        let mut ppm = b"P6\n# binary\n2 1\n255\n".to_vec();
+        ppm.extend([255, 0, 51, 0, 255, 204]);
+        let canvas = Canvas::from_ppm(&ppm).unwrap();
+        assert_eq!(canvas.pixel_at(0, 0), Color::new(1.0, 0.0, 0.2));
+        assert_eq!(canvas.pixel_at(1, 0), Color::new(0.0, 1.0, 0.8));
+    }
+
+    #[test]
+    fn reading_binary_ppm_with_two_byte_samples() {
+        let mut ppm = b"P6 1 1 65535\n".to_vec();
+        ppm.extend([255, 255, 128, 0, 0, 0]);
+        let canvas = Canvas::from_ppm(&ppm).unwrap();
+        assert_eq!(canvas.pixel_at(0, 0), Color::new(1.0, 0.50001, 0.0));
+    }
+
+    #[test]
+    fn writing_and_reading_ppm_back() {
+        let mut c = Canvas::new(3, 2);
+        c.write_pixel(1, 0, Color::new(1.0, 0.2, 0.6));
+        c.write_pixel(2, 1, Color::new(0.0, 0.8, 0.4));
+        let canvas = Canvas::from_ppm(c.get_ppm().as_bytes()).unwrap();
+        assert_eq!(canvas.pixel_at(1, 0), Color::new(1.0, 0.2, 0.6));
+        assert_eq!(canvas.pixel_at(2, 1), Color::new(0.0, 0.8, 0.4));
+    }
+
+    #[test]
+    fn streaming_ppm_matches_ppm_string() {
+        let mut c = Canvas::new(10, 2);
+        c.fill(Color::new(1.0, 0.8, 0.6));
+        let mut ppm = Vec::new();
+        c.write_ppm_to(&mut ppm, ToneMap::default()).unwrap();
+        assert_eq!(ppm, c.get_ppm().into_bytes());
+    }
+
+    #[test]
+    fn tone_map_is_applied_to_ppm() {
+        let mut c = Canvas::new(1, 1);
+        c.fill(Color::new(3.0, 1.0, 0.0));
+        let tone_map = ToneMap::new(-1.0, ToneMapping::Clip, Encoding::Linear).unwrap();
+        let mut p3 = Vec::new();
+        c.write_ppm_to(&mut p3, tone_map).unwrap();
+        assert_eq!(p3, b"P3\n1 1\n255\n255 128 0\n");
+        let mut p6 = Vec::new();
+        c.write_p6_to(&mut p6, tone_map).unwrap();
+        assert_eq!(p6, b"P6\n1 1\n255\n\xff\x80\x00");
+    }
+
+    #[test]
+    fn unchecked_tone_map_is_an_error() {
+        let tone_map = ToneMap {
+            encoding: Encoding::Gamma(-2.2),
+            ..Default::default()
+        };
+        let c = Canvas::new(1, 1);
+        let error = c.write_ppm_to(&mut Vec::new(), tone_map).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(c.write_p6_to(&mut Vec::new(), tone_map).is_err());
+
+        let path =
+            std::env::temp_dir().join(format!("ray_tracer_tone_map_{}.ppm", std::process::id()));
+        assert!(c.save_p6(path.to_str().unwrap(), tone_map).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn writing_binary_ppm() {
+        let mut c = Canvas::new(2, 1);
+        c.write_pixel(0, 0, Color::new(1.5, 0.0, -0.5));
+        c.write_pixel(1, 0, Color::new(0.2, 0.6, 1.0));
+        let mut ppm = Vec::new();
+        c.write_p6_to(&mut ppm, ToneMap::default()).unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\xff\x00\x00\x33\x99\xff");
+        let canvas = Canvas::from_ppm(&ppm).unwrap();
+        assert_eq!(canvas.pixel_at(1, 0), Color::new(0.2, 0.6, 1.0));
+    }
+
+    // Writer which fails after taking `limit` bytes, like a full disk
+    struct FailingWriter {
+        limit: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.limit {
+                return Err(io::Error::other("disk full"));
+            }
+            self.limit -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writing_errors_are_returned() {
+        let c = Canvas::new(20, 20);
+        assert!(c
+            .write_ppm_to(&mut FailingWriter { limit: 50 }, ToneMap::default())
+            .is_err());
+        assert!(c
+            .write_p6_to(&mut FailingWriter { limit: 50 }, ToneMap::default())
+            .is_err());
+        assert!(c
+            .save_p6("/nonexistent directory/image.ppm", ToneMap::default())
+            .is_err());
+        assert!(c
+            .write_ppm("P3", "/nonexistent directory/image.ppm")
+            .is_err());
+    }
+
+    #[test]
+    fn saving_and_reading_files() {
+        let mut c = Canvas::new(4, 3);
+        c.write_pixel(3, 2, Color::new(0.2, 0.4, 0.6));
+        let dir = std::env::temp_dir();
+        let p3 = dir.join(format!("ray_tracer_save_{}.ppm", std::process::id()));
+        let p6 = dir.join(format!("ray_tracer_save_p6_{}.ppm", std::process::id()));
+        c.save_ppm(p3.to_str().unwrap(), ToneMap::default())
+            .unwrap();
+        c.save_p6(p6.to_str().unwrap(), ToneMap::default()).unwrap();
+        for path in [p3, p6] {
+            let canvas = Canvas::read_ppm(path.to_str().unwrap()).unwrap();
+            assert_eq!(canvas.pixel_at(3, 2), Color::new(0.2, 0.4, 0.6));
+            fs::remove_file(path).unwrap();
+        }
+    }
 }
@@ -35,6 +35,11 @@ impl Color {
     pub fn blue() -> Color {
         Color::new(0.0, 0.0, 1.0)
     }
+
+    // Perceived brightness of the color (Rec. 709 weights)
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
 }
 
 // For color + color
@@ -142,6 +147,13 @@ mod color_tests {
         assert_eq!(c * 2.0, _c);
     }
 
+    #[test]
+    fn luminance_of_colors() {
+        assert!(Color::white().luminance().eq(1.0));
+        assert!(Color::black().luminance().eq(0.0));
+        assert!(Color::green().luminance().eq(0.7152));
+    }
+
     #[test]
     fn color_color_multiply() {
         let c1 = Color::new(1.0, 0.2, 0.4);
@@ -0,0 +1,188 @@
+use crate::random::Rng;
+use crate::vector;
+use crate::Background;
+use crate::Color;
+use crate::Tuple;
+use std::f64::consts::PI;
+
+// Resolution of the latitude-longitude grid used to find the bright parts of the environment
+const GRID_WIDTH: usize = 128;
+const GRID_HEIGHT: usize = 64;
+
+// Lights the scene with the environment around it, eg.- an HDRI of a studio or the sky
+// Light arrives from every direction with the radiance `environment.color_at(direction)`
+// The lighting is estimated by sampling `samples` directions at every hit, bright parts of the
+// environment are sampled more often (importance sampling by luminance) to reduce the noise
+#[derive(Clone, Debug)]
+pub struct EnvironmentLight {
+    pub environment: Background,
+    pub samples: usize, // Directions sampled per hit
+    row_cdf: Vec<f64>,  // Cumulative distribution of picking each row (latitude) of the grid
+    col_cdf: Vec<f64>,  // Cumulative distribution of picking each column within a row, row major
+}
+
+// A direction sampled from the environment light
+pub struct EnvironmentSample {
+    pub direction: Tuple, // Unit vector pointing towards the environment
+    pub radiance: Color,  // Light arriving from the direction
+    pub pdf: f64,         // Probability density of picking the direction, per steradian
+}
+
+impl EnvironmentLight {
+    // Builds the sampling distribution by looking at the environment in every direction of the grid
+    pub fn new(environment: Background, samples: usize) -> Self {
+        let mut row_cdf = Vec::with_capacity(GRID_HEIGHT);
+        let mut col_cdf = Vec::with_capacity(GRID_WIDTH * GRID_HEIGHT);
+        let mut total = 0.0;
+        for row in 0..GRID_HEIGHT {
+            let phi = (row as f64 + 0.5) / GRID_HEIGHT as f64 * PI;
+            // Rows near the poles cover a smaller solid angle
+            let cell_solid_angle = cell_solid_angle(row);
+            let mut row_total = 0.0;
+            for col in 0..GRID_WIDTH {
+                let u = (col as f64 + 0.5) / GRID_WIDTH as f64;
+                let radiance = environment.color_at(direction_from_uv(u, phi));
+                row_total += radiance.luminance().max(0.0) * cell_solid_angle;
+                col_cdf.push(row_total);
+            }
+            // Normalize the row so that the last entry is 1
+            if row_total > 0.0 {
+                for value in &mut col_cdf[row * GRID_WIDTH..] {
+                    *value /= row_total;
+                }
+            }
+            total += row_total;
+            row_cdf.push(total);
+        }
+        if total > 0.0 {
+            for value in &mut row_cdf {
+                *value /= total;
+            }
+        }
+
+        Self {
+            environment,
+            samples,
+            row_cdf,
+            col_cdf,
+        }
+    }
+
+    // Picks a direction, returns `None` when the environment gives no light at all
+    pub fn sample(&self, rng: &mut Rng) -> Option<EnvironmentSample> {
+        if self.row_cdf.last().is_none_or(|&total| total <= 0.0) {
+            return None;
+        }
+        let (row, row_pdf, row_offset) = sample_cdf(&self.row_cdf, rng.next_f64());
+        let cols = &self.col_cdf[row * GRID_WIDTH..(row + 1) * GRID_WIDTH];
+        let (col, col_pdf, col_offset) = sample_cdf(cols, rng.next_f64());
+
+        // Pick uniformly over the solid angle of the cell, by being uniform in cos(phi)
+        let u = (col as f64 + col_offset) / GRID_WIDTH as f64;
+        let (cos_top, cos_bottom) = row_cos_phi(row);
+        let phi = (cos_top - row_offset * (cos_top - cos_bottom)).acos();
+        let pdf = row_pdf * col_pdf / cell_solid_angle(row);
+        let direction = direction_from_uv(u, phi);
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.environment.color_at(direction),
+            pdf,
+        })
+    }
+}
+
+// cos(phi) at the top and the bottom edge of the `row` of the grid
+fn row_cos_phi(row: usize) -> (f64, f64) {
+    let top = row as f64 / GRID_HEIGHT as f64 * PI;
+    let bottom = (row + 1) as f64 / GRID_HEIGHT as f64 * PI;
+    (top.cos(), bottom.cos())
+}
+
+// Solid angle covered by one cell of the `row` of the grid
+fn cell_solid_angle(row: usize) -> f64 {
+    let (cos_top, cos_bottom) = row_cos_phi(row);
+    2.0 * PI / GRID_WIDTH as f64 * (cos_top - cos_bottom)
+}
+
+// Inverse of `spherical_map`, `phi` is the angle from the +y axis
+fn direction_from_uv(u: f64, phi: f64) -> Tuple {
+    let theta = 2.0 * PI * (0.5 - u);
+    vector(phi.sin() * theta.sin(), phi.cos(), phi.sin() * theta.cos())
+}
+
+// Picks an index of the normalized cumulative distribution with the random value `x`
+// Returns the index, its probability and where `x` falls inside it (0 to 1)
+fn sample_cdf(cdf: &[f64], x: f64) -> (usize, f64, f64) {
+    let index = cdf.partition_point(|&value| value <= x).min(cdf.len() - 1);
+    let start = if index == 0 { 0.0 } else { cdf[index - 1] };
+    let probability = cdf[index] - start;
+    let offset = if probability > 0.0 {
+        (x - start) / probability
+    } else {
+        0.5
+    };
+    (index, probability, offset.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod environment_light_tests {
+    use super::*;
+    use crate::{spherical_map, Canvas, Compare, TextureFilter, TextureMap, UVImage, UVMapping};
+    use std::rc::Rc;
+
+    #[test]
+    fn direction_from_uv_is_inverse_of_spherical_map() {
+        let d = direction_from_uv(0.3, 0.4 * PI);
+        let (u, v) = spherical_map(d);
+        assert!(u.eq(0.3));
+        assert!(v.eq(0.6));
+        assert!(d.magnitude().eq(1.0));
+    }
+
+    #[test]
+    fn sampling_uniform_environment() {
+        let light = EnvironmentLight::new(Background::Solid(Color::white()), 1);
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let sample = light.sample(&mut rng).unwrap();
+            assert_eq!(sample.radiance, Color::white());
+            // Every direction is equally likely
+            assert!(sample.pdf.eq(1.0 / (4.0 * PI)));
+        }
+    }
+
+    #[test]
+    fn sampling_picks_bright_parts_of_environment() {
+        // Only the top quarter of the sky is bright
+        let mut canvas = Canvas::new(4, 4);
+        for col in 0..4 {
+            canvas.write_pixel(col, 0, Color::new(2.0, 2.0, 2.0));
+        }
+        let pattern = TextureMap::new(
+            Box::new(UVImage::new(canvas, TextureFilter::Nearest)),
+            UVMapping::Spherical,
+        );
+        let light = EnvironmentLight::new(Background::Environment(Rc::new(pattern)), 1);
+        let mut rng = Rng::new(2);
+        for _ in 0..100 {
+            let sample = light.sample(&mut rng).unwrap();
+            assert!(sample.direction.y > 0.0);
+        }
+    }
+
+    #[test]
+    fn black_environment_gives_no_samples() {
+        let light = EnvironmentLight::new(Background::default(), 1);
+        assert!(light.sample(&mut Rng::new(3)).is_none());
+    }
+
+    #[test]
+    fn sampling_cumulative_distribution() {
+        let cdf = [0.25, 0.25, 1.0];
+        assert_eq!(sample_cdf(&cdf, 0.0), (0, 0.25, 0.0));
+        assert_eq!(sample_cdf(&cdf, 0.125), (0, 0.25, 0.5));
+        // Index 1 has zero probability so it is never picked
+        assert_eq!(sample_cdf(&cdf, 0.25), (2, 0.75, 0.0));
+        assert_eq!(sample_cdf(&cdf, 0.625), (2, 0.75, 0.5));
+    }
+}
@@ -0,0 +1,158 @@
+// Regression tests comparing renders to reference images stored in renderings/golden
+// After a change that is meant to change the images, update them with
+//     UPDATE_GOLDEN=1 cargo test golden
+// and look at the new images before committing them
+use crate::{BitDepth, Camera, Canvas, Color, PngOptions, World};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("renderings/golden")
+}
+
+// Where the render and the diff of a failed comparison are written, to look at them
+fn failures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn save(canvas: &Canvas, path: &Path) {
+    let options = PngOptions {
+        bit_depth: BitDepth::Sixteen,
+        ..Default::default()
+    };
+    canvas
+        .save_png(path.to_str().unwrap(), options)
+        .expect("Unable to write png");
+}
+
+// Fails when a channel of a pixel of the canvas is further than `tolerance` from the reference image `name`
+// References are 16 bit pngs, so, the canvas is clipped to 0.0..=1.0 before comparing
+pub(crate) fn assert_matches_golden(name: &str, canvas: &Canvas, tolerance: f64) {
+    let mut clipped = canvas.clone();
+    for color in clipped.data.iter_mut() {
+        *color = Color::new(
+            color.red.clamp(0.0, 1.0),
+            color.green.clamp(0.0, 1.0),
+            color.blue.clamp(0.0, 1.0),
+        );
+    }
+    let path = golden_dir().join(format!("{name}.png"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        save(&clipped, &path);
+        return;
+    }
+    let reference = Canvas::read_png(path.to_str().unwrap()).unwrap_or_else(|error| {
+        panic!(
+            "Unable to read the reference image {}: {error}, create it with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    let Some(diff) = clipped.compare(&reference) else {
+        panic!(
+            "Size of {name} changed from {}x{} to {}x{}",
+            reference.width, reference.height, clipped.width, clipped.height
+        );
+    };
+    if diff.max_error > tolerance {
+        fs::create_dir_all(failures_dir()).unwrap();
+        let actual = failures_dir().join(format!("{name}.actual.png"));
+        let heatmap = failures_dir().join(format!("{name}.diff.png"));
+        save(&clipped, &actual);
+        let diff_heatmap = clipped.diff_heatmap(&reference, diff.max_error).unwrap();
+        save(&diff_heatmap, &heatmap);
+        panic!(
+            "{name} doesn't match its reference, max error {:.5} at {:?}, rmse {:.5}, psnr {:.2} dB\n\
+             See {} and {}",
+            diff.max_error,
+            diff.max_error_at,
+            diff.rmse,
+            diff.psnr,
+            actual.display(),
+            heatmap.display()
+        );
+    }
+}
+
+// Renders the `world` with the `camera` and compares it to the reference image `name`
+pub(crate) fn assert_render_matches_golden(
+    name: &str,
+    world: &World,
+    camera: &Camera,
+    tolerance: f64,
+) {
+    assert_matches_golden(name, &camera.render(world), tolerance);
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+    use crate::{point, vector, Light, Matrix, Shape, Sphere};
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_3};
+
+    // Quantization of 16 bit pngs is far below it
+    const TOLERANCE: f64 = 1e-3;
+
+    fn camera(hsize: u32, vsize: u32, from: crate::Tuple) -> Camera {
+        Camera::builder(hsize, vsize)
+            .field_of_view(FRAC_PI_3)
+            .look_at(from, point(0.0, 1.0, 0.0), vector(0.0, 1.0, 0.0))
+            .build()
+    }
+
+    // Room like the one at the end of chapter 8, with a mirror and a glass sphere, so the reference
+    // also covers the reflections and refractions of `World`
+    fn room() -> World {
+        let mut floor = Sphere::default();
+        floor.set_transform(Matrix::get_scaling_matrix(10.0, 0.01, 10.0));
+        floor.material.color = Color::new(1.0, 0.9, 0.9);
+        floor.material.specular = 0.0;
+        floor.material.reflective = 0.2;
+
+        let mut wall = Sphere::default();
+        wall.set_transform(
+            Matrix::get_translation_matrix(0.0, 0.0, 5.0)
+                * Matrix::get_rotation_x_matrix(FRAC_PI_2)
+                * Matrix::get_scaling_matrix(10.0, 0.01, 10.0),
+        );
+        wall.material.color = Color::new(0.6, 0.7, 0.9);
+
+        let mut mirror = Sphere::default();
+        mirror.set_transform(Matrix::get_translation_matrix(-0.5, 1.0, 0.5));
+        mirror.material.color = Color::new(0.1, 0.1, 0.1);
+        mirror.material.reflective = 0.9;
+
+        let mut glass = Sphere::default();
+        glass.set_transform(
+            Matrix::get_translation_matrix(1.5, 0.5, -0.5)
+                * Matrix::get_scaling_matrix(0.5, 0.5, 0.5),
+        );
+        glass.material.color = Color::new(0.1, 0.0, 0.0);
+        glass.material.transparency = 0.9;
+        glass.material.refractive_index = 1.5;
+
+        World {
+            light: Light::new(point(-10.0, 10.0, -10.0), Color::white()),
+            objects: vec![
+                Box::new(floor),
+                Box::new(wall),
+                Box::new(mirror),
+                Box::new(glass),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn golden_default_world() {
+        let camera = camera(40, 30, point(0.0, 1.5, -5.0));
+        assert_render_matches_golden("default_world", &World::default(), &camera, TOLERANCE);
+    }
+
+    #[test]
+    fn golden_room() {
+        let camera = camera(64, 32, point(0.0, 1.5, -5.0));
+        assert_render_matches_golden("room", &room(), &camera, TOLERANCE);
+    }
+}
@@ -0,0 +1,547 @@
+use crate::utils::{invalid_data, save_with};
+use crate::Canvas;
+use crate::Color;
+use std::fs;
+use std::io;
+use std::io::Write;
+
+// High dynamic range formats keep the colors as they are, above 1.0 and with all their precision,
+// unlike the ppm and the png which clip them to 0.0..=1.0, so, exposure and grading can be done later
+impl Canvas {
+    // Portable float map, three 32 bit floats per pixel, rows from the bottom up as the format wants
+    pub fn write_pfm_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // A negative scale means little endian
+        writer.write_all(format!("PF\n{} {}\n-1.0\n", self.width, self.height).as_bytes())?;
+        let mut bytes = Vec::with_capacity(self.width * 12);
+        for row in (0..self.height).rev() {
+            bytes.clear();
+            for color in &self[row] {
+                for value in [color.red, color.green, color.blue] {
+                    bytes.extend_from_slice(&(value as f32).to_le_bytes());
+                }
+            }
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    // Radiance RGBE, a shared 8 bit exponent for the three colors of a pixel, with run length encoded rows
+    // Smaller than the other float formats, with about 1% of precision, and no negative colors
+    pub fn write_hdr_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+        writer.write_all(format!("-Y {} +X {}\n", self.height, self.width).as_bytes())?;
+        let mut pixels = Vec::with_capacity(self.width);
+        for row in 0..self.height {
+            pixels.clear();
+            pixels.extend(self[row].iter().map(|&color| rgbe(color)));
+            writer.write_all(&hdr_scanline(&pixels))?;
+        }
+        Ok(())
+    }
+
+    // Uncompressed scanline OpenEXR with 32 bit float R, G and B channels
+    pub fn write_exr_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let mut header = Vec::new();
+        header.extend_from_slice(&20000630_i32.to_le_bytes()); // Magic number
+        header.extend_from_slice(&2_i32.to_le_bytes()); // Version 2, single part scanline image
+
+        let mut channels = Vec::new();
+        // Channels are sorted by name
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2_i32.to_le_bytes()); // 32 bit float
+            channels.extend_from_slice(&[0, 0, 0, 0]); // Not linear, reserved
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // Sampling of x
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // Sampling of y
+        }
+        channels.push(0);
+        let mut window = Vec::new();
+        for value in [0, 0, width - 1, height - 1] {
+            window.extend_from_slice(&value.to_le_bytes());
+        }
+        exr_attribute(&mut header, "channels", "chlist", &channels);
+        exr_attribute(&mut header, "compression", "compression", &[0]);
+        exr_attribute(&mut header, "dataWindow", "box2i", &window);
+        exr_attribute(&mut header, "displayWindow", "box2i", &window);
+        exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // Increasing y
+        exr_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        exr_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        header.push(0);
+        writer.write_all(&header)?;
+
+        // Offsets of the rows from the start of the file, each row is its y, its size and its channels
+        let row_size = self.width * 3 * 4;
+        let first_row = header.len() + self.height * 8;
+        for row in 0..self.height {
+            let offset = (first_row + row * (8 + row_size)) as u64;
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        let mut bytes = Vec::with_capacity(8 + row_size);
+        for row in 0..self.height {
+            bytes.clear();
+            bytes.extend_from_slice(&(row as i32).to_le_bytes());
+            bytes.extend_from_slice(&(row_size as i32).to_le_bytes());
+            for channel in [|c: &Color| c.blue, |c: &Color| c.green, |c: &Color| c.red] {
+                for color in &self[row] {
+                    bytes.extend_from_slice(&(channel(color) as f32).to_le_bytes());
+                }
+            }
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_pfm(&self, file_path: &str) -> io::Result<()> {
+        save_with(file_path, |writer| self.write_pfm_to(writer))
+    }
+
+    pub fn save_hdr(&self, file_path: &str) -> io::Result<()> {
+        save_with(file_path, |writer| self.write_hdr_to(writer))
+    }
+
+    pub fn save_exr(&self, file_path: &str) -> io::Result<()> {
+        save_with(file_path, |writer| self.write_exr_to(writer))
+    }
+
+    pub fn read_hdr(file_path: &str) -> io::Result<Canvas> {
+        Canvas::from_hdr(&fs::read(file_path)?)
+    }
+
+    // Convert Radiance RGBE data to canvas, with flat, run length encoded or old style run length encoded rows
+    // Only the usual orientation, rows from the top down, is supported
+    pub fn from_hdr(data: &[u8]) -> io::Result<Canvas> {
+        if !data.starts_with(b"#?") {
+            return Err(invalid_data("not a Radiance file"));
+        }
+        let mut position = 0;
+        let mut next_line = || {
+            let rest = &data[position..];
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or_else(|| invalid_data("header is cut short"))?;
+            position += end + 1;
+            Ok::<_, io::Error>((String::from_utf8_lossy(&rest[..end]).into_owned(), position))
+        };
+        // Header lines end with an empty line
+        next_line()?;
+        loop {
+            let (line, _) = next_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(invalid_data("unsupported pixel format"));
+                }
+            }
+        }
+        let (resolution, start) = next_line()?;
+        let parts: Vec<&str> = resolution.split_whitespace().collect();
+        let (height, width) = match parts[..] {
+            ["-Y", height, "+X", width] => (
+                height
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data("invalid height"))?,
+                width
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data("invalid width"))?,
+            ),
+            _ => return Err(invalid_data("unsupported orientation")),
+        };
+        // Sizes are checked before anything of the size of the image is allocated: every row starts
+        // with a whole pixel, and even old style runs of a flat image don't give that many pixels a byte
+        let pixel_count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data("image is too large"))?;
+        let remaining = data.len() - start;
+        if width > 0 && (height > remaining / 4 || pixel_count / MAX_PIXELS_PER_BYTE > remaining) {
+            return Err(invalid_data(
+                "pixel data is too short for the size of the image",
+            ));
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        let mut reader = ScanlineReader {
+            data,
+            position: start,
+        };
+        for row in 0..height {
+            let pixels = reader.scanline(width)?;
+            for (x, &pixel) in pixels.iter().enumerate() {
+                canvas.data[row * width + x] = from_rgbe(pixel);
+            }
+        }
+        Ok(canvas)
+    }
+}
+
+// Most pixels a byte of RGBE data can hold, for sizes of images in headers to be believable
+const MAX_PIXELS_PER_BYTE: usize = 1 << 16;
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Color as three mantissas and the exponent they share, negative values are stored as 0.0
+pub(crate) fn rgbe(color: Color) -> [u8; 4] {
+    let (r, g, b) = (
+        color.red.max(0.0),
+        color.green.max(0.0),
+        color.blue.max(0.0),
+    );
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 {
+        return [0; 4];
+    }
+    // brightest = mantissa * 2^exponent, with the mantissa in 0.5..1.0
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    if brightest / 2f64.powi(exponent) >= 1.0 {
+        exponent += 1;
+    } else if brightest / 2f64.powi(exponent) < 0.5 {
+        exponent -= 1;
+    }
+    let exponent = exponent.clamp(-128, 127);
+    let scale = 256.0 / 2f64.powi(exponent);
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+pub(crate) fn from_rgbe(pixel: [u8; 4]) -> Color {
+    if pixel[3] == 0 {
+        return Color::black();
+    }
+    // Middle of the range of each mantissa
+    let scale = 2f64.powi(pixel[3] as i32 - 136);
+    Color::new(
+        (pixel[0] as f64 + 0.5) * scale,
+        (pixel[1] as f64 + 0.5) * scale,
+        (pixel[2] as f64 + 0.5) * scale,
+    )
+}
+
+struct ScanlineReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl ScanlineReader<'_> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| invalid_data("pixel data is cut short"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn pixel(&mut self) -> io::Result<[u8; 4]> {
+        Ok([self.byte()?, self.byte()?, self.byte()?, self.byte()?])
+    }
+
+    fn scanline(&mut self, width: usize) -> io::Result<Vec<[u8; 4]>> {
+        let mut pixels = Vec::with_capacity(width);
+        if width == 0 {
+            return Ok(pixels);
+        }
+        let first = self.pixel()?;
+        if first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0 && (8..=0x7FFF).contains(&width) {
+            if ((first[2] as usize) << 8 | first[3] as usize) != width {
+                return Err(invalid_data("scanline has the wrong width"));
+            }
+            pixels.resize(width, [0; 4]);
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = self.byte()? as usize;
+                    let (length, run) = if count > 128 {
+                        (count - 128, true)
+                    } else {
+                        (count, false)
+                    };
+                    if length == 0 || x + length > width {
+                        return Err(invalid_data("run goes past the end of the scanline"));
+                    }
+                    let value = if run { self.byte()? } else { 0 };
+                    for pixel in &mut pixels[x..x + length] {
+                        pixel[component] = if run { value } else { self.byte()? };
+                    }
+                    x += length;
+                }
+            }
+            return Ok(pixels);
+        }
+
+        // Flat pixels, where (1, 1, 1, n) repeats the previous pixel, n shifted by 8 more bits each time in a row
+        let mut pixel = first;
+        let mut shift = 0u32;
+        loop {
+            if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+                let previous = *pixels
+                    .last()
+                    .ok_or_else(|| invalid_data("repeat without a pixel before"))?;
+                let count = (pixel[3] as usize)
+                    .checked_shl(shift)
+                    .filter(|count| count >> shift == pixel[3] as usize)
+                    .ok_or_else(|| invalid_data("repeat count overflows"))?;
+                if count == 0 {
+                    return Err(invalid_data("repeat of no pixels"));
+                }
+                if count > width - pixels.len() {
+                    return Err(invalid_data("run goes past the end of the scanline"));
+                }
+                pixels.extend(std::iter::repeat_n(previous, count));
+                shift += 8;
+            } else {
+                pixels.push(pixel);
+                shift = 0;
+            }
+            if pixels.len() == width {
+                return Ok(pixels);
+            }
+            pixel = self.pixel()?;
+        }
+    }
+}
+
+// Row of RGBE pixels, run length encoded one component at a time as newer Radiance files are
+// Rows shorter than 8 or longer than 32767 pixels can't be, and are written as they are
+fn hdr_scanline(pixels: &[[u8; 4]]) -> Vec<u8> {
+    let width = pixels.len();
+    if !(8..=0x7FFF).contains(&width) {
+        return pixels.concat();
+    }
+    let mut out = vec![2, 2, (width >> 8) as u8, (width & 0xFF) as u8];
+    for component in 0..4 {
+        let values: Vec<u8> = pixels.iter().map(|p| p[component]).collect();
+        run_length_encode(&values, &mut out);
+    }
+    out
+}
+
+// Runs are a count above 128 and the repeated value, other bytes are a count up to 128 and the bytes as they are
+fn run_length_encode(values: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4; // Shorter runs are cheaper to write as they are
+    let mut i = 0;
+    while i < values.len() {
+        // Find where the next long enough run starts
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(127)
+                .take_while(|&&v| v == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += 1;
+        }
+        for literal in values[i..run_start].chunks(128) {
+            out.push(literal.len() as u8);
+            out.extend_from_slice(literal);
+        }
+        if run_start < values.len() {
+            out.push(128 + run_length as u8);
+            out.push(values[run_start]);
+            i = run_start + run_length;
+        } else {
+            i = run_start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod hdr_tests {
+    use super::*;
+
+    fn bright_canvas() -> Canvas {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 0, Color::new(1.0, 2.0, 3.0));
+        c.write_pixel(1, 0, Color::new(-0.5, 0.25, 100.0));
+        c.write_pixel(0, 1, Color::new(0.0, 0.0, 0.0));
+        c.write_pixel(1, 1, Color::new(0.5, 0.5, 0.5));
+        c
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writing_pfm() {
+        let mut pfm = Vec::new();
+        bright_canvas().write_pfm_to(&mut pfm).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(pfm[..header.len()], header[..]);
+        assert_eq!(pfm.len(), header.len() + 4 * 3 * 4);
+        // Bottom row first
+        let data = &pfm[header.len()..];
+        assert_eq!(f32_at(data, 12), 0.5);
+        assert_eq!(f32_at(data, 24), 1.0);
+        assert_eq!(f32_at(data, 36), -0.5);
+        assert_eq!(f32_at(data, 44), 100.0);
+    }
+
+    #[test]
+    fn rgbe_shares_exponent() {
+        assert_eq!(rgbe(Color::new(1.0, 1.0, 1.0)), [128, 128, 128, 129]);
+        assert_eq!(rgbe(Color::new(0.5, 0.25, 0.0)), [128, 64, 0, 128]);
+        assert_eq!(rgbe(Color::new(-1.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        for color in [Color::new(1.0, 2.0, 3.0), Color::new(100.0, 0.1, 7.5)] {
+            let [r, g, b, e] = rgbe(color);
+            let scale = 2f64.powi(e as i32 - 128 - 8);
+            for (a, b) in [
+                (r as f64 * scale, color.red),
+                (g as f64 * scale, color.green),
+                (b as f64 * scale, color.blue),
+            ] {
+                // Precision is relative to the brightest of the three
+                assert!((a - b).abs() <= color.red.max(color.blue) / 128.0);
+            }
+        }
+    }
+
+    #[test]
+    fn writing_hdr() {
+        let mut hdr = Vec::new();
+        bright_canvas().write_hdr_to(&mut hdr).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 2\n";
+        assert_eq!(hdr[..header.len()], header[..]);
+        // Rows of two pixels are too short to be run length encoded
+        assert_eq!(hdr.len(), header.len() + 16);
+        assert_eq!(
+            hdr[header.len()..header.len() + 4],
+            rgbe(Color::new(1.0, 2.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn run_length_encoding_rows() {
+        let mut out = Vec::new();
+        run_length_encode(&[7, 7, 7, 7, 7, 7, 1, 2, 3, 3, 9, 9, 9, 9], &mut out);
+        assert_eq!(out, [134, 7, 4, 1, 2, 3, 3, 132, 9]);
+
+        let pixels = vec![[10, 20, 30, 129]; 10];
+        assert_eq!(
+            hdr_scanline(&pixels),
+            [2, 2, 0, 10, 138, 10, 138, 20, 138, 30, 138, 129]
+        );
+        let long: Vec<u8> = (0..300).map(|i| (i % 2) as u8).collect();
+        let mut out = Vec::new();
+        run_length_encode(&long, &mut out);
+        assert_eq!(out.len(), 300 + 3);
+        assert_eq!((out[0], out[129], out[258]), (128, 128, 44));
+    }
+
+    #[test]
+    fn writing_exr() {
+        let mut exr = Vec::new();
+        bright_canvas().write_exr_to(&mut exr).unwrap();
+        assert_eq!(exr[..8], [0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0]);
+        // Offsets of the two rows follow the header, each row is y, size, then B, G and R of both pixels
+        let text = String::from_utf8_lossy(&exr);
+        for name in [
+            "channels",
+            "chlist",
+            "compression",
+            "dataWindow",
+            "lineOrder",
+        ] {
+            assert!(text.contains(name));
+        }
+        let table = exr.len() - 2 * (8 + 24) - 16;
+        let offset = |i: usize| {
+            u64::from_le_bytes(exr[table + i * 8..table + i * 8 + 8].try_into().unwrap()) as usize
+        };
+        assert_eq!(offset(0), table + 16);
+        assert_eq!(offset(1), table + 16 + 32);
+        let row = offset(0);
+        assert_eq!(exr[row..row + 8], [0, 0, 0, 0, 24, 0, 0, 0]);
+        // Blue of both pixels, then green, then red
+        assert_eq!(f32_at(&exr, row + 8), 3.0);
+        assert_eq!(f32_at(&exr, row + 12), 100.0);
+        assert_eq!(f32_at(&exr, row + 24), 1.0);
+        assert_eq!(f32_at(&exr, row + 28), -0.5);
+        assert_eq!(exr[table - 1], 0);
+    }
+
+    #[test]
+    fn reading_written_hdr() {
+        // Run length encoded rows and rows too short for it
+        for width in [12, 3] {
+            let mut c = Canvas::new(width, 2);
+            for x in 0..width {
+                c.write_pixel(x, 0, Color::new(x as f64 * 0.5, 2.0, 100.0));
+                c.write_pixel(x, 1, Color::new(0.1, 0.2, 0.3));
+            }
+            let mut hdr = Vec::new();
+            c.write_hdr_to(&mut hdr).unwrap();
+            let read = Canvas::from_hdr(&hdr).unwrap();
+            assert_eq!((read.width, read.height), (width, 2));
+            for (a, b) in read.data.iter().zip(&c.data) {
+                let brightest = b.red.max(b.green).max(b.blue);
+                for (x, y) in [(a.red, b.red), (a.green, b.green), (a.blue, b.blue)] {
+                    assert!((x - y).abs() <= brightest / 128.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reading_old_run_length_encoding() {
+        let mut hdr = b"#?RADIANCE\n\n-Y 1 +X 5\n".to_vec();
+        // A pixel repeated 4 times
+        hdr.extend_from_slice(&[128, 64, 32, 129, 1, 1, 1, 4]);
+        let read = Canvas::from_hdr(&hdr).unwrap();
+        for x in 0..5 {
+            assert_eq!(read.pixel_at(x, 0), from_rgbe([128, 64, 32, 129]));
+        }
+        assert_eq!(
+            from_rgbe([128, 64, 32, 129]),
+            Color::new(1.00390625, 0.50390625, 0.25390625)
+        );
+        assert_eq!(from_rgbe([0, 0, 0, 0]), Color::black());
+    }
+
+    #[test]
+    fn reading_broken_hdr() {
+        assert!(Canvas::from_hdr(b"P3\n1 1\n").is_err());
+        assert!(
+            Canvas::from_hdr(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n1234").is_err()
+        );
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n+Y 1 +X 1\n1234").is_err());
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 1 +X 2\n1234").is_err());
+        // Run longer than the row
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 1 +X 2\n1234\x01\x01\x01\x02").is_err());
+        // Repeat of no pixels, which would let later repeats shift their counts past the size of a usize
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 1 +X 2\n1234\x01\x01\x01\x00").is_err());
+        // Huge sizes are rejected before anything of their size is allocated
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 100000 +X 100000\n1234").is_err());
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 1 +X 18446744073709551615\n1234").is_err());
+        assert!(Canvas::from_hdr(b"#?RADIANCE\n\n-Y 4294967296 +X 4294967296\n1234").is_err());
+    }
+}
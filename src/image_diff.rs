@@ -0,0 +1,152 @@
+use crate::Canvas;
+use crate::Color;
+
+// How far apart two canvases of the same size are, the errors are absolute differences of the channels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageDiff {
+    pub mean_error: f64,
+    pub rmse: f64,                    // Root of the mean of the squared errors
+    pub psnr: f64, // Peak signal to noise ratio in decibels, for a peak of 1.0, infinite when equal
+    pub max_error: f64, // Largest error of any channel of any pixel
+    pub max_error_at: (usize, usize), // (col, row) of the pixel with `max_error`
+}
+
+impl Canvas {
+    // Measures the errors of the canvas compared to the `reference`, None when their sizes differ
+    pub fn compare(&self, reference: &Canvas) -> Option<ImageDiff> {
+        if !same_size(self, reference) {
+            return None;
+        }
+        let mut total = 0.0;
+        let mut total_squared = 0.0;
+        let mut max_error = 0.0;
+        let mut max_error_at = (0, 0);
+        for (i, (a, b)) in self.data.iter().zip(&reference.data).enumerate() {
+            let difference = channel_errors(*a, *b);
+            for error in difference {
+                total += error;
+                total_squared += error * error;
+            }
+            let error = pixel_error(*a, *b);
+            if error > max_error {
+                max_error = error;
+                max_error_at = (i % self.width, i / self.width);
+            }
+        }
+        let count = (self.data.len() * 3).max(1) as f64;
+        let rmse = (total_squared / count).sqrt();
+        Some(ImageDiff {
+            mean_error: total / count,
+            rmse,
+            psnr: -20.0 * rmse.log10(),
+            max_error,
+            max_error_at,
+        })
+    }
+
+    // Canvas of the absolute differences of each channel with the `reference`, None when their sizes differ
+    pub fn absolute_difference(&self, reference: &Canvas) -> Option<Canvas> {
+        if !same_size(self, reference) {
+            return None;
+        }
+        let mut canvas = Canvas::new(self.width, self.height);
+        for (i, (a, b)) in self.data.iter().zip(&reference.data).enumerate() {
+            let [red, green, blue] = channel_errors(*a, *b);
+            canvas.data[i] = Color::new(red, green, blue);
+        }
+        Some(canvas)
+    }
+
+    // Heatmap of the largest channel error of each pixel, black where the canvases are the same, then red,
+    // yellow and white at an error of `scale` and above. None when their sizes differ
+    pub fn diff_heatmap(&self, reference: &Canvas, scale: f64) -> Option<Canvas> {
+        if !same_size(self, reference) {
+            return None;
+        }
+        let mut canvas = Canvas::new(self.width, self.height);
+        for (i, (a, b)) in self.data.iter().zip(&reference.data).enumerate() {
+            let t = (pixel_error(*a, *b) / scale).clamp(0.0, 1.0) * 3.0;
+            canvas.data[i] = Color::new(
+                t.min(1.0),
+                (t - 1.0).clamp(0.0, 1.0),
+                (t - 2.0).clamp(0.0, 1.0),
+            );
+        }
+        Some(canvas)
+    }
+}
+
+fn same_size(a: &Canvas, b: &Canvas) -> bool {
+    (a.width, a.height) == (b.width, b.height)
+}
+
+fn channel_errors(a: Color, b: Color) -> [f64; 3] {
+    [
+        (a.red - b.red).abs(),
+        (a.green - b.green).abs(),
+        (a.blue - b.blue).abs(),
+    ]
+}
+
+fn pixel_error(a: Color, b: Color) -> f64 {
+    let [red, green, blue] = channel_errors(a, b);
+    red.max(green).max(blue)
+}
+
+#[cfg(test)]
+mod image_diff_tests {
+    use super::*;
+
+    fn canvases() -> (Canvas, Canvas) {
+        let mut a = Canvas::new(2, 2);
+        a.fill(Color::new(0.5, 0.5, 0.5));
+        let mut b = a.clone();
+        b.write_pixel(1, 0, Color::new(0.5, 0.9, 0.3));
+        (a, b)
+    }
+
+    #[test]
+    fn comparing_same_canvases() {
+        let (a, _) = canvases();
+        let diff = a.compare(&a).unwrap();
+        assert_eq!(diff.rmse, 0.0);
+        assert_eq!(diff.max_error, 0.0);
+        assert_eq!(diff.psnr, f64::INFINITY);
+    }
+
+    #[test]
+    fn comparing_different_canvases() {
+        let (a, b) = canvases();
+        let diff = a.compare(&b).unwrap();
+        assert!((diff.mean_error - 0.6 / 12.0).abs() < 1e-12);
+        assert!((diff.rmse - (0.2f64 / 12.0).sqrt()).abs() < 1e-12);
+        assert!((diff.psnr - 17.78151).abs() < 1e-5);
+        assert!((diff.max_error - 0.4).abs() < 1e-12);
+        assert_eq!(diff.max_error_at, (1, 0));
+    }
+
+    #[test]
+    fn difference_and_heatmap() {
+        let (a, b) = canvases();
+        assert_eq!(
+            a.absolute_difference(&b).unwrap().pixel_at(1, 0),
+            Color::new(0.0, 0.4, 0.2)
+        );
+        let heatmap = a.diff_heatmap(&b, 0.8).unwrap();
+        assert_eq!(heatmap.pixel_at(0, 0), Color::black());
+        assert_eq!(heatmap.pixel_at(1, 0), Color::new(1.0, 0.5, 0.0));
+        assert_eq!(
+            a.diff_heatmap(&b, 0.1).unwrap().pixel_at(1, 0),
+            Color::white()
+        );
+    }
+
+    #[test]
+    fn comparing_canvases_of_different_sizes() {
+        let (a, _) = canvases();
+        let wide = Canvas::new(3, 2);
+        assert_eq!(a.compare(&wide), None);
+        assert!(a.absolute_difference(&wide).is_none());
+        assert!(wide.diff_heatmap(&a, 1.0).is_none());
+    }
+}
@@ -1,43 +1,64 @@
 use crate::utils::EPSILON;
 use crate::Ray;
 use crate::Shape;
-use crate::Sphere;
 use crate::Tuple;
 
 // Store data for ray intersection with a object in the scene
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct Intersection {
-    pub t: f64,         // At what time hit occured
-    pub object: Sphere, // TODO: use dynamic data type
+#[derive(Copy, Clone, Debug)]
+pub struct Intersection<'a> {
+    pub t: f64,                // At what time hit occured
+    pub object: &'a dyn Shape, // Object which was hit, borrowed from the `World`
+    // Where the hit is on the surface, for shapes which need it, eg.- barycentric coordinates of a triangle
+    pub u: f64,
+    pub v: f64,
 }
 
 // Store vector of all intersections
 #[derive(Default)]
-pub struct Intersections {
-    data: Vec<Intersection>,
+pub struct Intersections<'a> {
+    data: Vec<Intersection<'a>>,
 }
 
 // Store some precomputations for the intersection
-pub struct Computation {
-    t: f64,
-    pub object: Sphere,
+pub struct Computation<'a> {
+    pub t: f64,
+    pub object: &'a dyn Shape,
     pub point: Tuple,
     pub eyev: Tuple,
     pub normalv: Tuple,
-    inside: bool,
+    pub inside: bool,
     pub over_point: Tuple, // Just slightly above the point towards the normal to avoid `acne`
+    pub under_point: Tuple, // Just slightly below the surface, where refracted rays start
+    pub reflectv: Tuple,   // Direction of the ray mirrored by the surface
+    pub n1: f64,           // Refractive index of the material the ray is leaving
+    pub n2: f64,           // Refractive index of the material the ray is entering
+    pub time: f64,         // Time of the ray, rays leaving the hit should be shot at the same time
 }
 
-impl Intersection {
-    pub fn new(t: f64, object: Sphere) -> Self {
-        Self { t, object }
+impl<'a> Intersection<'a> {
+    pub fn new(t: f64, object: &'a dyn Shape) -> Self {
+        Self::new_with_uv(t, object, 0.0, 0.0)
+    }
+
+    pub fn new_with_uv(t: f64, object: &'a dyn Shape, u: f64, v: f64) -> Self {
+        Self { t, object, u, v }
     }
 
     // Create computation object for ray intersection with the object
-    pub fn prepare_computations(&self, ray: &Ray) -> Computation {
+    // `xs` are all the intersections of the ray, sorted, which tell what the ray is inside of at the hit
+    pub fn prepare_computations(&self, ray: &Ray, xs: &Intersections<'a>) -> Computation<'a> {
         let point = ray.position(self.t);
         let eyev = -ray.direction;
-        let mut normalv = self.object.normal_at(point);
+        let material = self.object.get_material();
+        let mut normalv = self.object.normal_at_hit(point, self, ray.time);
+        if let Some(bump_map) = &material.bump_map {
+            normalv = bump_map.perturb_normal(self.object, point, normalv, ray.time);
+        }
+        if let Some(normal_map) = &material.normal_map {
+            if let Some(((u, v), tangent)) = self.object.tangent_at_hit(self, ray.time) {
+                normalv = normal_map.perturb_normal(normalv, tangent, u, v);
+            }
+        }
         let mut inside = false;
 
         // when eye vector is inside the sphere, negate the normal vector
@@ -47,6 +68,9 @@ impl Intersection {
         }
         // let over_point = point + normalv * EPSILON * 1000.0; // NOTE: why do we need this so large?? To compensate for f64 rounding errors -> use f64
         let over_point = point + normalv * EPSILON; // NOTE: use this in f64 to pass tests
+        let under_point = point - normalv * EPSILON;
+        let reflectv = ray.direction.reflect(&normalv);
+        let (n1, n2) = self.refractive_indices(xs);
 
         Computation {
             t: self.t,
@@ -56,23 +80,94 @@ impl Intersection {
             normalv,
             inside,
             over_point,
+            under_point,
+            reflectv,
+            n1,
+            n2,
+            time: ray.time,
+        }
+    }
+
+    // Refractive indices on both sides of the hit, objects can be nested or overlap
+    // Walk the intersections up to the hit, keeping the objects the ray is inside of (containers),
+    // the last one entered is the material around the ray
+    fn refractive_indices(&self, xs: &Intersections<'a>) -> (f64, f64) {
+        let mut containers: Vec<&dyn Shape> = Vec::new();
+        let outermost = |containers: &Vec<&dyn Shape>| {
+            containers
+                .last()
+                .map_or(1.0, |object| object.get_material().refractive_index)
+        };
+        let mut n1 = 1.0;
+        for intersection in &xs.data {
+            if intersection == self {
+                n1 = outermost(&containers);
+            }
+            match containers
+                .iter()
+                .position(|&object| std::ptr::addr_eq(object, intersection.object))
+            {
+                Some(index) => {
+                    containers.remove(index);
+                }
+                None => containers.push(intersection.object),
+            }
+            if intersection == self {
+                return (n1, outermost(&containers));
+            }
+        }
+        // The hit isn't one of `xs`, take it as a lone surface in the air
+        let refractive_index = self.object.get_material().refractive_index;
+        (1.0, refractive_index)
+    }
+}
+
+impl Computation<'_> {
+    // Direction of the ray bent by the surface (Snell's law), `None` on total internal reflection
+    pub fn refracted_direction(&self) -> Option<Tuple> {
+        let n_ratio = self.n1 / self.n2;
+        let cos_i = self.eyev.dot(&self.normalv);
+        let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some((self.normalv * (n_ratio * cos_i - cos_t) - self.eyev * n_ratio).normalize())
+    }
+
+    // Fraction of the light reflected by a transparent surface, Schlick's approximation of the Fresnel equations
+    pub fn schlick(&self) -> f64 {
+        let mut cos = self.eyev.dot(&self.normalv);
+        if self.n1 > self.n2 {
+            let n = self.n1 / self.n2;
+            let sin2_t = n * n * (1.0 - cos * cos);
+            if sin2_t > 1.0 {
+                return 1.0;
+            }
+            cos = (1.0 - sin2_t).sqrt();
         }
+        let r0 = ((self.n1 - self.n2) / (self.n1 + self.n2)).powi(2);
+        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
     }
 }
 
-// impl PartialEq for Intersection {
-//     fn eq(&self, other: &Self) -> bool {
-//         self.t == other.t && self.object == other.object
-//     }
-// }
+// Intersections are the same when they hit the same object, the very same one, not an equal copy
+impl PartialEq for Intersection<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.t == other.t
+            && std::ptr::addr_eq(self.object, other.object)
+            && self.u == other.u
+            && self.v == other.v
+    }
+}
 
-impl Intersections {
+impl<'a> Intersections<'a> {
     pub fn new() -> Self {
         Default::default()
     }
 
     // Self has only a vector so abstract out push
-    pub fn push(&mut self, intersection: Intersection) {
+    pub fn push(&mut self, intersection: Intersection<'a>) {
         self.data.push(intersection)
     }
 
@@ -83,7 +178,7 @@ impl Intersections {
 
     // Append one Intersection object with the other
     // Basically, append the data vector of both
-    pub fn extend(&mut self, b: Intersections) {
+    pub fn extend(&mut self, b: Intersections<'a>) {
         self.data.extend(b.data);
     }
 
@@ -98,9 +193,9 @@ impl Intersections {
 
     // Hit is the `intersection` with the lowest non-negative value.
     // Can be empty as well.
-    pub fn hit(&self) -> Option<Intersection> {
-        let mut result: Option<Intersection> = None;
-        for &intersection in &self.data {
+    pub fn hit(&self) -> Option<Intersection<'a>> {
+        let mut result: Option<&Intersection> = None;
+        for intersection in &self.data {
             if intersection.t > 0.0 {
                 match result {
                     Some(prev_intersection) => {
@@ -112,20 +207,26 @@ impl Intersections {
                 }
             }
         }
-        result
+        result.cloned()
+    }
+}
+
+impl<'a> From<Vec<Intersection<'a>>> for Intersections<'a> {
+    fn from(data: Vec<Intersection<'a>>) -> Self {
+        Self { data }
     }
 }
 
 // Self has only a vector so abstract out indexing
-impl std::ops::Index<usize> for Intersections {
-    type Output = Intersection;
+impl<'a> std::ops::Index<usize> for Intersections<'a> {
+    type Output = Intersection<'a>;
 
     fn index(&self, idx: usize) -> &Self::Output {
         &self.data[idx]
     }
 }
 
-impl std::ops::IndexMut<usize> for Intersections {
+impl std::ops::IndexMut<usize> for Intersections<'_> {
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
         &mut self.data[idx]
     }
@@ -134,22 +235,31 @@ impl std::ops::IndexMut<usize> for Intersections {
 #[cfg(test)]
 mod sphere_tests {
     use super::*;
+    use crate::BumpMap;
     use crate::{point, vector};
-    use crate::{Matrix, Ray};
+    use crate::{Compare, Matrix, Ray, Sphere};
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    fn glass_sphere() -> Sphere {
+        let mut s = Sphere::default();
+        s.material.transparency = 1.0;
+        s.material.refractive_index = 1.5;
+        s
+    }
 
     #[test]
     fn intersection_creation() {
-        let s = Default::default();
-        let i = Intersection::new(3.5, Default::default());
+        let s = Sphere::default();
+        let i = Intersection::new(3.5, &s);
         assert_eq!(i.t, 3.5);
-        assert_eq!(i.object, s);
+        assert!(std::ptr::addr_eq(i.object, &s));
     }
 
     #[test]
     fn intersections_test() {
         let s: Sphere = Default::default(); // INFO: this will work if we remove `Sphere`. As compiler infers it from the next line
-        let i1 = Intersection::new(1.0, s);
-        let i2 = Intersection::new(2.0, s);
+        let i1 = Intersection::new(1.0, &s);
+        let i2 = Intersection::new(2.0, &s);
         let mut xs: Intersections = Default::default();
         xs.push(i1);
         xs.push(i2);
@@ -163,15 +273,15 @@ mod sphere_tests {
         let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
         let s: Sphere = Default::default();
         let xs = s.intersect(r);
-        assert_eq!(xs[0].object, s);
-        assert_eq!(xs[1].object, s);
+        assert!(std::ptr::addr_eq(xs[0].object, &s));
+        assert!(std::ptr::addr_eq(xs[1].object, &s));
     }
 
     #[test]
     fn hit1() {
         let s: Sphere = Default::default();
-        let i1 = Intersection::new(1.0, s);
-        let i2 = Intersection::new(2.0, s);
+        let i1 = Intersection::new(1.0, &s);
+        let i2 = Intersection::new(2.0, &s);
         let mut xs = Intersections::default();
         xs.push(i2);
         xs.push(i1);
@@ -182,8 +292,8 @@ mod sphere_tests {
     #[test]
     fn hit2() {
         let s: Sphere = Default::default();
-        let i1 = Intersection::new(-1.0, s);
-        let i2 = Intersection::new(1.0, s);
+        let i1 = Intersection::new(-1.0, &s);
+        let i2 = Intersection::new(1.0, &s);
         let mut xs = Intersections::default();
         xs.push(i2);
         xs.push(i1);
@@ -194,8 +304,8 @@ mod sphere_tests {
     #[test]
     fn hit3() {
         let s: Sphere = Default::default();
-        let i1 = Intersection::new(-2.0, s);
-        let i2 = Intersection::new(-1.0, s);
+        let i1 = Intersection::new(-2.0, &s);
+        let i2 = Intersection::new(-1.0, &s);
         let mut xs = Intersections::default();
         xs.push(i2);
         xs.push(i1);
@@ -206,10 +316,10 @@ mod sphere_tests {
     #[test]
     fn hit4() {
         let s: Sphere = Default::default();
-        let i1 = Intersection::new(5.0, s);
-        let i2 = Intersection::new(7.0, s);
-        let i3 = Intersection::new(-3.0, s);
-        let i4 = Intersection::new(2.0, s);
+        let i1 = Intersection::new(5.0, &s);
+        let i2 = Intersection::new(7.0, &s);
+        let i3 = Intersection::new(-3.0, &s);
+        let i4 = Intersection::new(2.0, &s);
         let mut xs = Intersections::default();
         xs.push(i1);
         xs.push(i2);
@@ -223,10 +333,10 @@ mod sphere_tests {
     fn precomputing_intersection_state() {
         let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
         let shape: Sphere = Default::default();
-        let i = Intersection::new(4.0, shape);
-        let comps = i.prepare_computations(&r);
+        let i = Intersection::new(4.0, &shape);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
         assert_eq!(comps.t, i.t);
-        assert_eq!(comps.object, i.object);
+        assert!(std::ptr::addr_eq(comps.object, i.object));
         assert_eq!(comps.point, point(0.0, 0.0, -1.0));
         assert_eq!(comps.eyev, vector(0.0, 0.0, -1.0));
         assert_eq!(comps.normalv, vector(0.0, 0.0, -1.0));
@@ -236,8 +346,8 @@ mod sphere_tests {
     fn hit_when_intersection_is_outside() {
         let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
         let shape: Sphere = Default::default();
-        let i = Intersection::new(4.0, shape);
-        let comps = i.prepare_computations(&r);
+        let i = Intersection::new(4.0, &shape);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
         assert!(!comps.inside);
     }
 
@@ -245,8 +355,8 @@ mod sphere_tests {
     fn hit_when_intersection_is_inside() {
         let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
         let shape: Sphere = Default::default();
-        let i = Intersection::new(1.0, shape);
-        let comps = i.prepare_computations(&r);
+        let i = Intersection::new(1.0, &shape);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
         assert_eq!(comps.point, point(0.0, 0.0, 1.0));
         assert_eq!(comps.eyev, vector(0.0, 0.0, -1.0));
         assert!(comps.inside);
@@ -258,9 +368,136 @@ mod sphere_tests {
         let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
         let mut shape = Sphere::default();
         shape.set_transform(Matrix::get_translation_matrix(0.0, 0.0, 1.0));
-        let i = Intersection::new(5.0, shape);
-        let comps = i.prepare_computations(&r);
+        let i = Intersection::new(5.0, &shape);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
         assert!(comps.over_point.z < -EPSILON / 2.0);
         assert!(comps.point.z > comps.over_point.z);
     }
+
+    #[test]
+    fn precomputing_reflection_vector() {
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let s = Sphere::default();
+        let i = Intersection::new(4.0, &s);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        assert_eq!(comps.reflectv, vector(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn under_point_is_below_surface() {
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let s = glass_sphere();
+        let xs = s.intersect(r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert!(comps.under_point.z > EPSILON / 2.0 - 1.0);
+        assert!(comps.point.z < comps.under_point.z);
+    }
+
+    #[test]
+    fn refractive_indices_at_nested_objects() {
+        let mut a = glass_sphere();
+        a.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        let mut b = glass_sphere();
+        b.set_transform(Matrix::get_translation_matrix(0.0, 0.0, -0.25));
+        b.material.refractive_index = 2.0;
+        let mut c = glass_sphere();
+        c.set_transform(Matrix::get_translation_matrix(0.0, 0.0, 0.25));
+        c.material.refractive_index = 2.5;
+
+        let r = Ray::new(point(0.0, 0.0, -4.0), vector(0.0, 0.0, 1.0));
+        let xs = Intersections::from(vec![
+            Intersection::new(2.0, &a),
+            Intersection::new(2.75, &b),
+            Intersection::new(3.25, &c),
+            Intersection::new(4.75, &b),
+            Intersection::new(5.25, &c),
+            Intersection::new(6.0, &a),
+        ]);
+        let expected = [
+            (1.0, 1.5),
+            (1.5, 2.0),
+            (2.0, 2.5),
+            (2.5, 2.5),
+            (2.5, 1.5),
+            (1.5, 1.0),
+        ];
+        for (index, (n1, n2)) in expected.into_iter().enumerate() {
+            let comps = xs[index].prepare_computations(&r, &xs);
+            assert_eq!((comps.n1, comps.n2), (n1, n2));
+        }
+    }
+
+    #[test]
+    fn refraction_bends_towards_normal() {
+        let r = Ray::new(point(0.0, 0.5, -5.0), vector(0.0, 0.0, 1.0));
+        let s = glass_sphere();
+        let xs = s.intersect(r);
+        let comps = xs.hit().unwrap().prepare_computations(&r, &xs);
+        let refracted = comps.refracted_direction().unwrap();
+        let sin_i = comps.eyev.cross(&comps.normalv).magnitude();
+        let sin_t = refracted.cross(&comps.normalv).magnitude();
+        assert!((sin_i / sin_t).eq(1.5));
+        assert!(refracted.dot(&comps.normalv) < 0.0);
+    }
+
+    #[test]
+    fn schlick_under_total_internal_reflection() {
+        let r = Ray::new(point(0.0, 0.0, FRAC_1_SQRT_2), vector(0.0, 1.0, 0.0));
+        let s = glass_sphere();
+        let xs = Intersections::from(vec![
+            Intersection::new(-FRAC_1_SQRT_2, &s),
+            Intersection::new(FRAC_1_SQRT_2, &s),
+        ]);
+        let comps = xs[1].prepare_computations(&r, &xs);
+        assert_eq!(comps.refracted_direction(), None);
+        assert_eq!(comps.schlick(), 1.0);
+    }
+
+    #[test]
+    fn schlick_with_perpendicular_viewing_angle() {
+        let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        let s = glass_sphere();
+        let xs = Intersections::from(vec![
+            Intersection::new(-1.0, &s),
+            Intersection::new(1.0, &s),
+        ]);
+        let comps = xs[1].prepare_computations(&r, &xs);
+        assert!(comps.schlick().eq(0.04));
+    }
+
+    #[test]
+    fn schlick_with_small_angle_and_n2_greater_than_n1() {
+        let r = Ray::new(point(0.0, 0.99, -2.0), vector(0.0, 0.0, 1.0));
+        let s = glass_sphere();
+        let i = Intersection::new(1.8589, &s);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        assert!(comps.schlick().eq(0.48873));
+    }
+
+    #[test]
+    fn bump_map_perturbs_normal_before_flipping_inside_hit() {
+        let mut shape = Sphere::default();
+        shape.material.bump_map = Some(BumpMap::Noise {
+            scale: 0.2,
+            octaves: 2,
+            strength: 0.8,
+        });
+        let outside_ray = Ray::new(point(0.3, 0.2, -5.0), vector(0.0, 0.0, 1.0));
+        let outside = shape.intersect(outside_ray).hit().unwrap();
+        let outside_comps =
+            outside.prepare_computations(&outside_ray, &shape.intersect(outside_ray));
+        let plain_normal = shape.normal_at(outside_comps.point);
+        assert_ne!(outside_comps.normalv, plain_normal);
+        assert!(!outside_comps.inside);
+
+        // Same point seen from inside gets the same tilted normal, just flipped
+        let inside_ray = Ray::new(
+            point(0.0, 0.0, 0.0),
+            (outside_comps.point - point(0.0, 0.0, 0.0)).normalize(),
+        );
+        let inside = shape.intersect(inside_ray).hit().unwrap();
+        let inside_comps = inside.prepare_computations(&inside_ray, &shape.intersect(inside_ray));
+        assert!(inside_comps.inside);
+        assert_eq!(inside_comps.normalv, -outside_comps.normalv);
+    }
 }
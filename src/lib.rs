@@ -1,34 +1,88 @@
+mod background;
+mod bump;
 mod camera;
 mod canvas;
 mod color;
+mod environment_light;
+#[cfg(test)]
+mod golden;
+mod hdr;
+mod image_diff;
 mod intersection;
 mod light;
 mod material;
 mod matrix;
 mod matrix_small;
+mod microfacet;
+mod motion;
+mod noise;
+mod normal_map;
+mod path_tracer;
+mod pattern;
+mod photon_map;
 mod plane;
+mod png;
+mod post_process;
+mod progressive;
 mod projectile;
+mod random;
 mod ray;
+mod render_job;
 mod shape;
 mod sphere;
+mod stereo;
+#[cfg(test)]
+mod test_fixtures;
+mod texture;
+mod tone_map;
 mod transformation;
+mod triangle;
 mod tuple;
 mod utils;
+mod volume;
 mod world;
+mod zlib;
 
 // Use in chapter end exercises
-pub use camera::Camera;
-pub use canvas::Canvas;
+pub use background::Background;
+pub use bump::BumpMap;
+pub use camera::{Camera, CameraBuilder, Projection};
+pub use canvas::{Canvas, Region};
 pub use color::Color;
+pub use environment_light::{EnvironmentLight, EnvironmentSample};
+pub use image_diff::ImageDiff;
 pub use intersection::{Computation, Intersection, Intersections};
 pub use light::Light;
 pub use material::Material;
 pub use matrix::Matrix;
 pub use matrix_small::{Matrix2, Matrix3};
+pub use microfacet::{
+    ggx_distribution, metallic_roughness_brdf, schlick_fresnel, smith_geometry, ReflectionModel,
+};
+pub use motion::Motion;
+pub use noise::{fractal_noise, perlin_noise};
+pub use normal_map::{vertex_tangents, NormalMap, Tangent};
+pub use path_tracer::PathTracer;
+pub use pattern::Pattern;
+pub use photon_map::{Photon, PhotonMap};
+pub use plane::Plane;
+pub use png::{BitDepth, Compression, PngOptions};
+pub use post_process::ResizeFilter;
+pub use progressive::{ProgressiveRender, StopCondition, StopReason};
 pub use projectile::{Environment, Projectile};
+pub use random::Rng;
 pub use ray::Ray;
+pub use render_job::RenderJob;
 pub use shape::Shape;
 pub use sphere::Sphere;
+pub use stereo::{anaglyph, side_by_side, Eye, StereoCamera, StereoLayout};
+pub use texture::{
+    cylindrical_map, planar_map, spherical_map, CubeFace, CubeMap, TextureFilter, TextureMap,
+    UVImage, UVMapping, UVPattern,
+};
+pub use tone_map::{srgb_decode, srgb_encode, Encoding, ToneMap, ToneMapping};
+pub use triangle::{Triangle, Vertex};
 pub use tuple::{point, vector, Tuple};
 pub use utils::Compare;
+pub use volume::{henyey_greenstein, Density, Fog, Medium, Volume};
 pub use world::World;
@@ -1,42 +1,100 @@
+use crate::microfacet::metallic_roughness_brdf;
+use crate::BumpMap;
 use crate::Light;
+use crate::NormalMap;
+use crate::Pattern;
+use crate::ReflectionModel;
+use crate::Shape;
 use crate::{Color, Tuple};
+use std::f64::consts::PI;
+use std::rc::Rc;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub color: Color,
-    pub ambient: f64,   // 0.0 to 1.0
-    pub diffuse: f64,   // 0.0 to 1.0
-    pub specular: f64,  // 0.0 to 1.0
+    pub pattern: Option<Rc<dyn Pattern>>, // Overrides `color` when present; shared so that materials stay cheap to clone
+    pub ambient: f64,                     // 0.0 to 1.0
+    pub diffuse: f64,                     // 0.0 to 1.0
+    pub specular: f64,                    // 0.0 to 1.0
     pub shininess: f64, // usally between 10.0 (very large highlight) to 200.0 (very small highlight)
+    pub reflective: f64, // 0.0 to 1.0, fraction of the light mirrored
+    pub transparency: f64, // 0.0 to 1.0, fraction of the light going through
+    pub refractive_index: f64, // How much the light bends when entering the object, eg.- 1.5 for glass
+    pub bump_map: Option<BumpMap>, // Tilts the normal of the surface to fake small bumps
+    pub normal_map: Option<Rc<NormalMap>>, // Tangent space normals, for shapes with texture coordinates like meshes
+    pub model: ReflectionModel,            // Phong by default
+    pub emissive: Color, // Light given off by the surface itself, not affected by lights or shadows
 }
 
 impl Material {
     pub fn new(color: Color, ambient: f64, diffuse: f64, specular: f64, shininess: f64) -> Self {
         Self {
             color,
+            pattern: None,
             ambient,
             diffuse,
             specular,
             shininess,
+            reflective: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+            bump_map: None,
+            normal_map: None,
+            model: ReflectionModel::Phong,
+            emissive: Color::black(),
         }
     }
 
-    // Phong reflection model for shading
+    // Physically based material, see `ReflectionModel::MetallicRoughness`
+    pub fn metallic_roughness(color: Color, metallic: f64, roughness: f64) -> Self {
+        Self {
+            color,
+            model: ReflectionModel::MetallicRoughness {
+                metallic,
+                roughness,
+            },
+            ..Default::default()
+        }
+    }
+
+    // Shading from a point light, with the Phong reflection model unless another `model` is selected
+    // `emissive` is always added, even in shadows
+    #[allow(clippy::too_many_arguments)]
     pub fn lighting(
         &self,
+        object: &dyn Shape, // Object being shaded, needed to find the color of the pattern
         light: Light,
         hit_point: Tuple,
         eyev: Tuple,
         normalv: Tuple,
         in_shadow: bool, // whether the point is in the shadow
+        time: f64,       // Time of the ray, where a moving object and its pattern are
     ) -> Color {
-        let effective_color = self.color * light.intensity;
+        let effective_color = self.color_at(object, hit_point, time) * light.intensity;
         let lightv = (light.position - hit_point).normalize();
         let ambient = effective_color * self.ambient;
         let mut diffuse = Color::black();
         let mut specular = Color::black();
 
         let light_dot_normal = lightv.dot(&normalv);
+        if let ReflectionModel::MetallicRoughness {
+            metallic,
+            roughness,
+        } = self.model
+        {
+            if in_shadow {
+                return self.emissive + ambient;
+            }
+            // Scaled by PI so that a white matte surface facing a light is as bright as the light,
+            // like `diffuse` of 1.0 in the Phong model
+            let base_color = self.color_at(object, hit_point, time);
+            let brdf =
+                metallic_roughness_brdf(base_color, metallic, roughness, lightv, eyev, normalv);
+            return self.emissive
+                + ambient
+                + brdf * light.intensity * (PI * light_dot_normal.max(0.0));
+        }
+
         // Don't compute diffuse and specular when the point is on shadow
         if (light_dot_normal >= 0.0) && !in_shadow {
             diffuse = effective_color * self.diffuse * light_dot_normal;
@@ -48,7 +106,99 @@ impl Material {
                 specular = light.intensity * self.specular * factor;
             }
         }
-        ambient + diffuse + specular
+        self.emissive + ambient + diffuse + specular
+    }
+
+    // Light reflected towards the eye when light of `radiance` arrives from the direction `lightv`
+    // Used for lights spread over many directions, like `EnvironmentLight`, which are integrated by sampling
+    // Diffuse and specular are normalized (energy conserving) so that a uniform white environment
+    // lights a surface like a white point light shining straight at it
+    #[allow(clippy::too_many_arguments)]
+    pub fn reflected_radiance(
+        &self,
+        object: &dyn Shape,
+        radiance: Color,
+        hit_point: Tuple,
+        lightv: Tuple, // Unit vector towards the light
+        eyev: Tuple,
+        normalv: Tuple,
+        time: f64,
+    ) -> Color {
+        let light_dot_normal = lightv.dot(&normalv);
+        if light_dot_normal <= 0.0 {
+            return Color::black();
+        }
+        self.brdf(object, hit_point, lightv, eyev, normalv, time) * radiance * light_dot_normal
+    }
+
+    // Fraction of the light arriving from `lightv` which is reflected towards `eyev`, per steradian
+    pub fn brdf(
+        &self,
+        object: &dyn Shape,
+        hit_point: Tuple,
+        lightv: Tuple,
+        eyev: Tuple,
+        normalv: Tuple,
+        time: f64,
+    ) -> Color {
+        if lightv.dot(&normalv) <= 0.0 {
+            return Color::black();
+        }
+        if let ReflectionModel::MetallicRoughness {
+            metallic,
+            roughness,
+        } = self.model
+        {
+            let base_color = self.color_at(object, hit_point, time);
+            return metallic_roughness_brdf(base_color, metallic, roughness, lightv, eyev, normalv);
+        }
+        let diffuse = self.color_at(object, hit_point, time) * (self.diffuse / PI);
+
+        let mut specular = Color::black();
+        let reflect_dot_eye = (-lightv).reflect(&normalv).dot(&eyev);
+        if reflect_dot_eye > 0.0 {
+            let normalization = (self.shininess + 2.0) / (2.0 * PI);
+            let factor = self.specular * normalization * reflect_dot_eye.powf(self.shininess);
+            specular = Color::new(factor, factor, factor);
+        }
+        diffuse + specular
+    }
+
+    // Color of the material at the world point `hit_point` of the `object`, at the `time`
+    fn color_at(&self, object: &dyn Shape, hit_point: Tuple, time: f64) -> Color {
+        match &self.pattern {
+            Some(pattern) => pattern.pattern_at_shape(object, hit_point, time),
+            None => self.color,
+        }
+    }
+}
+
+// Patterns are compared by identity as they can be arbitrary trait objects
+impl PartialEq for Material {
+    fn eq(&self, other: &Self) -> bool {
+        let same_pattern = match (&self.pattern, &other.pattern) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        let same_normal_map = match (&self.normal_map, &other.normal_map) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.color == other.color
+            && same_pattern
+            && self.ambient == other.ambient
+            && self.diffuse == other.diffuse
+            && self.specular == other.specular
+            && self.shininess == other.shininess
+            && self.reflective == other.reflective
+            && self.transparency == other.transparency
+            && self.refractive_index == other.refractive_index
+            && self.bump_map == other.bump_map
+            && same_normal_map
+            && self.model == other.model
+            && self.emissive == other.emissive
     }
 }
 
@@ -61,7 +211,9 @@ impl Default for Material {
 #[cfg(test)]
 mod material_tests {
     use super::*;
+    use crate::texture::{TextureFilter, TextureMap, UVImage, UVMapping};
     use crate::{point, vector};
+    use crate::{Canvas, Matrix, Sphere};
     use std::f64::consts::FRAC_1_SQRT_2;
 
     #[test]
@@ -72,6 +224,12 @@ mod material_tests {
         assert_eq!(m.diffuse, 0.9);
         assert_eq!(m.specular, 0.9);
         assert_eq!(m.shininess, 200.0);
+        assert_eq!(m.reflective, 0.0);
+        assert_eq!(m.transparency, 0.0);
+        assert_eq!(m.refractive_index, 1.0);
+        assert_eq!(m.bump_map, None);
+        assert_eq!(m.model, ReflectionModel::Phong);
+        assert_eq!(m.emissive, Color::black());
     }
 
     #[test]
@@ -82,7 +240,15 @@ mod material_tests {
         let normalv = vector(0.0, 0.0, -1.0);
         let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
         let in_shadow = false;
-        let result = m.lighting(light, position, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(1.9, 1.9, 1.9));
     }
 
@@ -94,7 +260,15 @@ mod material_tests {
         let normalv = vector(0.0, 0.0, -1.0);
         let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
         let in_shadow = false;
-        let result = m.lighting(light, position, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(1.0, 1.0, 1.0));
     }
 
@@ -106,7 +280,15 @@ mod material_tests {
         let normalv = vector(0.0, 0.0, -1.0);
         let light = Light::new(point(0.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0));
         let in_shadow = false;
-        let result = m.lighting(light, position, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(0.7364, 0.7364, 0.7364));
     }
 
@@ -118,7 +300,15 @@ mod material_tests {
         let normalv = vector(0.0, 0.0, -1.0);
         let light = Light::new(point(0.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0));
         let in_shadow = false;
-        let result = m.lighting(light, position, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(1.636396, 1.636396, 1.636396));
     }
 
@@ -130,7 +320,15 @@ mod material_tests {
         let normalv = vector(0.0, 0.0, -1.0);
         let light = Light::new(point(0.0, 0.0, 10.0), Color::new(1.0, 1.0, 1.0));
         let in_shadow = false;
-        let result = m.lighting(light, position, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(0.1, 0.1, 0.1));
     }
 
@@ -142,7 +340,261 @@ mod material_tests {
         let eyev = vector(0.0, 0.0, -1.0);
         let normalv = vector(0.0, 0.0, -1.0);
         let in_shadow = true;
-        let result = m.lighting(light, hit_point, eyev, normalv, in_shadow);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            hit_point,
+            eyev,
+            normalv,
+            in_shadow,
+            0.0,
+        );
         assert_eq!(result, Color::new(0.1, 0.1, 0.1));
     }
+
+    #[test]
+    fn lighting_with_pattern_applied() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(0, 0, Color::red());
+        canvas.write_pixel(1, 0, Color::blue());
+        let texture = TextureMap::new(
+            Box::new(UVImage::new(canvas, TextureFilter::Nearest)),
+            UVMapping::Planar,
+        );
+        let m = Material {
+            pattern: Some(Rc::new(texture)),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            ..Default::default()
+        };
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let object = Sphere::default();
+        let c1 = m.lighting(
+            &object,
+            light,
+            point(0.2, 0.0, 0.0),
+            eyev,
+            normalv,
+            false,
+            0.0,
+        );
+        let c2 = m.lighting(
+            &object,
+            light,
+            point(0.8, 0.0, 0.0),
+            eyev,
+            normalv,
+            false,
+            0.0,
+        );
+        assert_eq!(c1, Color::red());
+        assert_eq!(c2, Color::blue());
+    }
+
+    #[test]
+    fn lighting_with_pattern_on_transformed_object() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(0, 0, Color::red());
+        canvas.write_pixel(1, 0, Color::blue());
+        let texture = TextureMap::new(
+            Box::new(UVImage::new(canvas, TextureFilter::Nearest)),
+            UVMapping::Planar,
+        );
+        let m = Material {
+            pattern: Some(Rc::new(texture)),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            ..Default::default()
+        };
+        let mut object = Sphere::default();
+        object.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        // x = 1.6 in the world space is 0.8 in the object space
+        let c = m.lighting(
+            &object,
+            light,
+            point(1.6, 0.0, 0.0),
+            eyev,
+            normalv,
+            false,
+            0.0,
+        );
+        assert_eq!(c, Color::blue());
+    }
+
+    #[test]
+    fn materials_with_same_pattern_are_equal() {
+        let canvas = Canvas::new(1, 1);
+        let texture: Rc<dyn Pattern> = Rc::new(TextureMap::new(
+            Box::new(UVImage::new(canvas, TextureFilter::Nearest)),
+            UVMapping::Planar,
+        ));
+        let m1 = Material {
+            pattern: Some(texture.clone()),
+            ..Default::default()
+        };
+        let m2 = m1.clone();
+        assert_eq!(m1, m2);
+        assert_ne!(m1, Material::default());
+    }
+
+    #[test]
+    fn reflected_radiance_from_light_behind_surface() {
+        let m = Material::default();
+        let c = m.reflected_radiance(
+            &Sphere::default(),
+            Color::white(),
+            point(0.0, 0.0, 0.0),
+            vector(0.0, 0.0, 1.0),
+            vector(0.0, 0.0, -1.0),
+            vector(0.0, 0.0, -1.0),
+            0.0,
+        );
+        assert_eq!(c, Color::black());
+    }
+
+    #[test]
+    fn reflected_radiance_is_normalized_diffuse() {
+        let m = Material {
+            specular: 0.0,
+            ..Default::default()
+        };
+        let c = m.reflected_radiance(
+            &Sphere::default(),
+            Color::white(),
+            point(0.0, 0.0, 0.0),
+            vector(0.0, 0.0, -1.0),
+            vector(0.0, 0.0, -1.0),
+            vector(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let expected = 0.9 / PI;
+        assert_eq!(c, Color::new(expected, expected, expected));
+    }
+
+    #[test]
+    fn metallic_roughness_material() {
+        let m = Material::metallic_roughness(Color::red(), 1.0, 0.3);
+        assert_eq!(m.color, Color::red());
+        assert_eq!(
+            m.model,
+            ReflectionModel::MetallicRoughness {
+                metallic: 1.0,
+                roughness: 0.3
+            }
+        );
+        assert_eq!(m.ambient, Material::default().ambient);
+    }
+
+    #[test]
+    fn metallic_roughness_lighting_matte_surface_facing_light() {
+        let m = Material {
+            ambient: 0.0,
+            ..Material::metallic_roughness(Color::white(), 0.0, 1.0)
+        };
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::white());
+        let eyev = vector(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let c = m.lighting(
+            &Sphere::default(),
+            light,
+            point(0.0, 0.0, 0.0),
+            eyev,
+            normalv,
+            false,
+            0.0,
+        );
+        // Close to a white Lambertian surface, a little is lost to the specular reflection
+        assert!(c.red > 0.85 && c.red < 1.0, "{:?}", c);
+    }
+
+    #[test]
+    fn metallic_roughness_lighting_in_shadow() {
+        let m = Material::metallic_roughness(Color::white(), 0.0, 0.5);
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::white());
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let c = m.lighting(
+            &Sphere::default(),
+            light,
+            point(0.0, 0.0, 0.0),
+            eyev,
+            normalv,
+            true,
+            0.0,
+        );
+        assert_eq!(c, Color::new(0.1, 0.1, 0.1));
+    }
+
+    #[test]
+    fn metallic_roughness_reflected_radiance_is_brdf_times_cosine() {
+        let m = Material::metallic_roughness(Color::white(), 0.0, 1.0);
+        let lightv = vector(0.0, 0.6, -0.8);
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let c = m.reflected_radiance(
+            &Sphere::default(),
+            Color::white(),
+            point(0.0, 0.0, 0.0),
+            lightv,
+            eyev,
+            normalv,
+            0.0,
+        );
+        let brdf = metallic_roughness_brdf(Color::white(), 0.0, 1.0, lightv, eyev, normalv);
+        assert_eq!(c, brdf * 0.8);
+    }
+
+    #[test]
+    fn emissive_is_added_to_lighting() {
+        let m = Material {
+            emissive: Color::new(0.5, 0.25, 0.0),
+            ..Default::default()
+        };
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let position = point(0.0, 0.0, 0.0);
+        let result = m.lighting(
+            &Sphere::default(),
+            light,
+            position,
+            eyev,
+            normalv,
+            false,
+            0.0,
+        );
+        assert_eq!(result, Color::new(2.4, 2.15, 1.9));
+    }
+
+    #[test]
+    fn emissive_is_added_in_shadow() {
+        let emissive = Color::new(0.5, 0.25, 0.0);
+        let light = Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let eyev = vector(0.0, 0.0, -1.0);
+        let normalv = vector(0.0, 0.0, -1.0);
+        let position = point(0.0, 0.0, 0.0);
+        for m in [
+            Material::default(),
+            Material::metallic_roughness(Color::new(1.0, 1.0, 1.0), 0.0, 0.5),
+        ] {
+            let m = Material { emissive, ..m };
+            let result = m.lighting(
+                &Sphere::default(),
+                light,
+                position,
+                eyev,
+                normalv,
+                true,
+                0.0,
+            );
+            assert_eq!(result, Color::new(0.6, 0.35, 0.1));
+        }
+    }
 }
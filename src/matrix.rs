@@ -27,7 +27,7 @@ impl Matrix {
 
     // Create a copy of input and transpose it
     pub fn transpose(&self) -> Self {
-        let mut result = self.clone(); // TODO: fix clippy lint
+        let mut result = *self;
         for i in 1..MATRIX_SIZE {
             for j in 0..i {
                 (result[i][j], result[j][i]) = (result[j][i], result[i][j]);
@@ -104,7 +104,7 @@ impl PartialEq for Matrix2 {
         for row in 0..2 {
             for col in 0..2 {
                 if self.data[row][col].neq(other[row][col]) {
-                    false;
+                    return false;
                 }
             }
         }
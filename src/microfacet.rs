@@ -0,0 +1,230 @@
+use crate::Color;
+use crate::Tuple;
+use std::f64::consts::PI;
+
+// Roughness below this makes the highlight of a point light infinitely small and bright
+const MIN_ROUGHNESS: f64 = 0.02;
+
+// Reflectance of dielectrics (plastic, wood, stone...) when looking straight at them
+const DIELECTRIC_REFLECTANCE: f64 = 0.04;
+
+// How the light reflected by a material is computed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReflectionModel {
+    // Classic Phong, uses `ambient`, `diffuse`, `specular` and `shininess` of the material
+    Phong,
+    // Physically based metallic-roughness model, as used by glTF and most PBR tools
+    // The surface is made of tiny mirrors (microfacets) with GGX distribution, Smith geometry and
+    // Schlick Fresnel. Energy conserving: light that is reflected specularly is not diffused
+    // Uses the color (or pattern) of the material as the base color, and its `ambient`
+    MetallicRoughness {
+        metallic: f64, // 0.0 for dielectrics, 1.0 for metals, which have no diffuse and tinted highlights
+        roughness: f64, // 0.0 (mirror like) to 1.0 (very rough)
+    },
+}
+
+impl ReflectionModel {
+    // Non-metallic, medium rough surface
+    pub fn metallic_roughness() -> Self {
+        ReflectionModel::MetallicRoughness {
+            metallic: 0.0,
+            roughness: 0.5,
+        }
+    }
+}
+
+// GGX (Trowbridge-Reitz) normal distribution function
+// Density of the microfacets whose normal is the half vector `halfv`, `alpha` is roughness squared
+pub fn ggx_distribution(normalv: Tuple, halfv: Tuple, alpha: f64) -> f64 {
+    let n_dot_h = normalv.dot(&halfv).max(0.0);
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * d * d)
+}
+
+// Smith shadowing-masking, the fraction of microfacets visible from both the light and the eye
+pub fn smith_geometry(n_dot_l: f64, n_dot_v: f64, alpha: f64) -> f64 {
+    let g1 = |n_dot_x: f64| {
+        let alpha2 = alpha * alpha;
+        2.0 * n_dot_x / (n_dot_x + (alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x).sqrt())
+    };
+    g1(n_dot_l) * g1(n_dot_v)
+}
+
+// Schlick's approximation of the Fresnel reflectance, `f0` is the reflectance when looking straight at the surface
+pub fn schlick_fresnel(f0: Color, cos_theta: f64) -> Color {
+    let factor = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Color::white() - f0) * factor
+}
+
+// Fraction of the light arriving from `lightv` which is reflected towards `eyev`, per steradian
+// All the vectors are unit vectors pointing away from the surface
+pub fn metallic_roughness_brdf(
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    lightv: Tuple,
+    eyev: Tuple,
+    normalv: Tuple,
+) -> Color {
+    let n_dot_l = normalv.dot(&lightv);
+    if n_dot_l <= 0.0 {
+        return Color::black();
+    }
+    // Avoid dividing by 0 when looking along the surface
+    let n_dot_v = normalv.dot(&eyev).max(1.0e-4);
+    let halfv = (lightv + eyev).normalize();
+    let metallic = metallic.clamp(0.0, 1.0);
+    let alpha = roughness.clamp(MIN_ROUGHNESS, 1.0).powi(2);
+
+    // Metals reflect with their own color, dielectrics reflect a little bit of white
+    let f0 = Color::new(
+        DIELECTRIC_REFLECTANCE,
+        DIELECTRIC_REFLECTANCE,
+        DIELECTRIC_REFLECTANCE,
+    ) * (1.0 - metallic)
+        + base_color * metallic;
+    let fresnel = schlick_fresnel(f0, halfv.dot(&eyev));
+    let d = ggx_distribution(normalv, halfv, alpha);
+    let g = smith_geometry(n_dot_l, n_dot_v, alpha);
+    let specular = fresnel * (d * g / (4.0 * n_dot_l * n_dot_v));
+
+    // Whatever is not reflected at the surface enters and gets diffused, except in metals
+    // Fresnel towards the eye bounds how much the microfacets can reflect in total, using it instead of
+    // the Fresnel of each light direction keeps diffuse + specular from going above 1
+    let reflected = schlick_fresnel(f0, n_dot_v);
+    let diffuse = (Color::white() - reflected) * base_color * ((1.0 - metallic) / PI);
+    diffuse + specular
+}
+
+#[cfg(test)]
+mod microfacet_tests {
+    use super::*;
+    use crate::{vector, Compare};
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    // Integrate brdf * cos over the hemisphere with the midpoint rule
+    fn directional_albedo(base_color: Color, metallic: f64, roughness: f64, eyev: Tuple) -> Color {
+        let normalv = vector(0.0, 1.0, 0.0);
+        let (steps_theta, steps_phi) = (200, 400);
+        let mut total = Color::black();
+        for i in 0..steps_theta {
+            let theta = (i as f64 + 0.5) / steps_theta as f64 * PI / 2.0;
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) / steps_phi as f64 * 2.0 * PI;
+                let lightv = vector(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega =
+                    theta.sin() * (PI / 2.0 / steps_theta as f64) * (2.0 * PI / steps_phi as f64);
+                let f =
+                    metallic_roughness_brdf(base_color, metallic, roughness, lightv, eyev, normalv);
+                total = total + f * (theta.cos() * d_omega);
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn default_model_is_dielectric() {
+        assert_eq!(
+            ReflectionModel::metallic_roughness(),
+            ReflectionModel::MetallicRoughness {
+                metallic: 0.0,
+                roughness: 0.5
+            }
+        );
+    }
+
+    #[test]
+    fn fresnel_at_normal_and_grazing_angles() {
+        let f0 = Color::new(0.04, 0.04, 0.04);
+        assert_eq!(schlick_fresnel(f0, 1.0), f0);
+        assert_eq!(schlick_fresnel(f0, 0.0), Color::white());
+    }
+
+    #[test]
+    fn ggx_distribution_integrates_to_one() {
+        // Projected area of the microfacets is the area of the surface
+        let normalv = vector(0.0, 1.0, 0.0);
+        let steps = 2000;
+        let mut total = 0.0;
+        for i in 0..steps {
+            let theta = (i as f64 + 0.5) / steps as f64 * PI / 2.0;
+            let halfv = vector(theta.sin(), theta.cos(), 0.0);
+            let d_omega = 2.0 * PI * theta.sin() * (PI / 2.0 / steps as f64);
+            total += ggx_distribution(normalv, halfv, 0.25) * theta.cos() * d_omega;
+        }
+        assert!((total - 1.0).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn smith_geometry_is_one_for_smooth_surface_facing_light() {
+        assert!(smith_geometry(1.0, 1.0, 0.0).eq(1.0));
+        assert!(smith_geometry(0.3, 0.7, 0.5) < 1.0);
+    }
+
+    #[test]
+    fn light_behind_surface_is_not_reflected() {
+        let c = metallic_roughness_brdf(
+            Color::white(),
+            0.0,
+            0.5,
+            vector(0.0, -1.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+        );
+        assert_eq!(c, Color::black());
+    }
+
+    #[test]
+    fn metals_have_no_diffuse() {
+        // Light and eye are far from the mirror direction of a smooth metal
+        let c = metallic_roughness_brdf(
+            Color::new(1.0, 0.8, 0.3),
+            1.0,
+            0.05,
+            vector(FRAC_1_SQRT_2, FRAC_1_SQRT_2, 0.0),
+            vector(0.0, FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            vector(0.0, 1.0, 0.0),
+        );
+        assert!(c.red < 1.0e-3 && c.green < 1.0e-3 && c.blue < 1.0e-3);
+    }
+
+    #[test]
+    fn metal_highlight_is_tinted_by_base_color() {
+        let base = Color::new(1.0, 0.5, 0.25);
+        let c = metallic_roughness_brdf(
+            base,
+            1.0,
+            0.3,
+            vector(0.0, 1.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+        );
+        assert!(c.red.eq(2.0 * c.green));
+        assert!(c.green.eq(2.0 * c.blue));
+    }
+
+    #[test]
+    fn rough_white_dielectric_is_nearly_lambertian() {
+        let eyev = vector(0.0, 1.0, 0.0);
+        let albedo = directional_albedo(Color::white(), 0.0, 1.0, eyev);
+        assert!(albedo.red > 0.9 && albedo.red <= 1.0, "{:?}", albedo);
+    }
+
+    #[test]
+    fn reflected_energy_never_exceeds_incoming() {
+        for roughness in [0.3, 0.5, 1.0] {
+            for metallic in [0.0, 1.0] {
+                for eyev in [vector(0.0, 1.0, 0.0), vector(0.8, 0.6, 0.0)] {
+                    let albedo = directional_albedo(Color::white(), metallic, roughness, eyev);
+                    // Allow for the error of the coarse integration of the narrow highlights
+                    assert!(albedo.red <= 1.0 + 1.0e-2, "{:?}", albedo);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::utils::EPSILON;
+use crate::Matrix;
+
+// Transformation changing over time, for motion blur of moving objects and cameras
+// Every keyframe is split into a translation, a rotation and a stretch (scaling and shearing), which are
+// interpolated on their own: the translation and the stretch linearly, the rotation along the shortest arc.
+// So, a spinning object keeps its size between the keyframes, and the transformation never becomes singular
+#[derive(Clone, Debug, PartialEq)]
+pub struct Motion {
+    keyframes: Vec<Keyframe>, // Sorted by time
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Keyframe {
+    time: f64,
+    translation: [f64; 3],
+    rotation: Quaternion,
+    stretch: Matrix, // Upper triangular, with a positive diagonal, apart from the last entry of mirrored keyframes
+}
+
+impl Motion {
+    // Moves from the `start` transformation at time 0.0 to the `end` transformation at time 1.0
+    // `None` when the transformations can't be interpolated, see `from_keyframes`
+    pub fn new(start: Matrix, end: Matrix) -> Option<Self> {
+        Self::from_keyframes(vec![(0.0, start), (1.0, end)])
+    }
+
+    // `None` when a keyframe is singular or has a perspective part, or when mirrored and unmirrored keyframes
+    // are mixed, as the interpolation would flatten the object on the way between them
+    pub fn from_keyframes(keyframes: Vec<(f64, Matrix)>) -> Option<Self> {
+        let mut keyframes = keyframes
+            .into_iter()
+            .map(|(time, m)| decompose(time, m))
+            .collect::<Option<Vec<Keyframe>>>()?;
+        let mirrored = |keyframe: &Keyframe| keyframe.stretch[2][2] < 0.0;
+        if let Some(first) = keyframes.first() {
+            if keyframes.iter().any(|k| mirrored(k) != mirrored(first)) {
+                return None;
+            }
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Some(Self { keyframes })
+    }
+
+    // (time, transformation) of every keyframe, sorted by time
+    pub fn get_keyframes(&self) -> Vec<(f64, Matrix)> {
+        self.keyframes
+            .iter()
+            .map(|keyframe| (keyframe.time, compose(keyframe)))
+            .collect()
+    }
+
+    // Transformation at the `time`, held at the first or last keyframe outside of them
+    pub fn transform_at(&self, time: f64) -> Matrix {
+        let Some(first) = self.keyframes.first() else {
+            return Matrix::I();
+        };
+        if time <= first.time {
+            return compose(first);
+        }
+        for window in self.keyframes.windows(2) {
+            let (k0, k1) = (&window[0], &window[1]);
+            if time <= k1.time {
+                let s = if k1.time > k0.time {
+                    (time - k0.time) / (k1.time - k0.time)
+                } else {
+                    1.0
+                };
+                return compose(&interpolate(k0, k1, s));
+            }
+        }
+        compose(&self.keyframes[self.keyframes.len() - 1])
+    }
+}
+
+// Splits the affine transformation `m` into `translation * rotation * stretch`
+// The columns of the upper 3x3 part are made orthonormal (Gram-Schmidt), which gives the rotation,
+// and the stretch is what is left, an upper triangular matrix
+fn decompose(time: f64, m: Matrix) -> Option<Keyframe> {
+    if m[3][0] != 0.0 || m[3][1] != 0.0 || m[3][2] != 0.0 || m[3][3] != 1.0 {
+        return None;
+    }
+    let column = |col: usize| [m[0][col], m[1][col], m[2][col]];
+    let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+    let mut axes = [[0.0; 3]; 3];
+    let mut stretch = Matrix::I();
+    for col in 0..3 {
+        let mut v = column(col);
+        for (row, axis) in axes.iter().enumerate().take(col) {
+            let projection = dot(column(col), *axis);
+            stretch[row][col] = projection;
+            for i in 0..3 {
+                v[i] -= projection * axis[i];
+            }
+        }
+        let length = dot(v, v).sqrt();
+        if length < EPSILON {
+            return None;
+        }
+        stretch[col][col] = length;
+        axes[col] = v.map(|x| x / length);
+    }
+
+    // A mirroring leaves the axes left handed, which isn't a rotation, so, the mirroring is moved to the stretch
+    let [x, y, z] = axes;
+    let cross = [
+        x[1] * y[2] - x[2] * y[1],
+        x[2] * y[0] - x[0] * y[2],
+        x[0] * y[1] - x[1] * y[0],
+    ];
+    if dot(cross, z) < 0.0 {
+        axes[2] = z.map(|x| -x);
+        stretch[2][2] = -stretch[2][2];
+    }
+
+    let mut rotation = Matrix::I();
+    for (col, axis) in axes.iter().enumerate() {
+        for row in 0..3 {
+            rotation[row][col] = axis[row];
+        }
+    }
+    Some(Keyframe {
+        time,
+        translation: [m[0][3], m[1][3], m[2][3]],
+        rotation: Quaternion::from_rotation(&rotation),
+        stretch,
+    })
+}
+
+fn compose(keyframe: &Keyframe) -> Matrix {
+    let [x, y, z] = keyframe.translation;
+    Matrix::get_translation_matrix(x, y, z) * keyframe.rotation.to_rotation() * keyframe.stretch
+}
+
+fn interpolate(a: &Keyframe, b: &Keyframe, s: f64) -> Keyframe {
+    let mut stretch = Matrix::I();
+    for row in 0..3 {
+        for col in 0..3 {
+            stretch[row][col] = lerp(a.stretch[row][col], b.stretch[row][col], s);
+        }
+    }
+    Keyframe {
+        time: lerp(a.time, b.time, s),
+        translation: [0, 1, 2].map(|i| lerp(a.translation[i], b.translation[i], s)),
+        rotation: a.rotation.slerp(&b.rotation, s),
+        stretch,
+    }
+}
+
+fn lerp(a: f64, b: f64, s: f64) -> f64 {
+    a + (b - a) * s
+}
+
+// Unit quaternion w + xi + yj + zk, a rotation
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    // From the rotation matrix `m`, picking the formula which divides by the largest number
+    fn from_rotation(m: &Matrix) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Self {
+                w: s / 4.0,
+                x: (m[2][1] - m[1][2]) / s,
+                y: (m[0][2] - m[2][0]) / s,
+                z: (m[1][0] - m[0][1]) / s,
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt();
+            Self {
+                w: (m[2][1] - m[1][2]) / s,
+                x: s / 4.0,
+                y: (m[0][1] + m[1][0]) / s,
+                z: (m[0][2] + m[2][0]) / s,
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt();
+            Self {
+                w: (m[0][2] - m[2][0]) / s,
+                x: (m[0][1] + m[1][0]) / s,
+                y: s / 4.0,
+                z: (m[1][2] + m[2][1]) / s,
+            }
+        } else {
+            let s = 2.0 * (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt();
+            Self {
+                w: (m[1][0] - m[0][1]) / s,
+                x: (m[0][2] + m[2][0]) / s,
+                y: (m[1][2] + m[2][1]) / s,
+                z: s / 4.0,
+            }
+        };
+        q.scale(1.0 / q.dot(&q).sqrt())
+    }
+
+    fn to_rotation(self) -> Matrix {
+        let Self { w, x, y, z } = self;
+        let mut m = Matrix::I();
+        m[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        m[0][1] = 2.0 * (x * y - w * z);
+        m[0][2] = 2.0 * (x * z + w * y);
+        m[1][0] = 2.0 * (x * y + w * z);
+        m[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        m[1][2] = 2.0 * (y * z - w * x);
+        m[2][0] = 2.0 * (x * z - w * y);
+        m[2][1] = 2.0 * (y * z + w * x);
+        m[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        m
+    }
+
+    fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    fn scale(self, s: f64) -> Self {
+        Self {
+            w: self.w * s,
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            w: self.w + other.w,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+
+    // Spherical interpolation, at a constant angular speed along the shortest arc
+    fn slerp(&self, other: &Self, s: f64) -> Self {
+        // `q` and `-q` are the same rotation, the one closer to `self` gives the shorter arc
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = other.scale(-1.0);
+        }
+        if cos > 1.0 - EPSILON {
+            // Almost the same rotation, where the angle can't be divided by
+            let q = self.scale(1.0 - s).add(other.scale(s));
+            return q.scale(1.0 / q.dot(&q).sqrt());
+        }
+        let angle = cos.acos();
+        let sin = angle.sin();
+        self.scale(((1.0 - s) * angle).sin() / sin)
+            .add(other.scale((s * angle).sin() / sin))
+    }
+}
+
+#[cfg(test)]
+mod motion_tests {
+    use super::*;
+    use crate::{point, Compare};
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    #[test]
+    fn interpolating_between_start_and_end() {
+        let motion = Motion::new(
+            Matrix::get_translation_matrix(0.0, 0.0, 0.0),
+            Matrix::get_translation_matrix(2.0, 4.0, 0.0),
+        )
+        .unwrap();
+        assert_eq!(motion.transform_at(0.0), Matrix::I());
+        assert_eq!(
+            motion.transform_at(0.25),
+            Matrix::get_translation_matrix(0.5, 1.0, 0.0)
+        );
+        assert_eq!(
+            motion.transform_at(1.0),
+            Matrix::get_translation_matrix(2.0, 4.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn holding_outside_of_keyframes() {
+        let motion = Motion::new(
+            Matrix::get_scaling_matrix(1.0, 1.0, 1.0),
+            Matrix::get_scaling_matrix(3.0, 3.0, 3.0),
+        )
+        .unwrap();
+        assert_eq!(motion.transform_at(-1.0), Matrix::I());
+        assert_eq!(
+            motion.transform_at(5.0),
+            Matrix::get_scaling_matrix(3.0, 3.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn interpolating_between_keyframes() {
+        let motion = Motion::from_keyframes(vec![
+            (2.0, Matrix::get_translation_matrix(1.0, 1.0, 0.0)),
+            (0.0, Matrix::I()),
+            (1.0, Matrix::get_translation_matrix(1.0, 0.0, 0.0)),
+        ])
+        .unwrap();
+        assert_eq!(
+            motion.transform_at(0.5),
+            Matrix::get_translation_matrix(0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            motion.transform_at(1.5),
+            Matrix::get_translation_matrix(1.0, 0.5, 0.0)
+        );
+    }
+
+    #[test]
+    fn keyframes_are_rebuilt_exactly() {
+        let m = Matrix::get_translation_matrix(1.0, -2.0, 3.0)
+            * Matrix::get_rotation_x_matrix(0.7)
+            * Matrix::get_rotation_z_matrix(2.9)
+            * Matrix::get_shearing_matrix(0.5, 0.0, 0.2, 0.0, 0.0, 0.3)
+            * Matrix::get_scaling_matrix(2.0, 0.5, 1.5);
+        let mirrored = m * Matrix::get_scaling_matrix(-1.0, 1.0, 1.0);
+        for m in [m, mirrored] {
+            let motion = Motion::new(m, m).unwrap();
+            assert_eq!(motion.transform_at(0.0), m);
+            assert_eq!(motion.transform_at(0.5), m);
+        }
+    }
+
+    #[test]
+    fn rotation_is_interpolated_along_the_arc() {
+        let motion = Motion::new(Matrix::I(), Matrix::get_rotation_z_matrix(PI)).unwrap();
+        // Interpolating each entry would give a singular matrix halfway
+        let halfway = motion.transform_at(0.5);
+        assert!(halfway.determinant().eq(1.0));
+        assert_eq!(
+            motion.transform_at(0.25),
+            Matrix::get_rotation_z_matrix(FRAC_PI_4)
+        );
+        assert_eq!(halfway * point(1.0, 0.0, 0.0), point(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn spinning_and_growing() {
+        let motion = Motion::new(
+            Matrix::I(),
+            Matrix::get_rotation_y_matrix(FRAC_PI_2) * Matrix::get_scaling_matrix(3.0, 3.0, 3.0),
+        )
+        .unwrap();
+        assert_eq!(
+            motion.transform_at(0.5),
+            Matrix::get_rotation_y_matrix(FRAC_PI_4) * Matrix::get_scaling_matrix(2.0, 2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn rejecting_keyframes_which_cant_be_interpolated() {
+        let flat = Matrix::get_scaling_matrix(1.0, 0.0, 1.0);
+        assert_eq!(Motion::new(Matrix::I(), flat), None);
+        let mut perspective = Matrix::I();
+        perspective[3][2] = 1.0;
+        assert_eq!(Motion::new(Matrix::I(), perspective), None);
+        let mirrored = Matrix::get_scaling_matrix(-1.0, 1.0, 1.0);
+        assert_eq!(Motion::new(Matrix::I(), mirrored), None);
+        assert!(Motion::new(mirrored, mirrored * Matrix::get_rotation_x_matrix(1.0)).is_some());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::Tuple;
+
+// Improved Perlin noise (Ken Perlin, 2002)
+// Smooth pseudo random value between about -1 and 1 that changes over a distance of around 1 unit
+// It is 0 at every point with integer coordinates
+pub fn perlin_noise(p: Tuple) -> f64 {
+    let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+    // Position inside the unit cube
+    let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+    let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    // Blend the contributions of the 8 corners of the cube
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        let h = lattice_hash(xi + dx, yi + dy, zi + dz);
+        grad(h, x - dx as f64, y - dy as f64, z - dz as f64)
+    };
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+
+// Sum of `octaves` layers of noise, each with double the frequency and half the amplitude of the previous one
+// Adds finer details, eg.- for rough stone. Scaled back to about -1 to 1
+pub fn fractal_noise(p: Tuple, octaves: u32) -> f64 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut max_value = 0.0;
+    for _ in 0..octaves.max(1) {
+        total += perlin_noise(p * frequency) * amplitude;
+        max_value += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    total / max_value
+}
+
+// 6t^5 - 15t^4 + 10t^3, makes the noise smooth across the cube boundaries
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// Pseudo random number for a corner of the integer lattice, used instead of the permutation table
+fn lattice_hash(x: i64, y: i64, z: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h = (h ^ (h >> 29)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^ (h >> 32)
+}
+
+// Dot product of the offset (x, y, z) with one of the 12 gradient directions picked by the hash
+fn grad(hash: u64, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod noise_tests {
+    use super::*;
+    use crate::point;
+
+    #[test]
+    fn noise_is_zero_at_lattice_points() {
+        assert_eq!(perlin_noise(point(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(perlin_noise(point(1.0, -2.0, 3.0)), 0.0);
+        assert_eq!(perlin_noise(point(-7.0, 5.0, 11.0)), 0.0);
+    }
+
+    #[test]
+    fn noise_is_deterministic_and_bounded() {
+        let mut non_zero = false;
+        for i in 0..1000 {
+            let p = point(i as f64 * 0.173, i as f64 * 0.071 - 3.0, i as f64 * -0.029);
+            let n = perlin_noise(p);
+            assert_eq!(n, perlin_noise(p));
+            assert!((-1.5..=1.5).contains(&n));
+            non_zero |= n.abs() > 0.1;
+        }
+        assert!(non_zero);
+    }
+
+    #[test]
+    fn noise_is_smooth() {
+        let p = point(0.3, 1.7, -2.2);
+        let q = p + crate::vector(1e-4, 1e-4, 1e-4);
+        assert!((perlin_noise(p) - perlin_noise(q)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fractal_noise_with_one_octave_is_perlin_noise() {
+        let p = point(0.3, 1.7, -2.2);
+        assert_eq!(fractal_noise(p, 1), perlin_noise(p));
+        assert_ne!(fractal_noise(p, 4), perlin_noise(p));
+    }
+}
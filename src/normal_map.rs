@@ -0,0 +1,236 @@
+use crate::utils::any_perpendicular;
+use crate::Tuple;
+use crate::UVPattern;
+
+// Tangent of a vertex, points in the direction of increasing `u` along the surface
+// `handedness` is 1 or -1 and gives the direction of increasing `v` as `handedness * normal x tangent`
+// It is -1 when the texture is mirrored on the surface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tangent {
+    pub vector: Tuple,
+    pub handedness: f64,
+}
+
+// Compute the tangent of every vertex of a triangle mesh from its positions, normals and texture coordinates
+// Tangents of all the triangles sharing a vertex are added, then made perpendicular to the vertex normal
+//
+// * `positions`: position of every vertex
+// * `normals`: unit normal of every vertex
+// * `uvs`: texture coordinates (u, v) of every vertex
+// * `triangles`: indices of the 3 vertices of every triangle
+pub fn vertex_tangents(
+    positions: &[Tuple],
+    normals: &[Tuple],
+    uvs: &[(f64, f64)],
+    triangles: &[[usize; 3]],
+) -> Vec<Tangent> {
+    let zero = Tuple::new(0.0, 0.0, 0.0, 0.0);
+    let mut tangents = vec![zero; positions.len()];
+    let mut bitangents = vec![zero; positions.len()];
+
+    for &[i0, i1, i2] in triangles {
+        let e1 = positions[i1] - positions[i0];
+        let e2 = positions[i2] - positions[i0];
+        let (du1, dv1) = (uvs[i1].0 - uvs[i0].0, uvs[i1].1 - uvs[i0].1);
+        let (du2, dv2) = (uvs[i2].0 - uvs[i0].0, uvs[i2].1 - uvs[i0].1);
+        let det = du1 * dv2 - du2 * dv1;
+        if det == 0.0 {
+            // Texture coordinates are degenerate, the triangle can't tell the direction of `u`
+            continue;
+        }
+        // Solve e1 = du1 * T + dv1 * B and e2 = du2 * T + dv2 * B
+        // Not normalized, so that bigger triangles have more weight
+        let r = 1.0 / det;
+        let tangent = (e1 * dv2 - e2 * dv1) * r;
+        let bitangent = (e2 * du1 - e1 * du2) * r;
+        for i in [i0, i1, i2] {
+            tangents[i] = tangents[i] + tangent;
+            bitangents[i] = bitangents[i] + bitangent;
+        }
+    }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(bitangents.iter()))
+        .map(|(&n, (&t, &b))| {
+            // Gram-Schmidt to make the tangent perpendicular to the normal
+            let mut vector = t - n * n.dot(&t);
+            if vector.magnitude() == 0.0 {
+                vector = any_perpendicular(n);
+            }
+            let vector = vector.normalize();
+            let handedness = if n.cross(&vector).dot(&b) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            Tangent { vector, handedness }
+        })
+        .collect()
+}
+
+// RGB texture which stores normals in the tangent space of the surface, the usual blue-ish normal maps
+// Red is along the tangent, green is along the bitangent and blue is along the normal, each mapped from
+// -1..1 to 0..1. So, (0.5, 0.5, 1.0) leaves the normal unchanged
+#[derive(Debug)]
+pub struct NormalMap {
+    pub uv_pattern: Box<dyn UVPattern>,
+    pub strength: f64,    // 1.0 uses the map as is, 0.0 ignores it
+    pub flip_green: bool, // For maps which store the bitangent pointing downwards (DirectX convention)
+}
+
+impl NormalMap {
+    pub fn new(uv_pattern: Box<dyn UVPattern>) -> Self {
+        Self {
+            uv_pattern,
+            strength: 1.0,
+            flip_green: false,
+        }
+    }
+
+    // Shading normal at the texture coordinates (u, v) of a surface with the unit `normal` and `tangent`
+    pub fn perturb_normal(&self, normal: Tuple, tangent: Tangent, u: f64, v: f64) -> Tuple {
+        let color = self.uv_pattern.uv_pattern_at(u, v);
+        let mut x = color.red * 2.0 - 1.0;
+        let mut y = color.green * 2.0 - 1.0;
+        let z = color.blue * 2.0 - 1.0;
+        if self.flip_green {
+            y = -y;
+        }
+        x *= self.strength;
+        y *= self.strength;
+
+        // Interpolated tangents may not be perpendicular to the normal anymore
+        let t = (tangent.vector - normal * normal.dot(&tangent.vector)).normalize();
+        let b = normal.cross(&t) * tangent.handedness;
+        (t * x + b * y + normal * z).normalize()
+    }
+}
+
+#[cfg(test)]
+mod normal_map_tests {
+    use super::*;
+    use crate::{point, vector, Canvas, Color, TextureFilter, UVImage};
+
+    fn solid_map(color: Color) -> NormalMap {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.fill(color);
+        NormalMap::new(Box::new(UVImage::new(canvas, TextureFilter::Nearest)))
+    }
+
+    // Positions, normals, uvs and triangles
+    type Mesh = (Vec<Tuple>, Vec<Tuple>, Vec<(f64, f64)>, Vec<[usize; 3]>);
+
+    // Unit square in the xy plane facing -z, with u along +x and v along +y
+    fn quad() -> Mesh {
+        let positions = vec![
+            point(0.0, 0.0, 0.0),
+            point(1.0, 0.0, 0.0),
+            point(1.0, 1.0, 0.0),
+            point(0.0, 1.0, 0.0),
+        ];
+        let normals = vec![vector(0.0, 0.0, -1.0); 4];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let triangles = vec![[0, 1, 2], [0, 2, 3]];
+        (positions, normals, uvs, triangles)
+    }
+
+    #[test]
+    fn tangents_follow_increasing_u() {
+        let (positions, normals, uvs, triangles) = quad();
+        let tangents = vertex_tangents(&positions, &normals, &uvs, &triangles);
+        assert_eq!(tangents.len(), 4);
+        for tangent in tangents {
+            assert_eq!(tangent.vector, vector(1.0, 0.0, 0.0));
+            // normal x tangent = (0, 0, -1) x (1, 0, 0) = (0, -1, 0), but v increases along +y
+            assert_eq!(tangent.handedness, -1.0);
+        }
+    }
+
+    #[test]
+    fn mirrored_texture_flips_handedness() {
+        let (positions, normals, _, triangles) = quad();
+        let uvs = vec![(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        let tangents = vertex_tangents(&positions, &normals, &uvs, &triangles);
+        for tangent in tangents {
+            assert_eq!(tangent.vector, vector(1.0, 0.0, 0.0));
+            assert_eq!(tangent.handedness, 1.0);
+        }
+    }
+
+    #[test]
+    fn tangent_is_perpendicular_to_vertex_normal() {
+        let (positions, _, uvs, triangles) = quad();
+        let tilted = vector(0.0, 0.6, -0.8);
+        let normals = vec![tilted; 4];
+        let tangents = vertex_tangents(&positions, &normals, &uvs, &triangles);
+        for tangent in tangents {
+            assert!(tangent.vector.dot(&tilted).abs() < 1e-9);
+            assert_eq!(tangent.vector, vector(1.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_still_give_a_tangent() {
+        let (positions, normals, _, triangles) = quad();
+        let uvs = vec![(0.5, 0.5); 4];
+        let tangents = vertex_tangents(&positions, &normals, &uvs, &triangles);
+        for tangent in tangents {
+            assert_eq!(tangent.vector.magnitude(), 1.0);
+            assert!(tangent.vector.dot(&normals[0]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_normal() {
+        let map = solid_map(Color::new(0.5, 0.5, 1.0));
+        let tangent = Tangent {
+            vector: vector(1.0, 0.0, 0.0),
+            handedness: 1.0,
+        };
+        let n = map.perturb_normal(vector(0.0, 0.0, -1.0), tangent, 0.3, 0.7);
+        assert_eq!(n, vector(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn normal_map_tilts_along_tangent_and_bitangent() {
+        let tangent = Tangent {
+            vector: vector(1.0, 0.0, 0.0),
+            handedness: 1.0,
+        };
+        let normal = vector(0.0, 1.0, 0.0);
+        // Bitangent is normal x tangent = (0, 0, -1)
+        let map = solid_map(Color::new(1.0, 0.5, 0.5));
+        assert_eq!(
+            map.perturb_normal(normal, tangent, 0.0, 0.0),
+            vector(1.0, 0.0, 0.0)
+        );
+        let map = solid_map(Color::new(0.5, 1.0, 0.5));
+        assert_eq!(
+            map.perturb_normal(normal, tangent, 0.0, 0.0),
+            vector(0.0, 0.0, -1.0)
+        );
+
+        let mut map = solid_map(Color::new(0.5, 1.0, 0.5));
+        map.flip_green = true;
+        assert_eq!(
+            map.perturb_normal(normal, tangent, 0.0, 0.0),
+            vector(0.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn normal_map_strength_scales_the_tilt() {
+        let tangent = Tangent {
+            vector: vector(1.0, 0.0, 0.0),
+            handedness: 1.0,
+        };
+        let normal = vector(0.0, 1.0, 0.0);
+        let mut map = solid_map(Color::new(1.0, 0.5, 1.0));
+        map.strength = 0.0;
+        assert_eq!(map.perturb_normal(normal, tangent, 0.0, 0.0), normal);
+        map.strength = 1.0;
+        let tilted = map.perturb_normal(normal, tangent, 0.0, 0.0);
+        assert_eq!(tilted, vector(1.0, 1.0, 0.0).normalize());
+    }
+}
@@ -0,0 +1,477 @@
+use crate::random::Rng;
+use crate::utils::any_perpendicular;
+use crate::utils::EPSILON;
+use crate::Color;
+use crate::Computation;
+use crate::Ray;
+use crate::Shape;
+use crate::Tuple;
+use crate::World;
+use std::f64::consts::PI;
+
+// Unbiased Monte Carlo path tracing, an alternative to the Whitted style `World::color_at`
+// Follows the light bouncing between the objects, so it gives indirect light and color bleeding
+// that `ambient` only fakes. `ambient` of the materials is ignored, `emissive` makes objects act as lights
+//
+// At every hit:
+// * mirrors and glass (`reflective` and `transparency` of the material) send the path on in a single direction
+// * other surfaces sample the point light, the environment light and the emissive shapes directly
+//   (next event estimation), add the caustics of `World::caustics`, and continue the path in a random
+//   direction picked with a cosine weighted distribution
+// * emissive shapes are found both by sampling them and by the paths bouncing into them, the two are
+//   weighted with the power heuristic (multiple importance sampling), so that neither is counted twice
+// * emissive shapes without a `Shape::bounding_sphere`, like planes, can't be sampled and are only
+//   found by the paths
+// * after `min_bounces`, dark paths are stopped at random (Russian roulette), and the surviving paths
+//   are brightened to make up for it, so the result stays unbiased
+//
+// The background is only seen directly or through mirrors and glass,
+// use `World::environment_light` to light the scene with it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathTracer {
+    pub samples_per_pixel: usize, // Paths averaged for each pixel, noise goes down with the square root of it
+    pub min_bounces: usize,       // Bounces before Russian roulette can stop a path
+    pub seed: u64,                // Same seed gives the same image
+}
+
+impl PathTracer {
+    pub fn new(samples_per_pixel: usize) -> Self {
+        Self {
+            samples_per_pixel,
+            min_bounces: 3,
+            seed: 0,
+        }
+    }
+
+    // Estimate of the light arriving along the ray, one path per call
+    pub fn color_at(&self, world: &World, ray: &Ray, rng: &mut Rng) -> Color {
+        let mut total = Color::black();
+        let mut throughput = Color::white(); // How much of the light at the current hit reaches the camera
+        let mut ray = *ray;
+        let mut bounce = 0;
+        let mut diffused = false; // Whether the path has been diffused, after which the background is not seen
+        let mut diffused_at = None; // Point and pdf of the direction of the last bounce, when it was diffuse
+        loop {
+            let xs = world.intersect_world(ray);
+            let Some(hit) = xs.hit() else {
+                if !diffused {
+                    total = total + throughput * world.background.color_at(ray.direction);
+                }
+                return total;
+            };
+            let comps = hit.prepare_computations(&ray, &xs);
+            let emissive = comps.object.get_material().emissive;
+            let weight = match diffused_at {
+                Some((point, bsdf_pdf)) => emission_weight(&comps, point, bsdf_pdf),
+                None => 1.0,
+            };
+            total = total + throughput * emissive * weight;
+
+            if let Some(next) = scatter_specular(&comps, rng) {
+                ray = next;
+                diffused_at = None;
+            } else {
+                total = total + throughput * self.direct_light(world, &comps, rng);
+                if let Some(caustics) = &world.caustics {
+                    total = total + throughput * caustics.radiance(&comps);
+                }
+
+                // Continue the path, weighted by brdf * cos / pdf, where the pdf is cos / PI
+                let direction = cosine_weighted_direction(comps.normalv, rng);
+                let brdf = comps.object.get_material().brdf(
+                    comps.object,
+                    comps.over_point,
+                    direction,
+                    comps.eyev,
+                    comps.normalv,
+                    comps.time,
+                );
+                throughput = throughput * brdf * PI;
+                ray = Ray::new(comps.over_point, direction).with_time(comps.time);
+                diffused = true;
+                diffused_at = Some((comps.over_point, direction.dot(&comps.normalv) / PI));
+            }
+
+            bounce += 1;
+            if bounce >= self.min_bounces {
+                let survival = throughput
+                    .red
+                    .max(throughput.green)
+                    .max(throughput.blue)
+                    .min(0.95);
+                if survival <= 0.0 || rng.next_f64() >= survival {
+                    return total;
+                }
+                throughput = throughput * (1.0 / survival);
+            }
+        }
+    }
+
+    // Light arriving straight from the point light, the environment light and the emissive shapes,
+    // towards the eye
+    fn direct_light(&self, world: &World, comps: &Computation, rng: &mut Rng) -> Color {
+        let material = comps.object.get_material();
+        let mut total = Color::black();
+
+        let lightv = (world.light.position - comps.over_point).normalize();
+        if lightv.dot(&comps.normalv) > 0.0 && !world.is_shadowed_at(comps.over_point, comps.time) {
+            // Scaled by PI like `Material::lighting`, so that both integrators light a matte surface alike
+            total = total
+                + material.reflected_radiance(
+                    comps.object,
+                    world.light.intensity * PI,
+                    comps.over_point,
+                    lightv,
+                    comps.eyev,
+                    comps.normalv,
+                    comps.time,
+                );
+        }
+
+        // A single sample per hit, as every bounce of every path samples it again
+        if let Some(sample) = world
+            .environment_light
+            .as_ref()
+            .and_then(|light| light.sample(rng))
+        {
+            if sample.direction.dot(&comps.normalv) > 0.0
+                && !world.is_occluded(comps.over_point, sample.direction, comps.time)
+            {
+                let reflected = material.reflected_radiance(
+                    comps.object,
+                    sample.radiance,
+                    comps.over_point,
+                    sample.direction,
+                    comps.eyev,
+                    comps.normalv,
+                    comps.time,
+                );
+                total = total + reflected * (1.0 / sample.pdf);
+            }
+        }
+
+        // One sample of every emissive shape, seen from outside of it
+        for lamp in world
+            .objects
+            .iter()
+            .filter(|object| is_lamp(object.as_ref()))
+        {
+            let Some(cone) = LightCone::new(lamp.as_ref(), comps.over_point, comps.time) else {
+                continue;
+            };
+            let direction = cone.sample(rng);
+            let cos = direction.dot(&comps.normalv);
+            if cos <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray::new(comps.over_point, direction).with_time(comps.time);
+            // The sample may pass next to a stretched sphere, or be blocked by another object
+            let Some(hit) = world.intersect_world(shadow_ray).hit() else {
+                continue;
+            };
+            if !std::ptr::addr_eq(hit.object, lamp.as_ref()) {
+                continue;
+            }
+            let weight = power_heuristic(cone.pdf, cos / PI);
+            let reflected = material.reflected_radiance(
+                comps.object,
+                lamp.get_material().emissive,
+                comps.over_point,
+                direction,
+                comps.eyev,
+                comps.normalv,
+                comps.time,
+            );
+            total = total + reflected * (weight / cone.pdf);
+        }
+        total
+    }
+}
+
+fn is_lamp(object: &dyn Shape) -> bool {
+    object.get_material().emissive != Color::black()
+}
+
+// Directions from a point towards the bounding sphere of an emissive shape, picked uniformly
+// Directions in the cone which miss the shape itself are wasted, but the others are weighted right
+struct LightCone {
+    axis: Tuple,
+    cos_max: f64, // Cosine of the angle between the axis and the edge of the cone
+    pdf: f64,     // Probability density of every direction in the cone, per steradian
+}
+
+impl LightCone {
+    // `None` when the `lamp` is unbounded, or the `point` is inside its bounding sphere at the `time`,
+    // where it can't be sampled this way
+    fn new(lamp: &dyn Shape, point: Tuple, time: f64) -> Option<LightCone> {
+        let (center, radius) = lamp.bounding_sphere(time)?;
+        let to_center = center - point;
+        let distance = to_center.magnitude();
+        if distance <= radius * (1.0 + EPSILON) {
+            return None;
+        }
+        let sin_max = radius / distance;
+        let cos_max = (1.0 - sin_max * sin_max).sqrt();
+        Some(LightCone {
+            axis: to_center * (1.0 / distance),
+            cos_max,
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+        })
+    }
+
+    fn sample(&self, rng: &mut Rng) -> Tuple {
+        let cos = 1.0 - rng.next_f64() * (1.0 - self.cos_max);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let angle = 2.0 * PI * rng.next_f64();
+        let tangent = any_perpendicular(self.axis);
+        let bitangent = self.axis.cross(&tangent);
+        (tangent * (sin * angle.cos()) + bitangent * (sin * angle.sin()) + self.axis * cos)
+            .normalize()
+    }
+}
+
+// Weight of the emission of the hit of a path diffused from `point` with the probability density `bsdf_pdf`
+// When the hit is on an emissive shape, `PathTracer::direct_light` could have sampled it too
+fn emission_weight(comps: &Computation, point: Tuple, bsdf_pdf: f64) -> f64 {
+    if !is_lamp(comps.object) {
+        return 1.0;
+    }
+    match LightCone::new(comps.object, point, comps.time) {
+        Some(cone) => power_heuristic(bsdf_pdf, cone.pdf),
+        None => 1.0,
+    }
+}
+
+// Multiple importance sampling weight of a sample picked with the density `pdf`,
+// when it could also have been picked with the density `other_pdf`
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+// Picks what happens to the light at the surface, in proportion to `reflective` and `transparency` of its material
+// Returns the ray leaving a mirror or glass, or `None` when the light is diffused
+pub(crate) fn scatter_specular(comps: &Computation, rng: &mut Rng) -> Option<Ray> {
+    let material = comps.object.get_material();
+    let x = rng.next_f64();
+    if x < material.reflective {
+        return Some(Ray::new(comps.over_point, comps.reflectv).with_time(comps.time));
+    }
+    if x < material.reflective + material.transparency {
+        // Glass both reflects and refracts, in proportion to its Fresnel reflectance
+        if let Some(direction) = comps.refracted_direction() {
+            if rng.next_f64() >= comps.schlick() {
+                return Some(Ray::new(comps.under_point, direction).with_time(comps.time));
+            }
+        }
+        return Some(Ray::new(comps.over_point, comps.reflectv).with_time(comps.time));
+    }
+    None
+}
+
+// Random unit vector in the hemisphere around the unit `normal`, more likely near the normal
+// The probability density is cos(angle to the normal) / PI
+fn cosine_weighted_direction(normal: Tuple, rng: &mut Rng) -> Tuple {
+    // Uniform point on the unit disk, projected up to the hemisphere
+    let r = rng.next_f64().sqrt();
+    let angle = 2.0 * PI * rng.next_f64();
+    let tangent = any_perpendicular(normal);
+    let bitangent = normal.cross(&tangent);
+    let height = (1.0 - r * r).max(0.0).sqrt();
+    (tangent * (r * angle.cos()) + bitangent * (r * angle.sin()) + normal * height).normalize()
+}
+
+#[cfg(test)]
+mod path_tracer_tests {
+    use super::*;
+    use crate::{
+        point, vector, Background, Compare, EnvironmentLight, Light, Matrix, Plane, Sphere,
+    };
+
+    fn dark_world(objects: Vec<Sphere>) -> World {
+        World {
+            light: Light::new(point(0.0, 0.0, -10.0), Color::black()),
+            objects: objects
+                .into_iter()
+                .map(|s| Box::new(s) as Box<dyn Shape>)
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // Average of many paths along the same ray
+    fn average(world: &World, ray: &Ray, samples: usize) -> Color {
+        let tracer = PathTracer::new(samples);
+        let mut rng = Rng::new(tracer.seed);
+        let mut total = Color::black();
+        for _ in 0..samples {
+            total = total + tracer.color_at(world, ray, &mut rng);
+        }
+        total * (1.0 / samples as f64)
+    }
+
+    #[test]
+    fn cosine_weighted_directions_are_in_hemisphere() {
+        let normal = vector(0.0, 0.6, -0.8);
+        let mut rng = Rng::new(3);
+        let mut mean_cos = 0.0;
+        for _ in 0..10000 {
+            let d = cosine_weighted_direction(normal, &mut rng);
+            assert!(d.magnitude().eq(1.0));
+            assert!(d.dot(&normal) >= 0.0);
+            mean_cos += d.dot(&normal) / 10000.0;
+        }
+        // Mean of cos with density cos / PI is 2 / 3
+        assert!((mean_cos - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn missed_ray_sees_background() {
+        let mut w = dark_world(vec![]);
+        w.background = Background::Solid(Color::new(0.2, 0.4, 0.6));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let mut rng = Rng::new(0);
+        assert_eq!(
+            PathTracer::new(1).color_at(&w, &r, &mut rng),
+            Color::new(0.2, 0.4, 0.6)
+        );
+    }
+
+    #[test]
+    fn emissive_object_is_seen_without_ambient() {
+        let mut s = Sphere::default();
+        s.material.emissive = Color::new(1.0, 0.5, 0.0);
+        let w = dark_world(vec![s]);
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(average(&w, &r, 16), Color::new(1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn point_light_matches_lighting_without_ambient() {
+        // Nothing to bounce to, so every path gives exactly the direct light
+        let mut s = Sphere::default();
+        s.material.specular = 0.0;
+        let w = World {
+            light: Light::new(point(0.0, 0.0, -10.0), Color::white()),
+            objects: vec![Box::new(s)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(average(&w, &r, 8), Color::new(0.9, 0.9, 0.9));
+    }
+
+    #[test]
+    fn light_bounces_inside_closed_sphere() {
+        // Inside a sphere which emits E and reflects a fraction a, light adds up to E / (1 - a)
+        let mut s = Sphere::default();
+        s.set_transform(Matrix::get_scaling_matrix(10.0, 10.0, 10.0));
+        s.material.diffuse = 0.5;
+        s.material.specular = 0.0;
+        s.material.emissive = Color::new(0.5, 0.5, 0.5);
+        let w = dark_world(vec![s]);
+        let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
+        let c = average(&w, &r, 4000);
+        assert!((c.red - 1.0).abs() < 0.05, "{:?}", c);
+    }
+
+    #[test]
+    fn indirect_light_bleeds_color() {
+        // The white floor is only lit by the glowing red ball next to it
+        let mut floor = Sphere::default();
+        floor.set_transform(Matrix::get_translation_matrix(0.0, -1.0, 0.0));
+        floor.material.specular = 0.0;
+        let mut wall = Sphere::default();
+        wall.set_transform(Matrix::get_translation_matrix(2.0, 0.5, 0.0));
+        wall.material.color = Color::new(1.0, 0.0, 0.0);
+        wall.material.emissive = Color::new(1.0, 0.0, 0.0);
+        let w = dark_world(vec![floor, wall]);
+        let r = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        let c = average(&w, &r, 200);
+        assert!(c.red > 0.0);
+        assert_eq!(c.green, 0.0);
+    }
+
+    #[test]
+    fn environment_light_is_sampled_directly() {
+        let mut s = Sphere::default();
+        s.material.specular = 0.0;
+        s.material.diffuse = 1.0;
+        let mut w = dark_world(vec![s]);
+        w.environment_light = Some(EnvironmentLight::new(Background::Solid(Color::white()), 1));
+        // A white diffuse convex object under a uniform white sky reflects all of it
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let c = average(&w, &r, 2000);
+        assert!((c.red - 1.0).abs() < 0.05, "{:?}", c);
+    }
+
+    #[test]
+    fn small_lamp_is_sampled_directly() {
+        // A white floor under a small lamp of radiance L at an angle `a` reflects L * sin(a)^2
+        let mut floor = Plane::default();
+        floor.material.specular = 0.0;
+        floor.material.diffuse = 1.0;
+        let mut lamp = Sphere::default();
+        lamp.set_transform(
+            Matrix::get_translation_matrix(0.0, 2.0, 0.0)
+                * Matrix::get_scaling_matrix(0.5, 0.5, 0.5),
+        );
+        lamp.material.emissive = Color::white();
+        // Black, so that the light bouncing back to it doesn't add up
+        lamp.material.diffuse = 0.0;
+        lamp.material.specular = 0.0;
+        let mut w = dark_world(vec![lamp]);
+        w.objects.push(Box::new(floor));
+        let r = Ray::new(point(0.0, 1.0, -3.0), vector(0.0, -1.0, 3.0).normalize());
+        // Paths alone would find the lamp once in 16 bounces, much noisier
+        // Counting both the sampled lamp and the paths bouncing into it would double the light
+        let c = average(&w, &r, 200);
+        assert!((c.red - 0.0625).abs() < 0.003, "{:?}", c);
+    }
+
+    #[test]
+    fn mirror_reflects_emissive_object() {
+        let mut mirror = Sphere::default();
+        mirror.material.reflective = 1.0;
+        let mut lamp = Sphere::default();
+        lamp.set_transform(Matrix::get_translation_matrix(0.0, 0.0, -5.0));
+        lamp.material.emissive = Color::new(0.0, 1.0, 0.0);
+        // Black, so that the light bouncing back to it doesn't add up
+        lamp.material.diffuse = 0.0;
+        lamp.material.specular = 0.0;
+        let w = dark_world(vec![mirror, lamp]);
+        // Looking back at the lamp through the mirror
+        let r = Ray::new(point(0.0, 0.0, -2.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(average(&w, &r, 8), Color::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn glass_shows_what_is_behind() {
+        // Without bending or reflecting, the lamp is seen through the glass unchanged
+        let mut glass = Sphere::default();
+        glass.material.transparency = 1.0;
+        glass.material.refractive_index = 1.0;
+        let mut lamp = Sphere::default();
+        lamp.set_transform(Matrix::get_translation_matrix(0.0, 0.0, 5.0));
+        lamp.material.emissive = Color::new(0.0, 0.0, 1.0);
+        // Black, so that the light bouncing back to it doesn't add up
+        lamp.material.diffuse = 0.0;
+        lamp.material.specular = 0.0;
+        let w = dark_world(vec![glass, lamp]);
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(average(&w, &r, 8), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn background_is_seen_through_glass() {
+        let mut glass = Sphere::default();
+        glass.material.transparency = 1.0;
+        glass.material.refractive_index = 1.0;
+        let mut w = dark_world(vec![glass]);
+        w.background = Background::Solid(Color::new(0.2, 0.4, 0.6));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(average(&w, &r, 8), Color::new(0.2, 0.4, 0.6));
+    }
+}
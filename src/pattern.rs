@@ -0,0 +1,106 @@
+use crate::Color;
+use crate::Matrix;
+use crate::Shape;
+use crate::Tuple;
+use std::fmt::Debug;
+
+// A pattern decides the color of a material at every point of a shape
+// Patterns have their own transformation which is applied on top of the shape's transformation
+pub trait Pattern: Debug {
+    fn set_transform(&mut self, m: Matrix);
+    fn get_transform(&self) -> Matrix;
+
+    // Color of the pattern at the point `p` in the pattern space
+    fn local_pattern_at(&self, p: Tuple) -> Color;
+
+    // Color of the pattern at the world point `world_point` of the `object`, seen at the `time`
+    // The point is converted to the object space and then, to the pattern space
+    // So, the pattern moves with a moving object
+    fn pattern_at_shape(&self, object: &dyn Shape, world_point: Tuple, time: f64) -> Color {
+        let object_point = object.transform_at(time).inverse() * world_point;
+        let pattern_point = self.get_transform().inverse() * object_point;
+        self.local_pattern_at(pattern_point)
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug)]
+pub struct TestPattern {
+    transform: Matrix,
+}
+
+#[cfg(test)]
+impl Pattern for TestPattern {
+    fn set_transform(&mut self, m: Matrix) {
+        self.transform = m;
+    }
+
+    fn get_transform(&self) -> Matrix {
+        self.transform
+    }
+
+    // Return the point itself as a color so that the transformations can be tested
+    fn local_pattern_at(&self, p: Tuple) -> Color {
+        Color::new(p.x, p.y, p.z)
+    }
+}
+
+#[cfg(test)]
+impl Default for TestPattern {
+    fn default() -> Self {
+        Self {
+            transform: Matrix::I(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod pattern_tests {
+    use super::*;
+    use crate::point;
+    use crate::Sphere;
+
+    #[test]
+    fn default_pattern_transformation() {
+        let pattern = TestPattern::default();
+        assert_eq!(pattern.get_transform(), Matrix::I());
+    }
+
+    #[test]
+    fn assigning_transformation() {
+        let mut pattern = TestPattern::default();
+        pattern.set_transform(Matrix::get_translation_matrix(1.0, 2.0, 3.0));
+        assert_eq!(
+            pattern.get_transform(),
+            Matrix::get_translation_matrix(1.0, 2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn pattern_with_object_transformation() {
+        let mut shape = Sphere::default();
+        shape.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        let pattern = TestPattern::default();
+        let c = pattern.pattern_at_shape(&shape, point(2.0, 3.0, 4.0), 0.0);
+        assert_eq!(c, Color::new(1.0, 1.5, 2.0));
+    }
+
+    #[test]
+    fn pattern_with_pattern_transformation() {
+        let shape = Sphere::default();
+        let mut pattern = TestPattern::default();
+        pattern.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        let c = pattern.pattern_at_shape(&shape, point(2.0, 3.0, 4.0), 0.0);
+        assert_eq!(c, Color::new(1.0, 1.5, 2.0));
+    }
+
+    #[test]
+    fn pattern_with_object_and_pattern_transformation() {
+        let mut shape = Sphere::default();
+        shape.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        let mut pattern = TestPattern::default();
+        pattern.set_transform(Matrix::get_translation_matrix(0.5, 1.0, 1.5));
+        let c = pattern.pattern_at_shape(&shape, point(2.5, 3.0, 3.5), 0.0);
+        assert_eq!(c, Color::new(0.75, 0.5, 0.25));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::path_tracer::scatter_specular;
+use crate::random::Rng;
+use crate::vector;
+use crate::Color;
+use crate::Computation;
+use crate::Ray;
+use crate::Tuple;
+use crate::World;
+use std::f64::consts::PI;
+
+// Photons going around mirrors and glass more times than this are dropped (eg.- trapped by total internal reflection)
+const MAX_BOUNCES: usize = 16;
+
+// A packet of light which landed on a surface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Photon {
+    pub position: Tuple,
+    pub direction: Tuple, // Unit vector in the direction the photon was travelling
+    pub power: Color,
+}
+
+// Photons stored in a balanced kd-tree, to quickly find the photons close to a point
+// The tree is kept in the vector itself: the middle photon of every range splits the rest of the range
+// in two halves along its axis, the photons before it and the photons after it
+#[derive(Clone, Debug)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>, // Axis (0 for x, 1 for y, 2 for z) along which each photon splits its range
+    pub radius: f64,  // Photons closer than this to a point are used to estimate the light there
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, radius: f64) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self {
+            photons,
+            axes,
+            radius,
+        }
+    }
+
+    // Traces `emitted` photons from the light of the world, and keeps the photons which land on a diffuse surface
+    // after going through mirrors or glass. These are the caustics, light focused by curved mirrors and glass,
+    // which can't be found by tracing rays from the eye towards a point light
+    // A smaller `radius` gives sharper but noisier caustics, and needs more photons
+    pub fn caustics(world: &World, emitted: usize, radius: f64, seed: u64) -> Self {
+        let light = world.light;
+        let mut rng = Rng::new(seed);
+        let mut photons = Vec::new();
+        for _ in 0..emitted {
+            let mut ray = Ray::new(light.position, uniform_sphere_direction(&mut rng));
+            let mut power = None;
+            for bounce in 0..MAX_BOUNCES {
+                let xs = world.intersect_world(ray);
+                let Some(hit) = xs.hit() else {
+                    break;
+                };
+                // Lights don't get dimmer with distance in this ray tracer, `Material::lighting` gives the same
+                // light at every distance. Photons spread out with the square of the distance, so, they are made
+                // brighter by it to match. Each photon carries 1 / emitted of 4 * PI^2 * intensity at distance 1
+                let power = *power.get_or_insert_with(|| {
+                    light.intensity * (4.0 * PI * PI * hit.t * hit.t / emitted as f64)
+                });
+                let comps = hit.prepare_computations(&ray, &xs);
+                let material = comps.object.get_material();
+                if bounce > 0 && material.reflective + material.transparency < 1.0 {
+                    photons.push(Photon {
+                        position: comps.point,
+                        direction: ray.direction,
+                        power,
+                    });
+                }
+                match scatter_specular(&comps, &mut rng) {
+                    Some(next) => ray = next,
+                    None => break,
+                }
+            }
+        }
+        Self::new(photons, radius)
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    // Photons closer than `radius` to the point
+    pub fn photons_within(&self, point: Tuple, radius: f64) -> Vec<&Photon> {
+        let mut found = Vec::new();
+        self.search(0, self.photons.len(), point, radius * radius, &mut found);
+        found
+    }
+
+    // Visits the range of the tree from `start` to `end`, skipping the halves which are too far
+    fn search<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        point: Tuple,
+        radius2: f64,
+        found: &mut Vec<&'a Photon>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        let offset = point - photon.position;
+        if offset.dot(&offset) < radius2 {
+            found.push(photon);
+        }
+        let distance =
+            coordinate(point, self.axes[middle]) - coordinate(photon.position, self.axes[middle]);
+        let (near, far) = if distance < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search(near.0, near.1, point, radius2, found);
+        if distance * distance < radius2 {
+            self.search(far.0, far.1, point, radius2, found);
+        }
+    }
+
+    // Light reflected towards the eye by the photons around the hit, the photon power per area times the brdf
+    pub fn radiance(&self, comps: &Computation<'_>) -> Color {
+        let material = comps.object.get_material();
+        let mut total = Color::black();
+        for photon in self.photons_within(comps.point, self.radius) {
+            let brdf = material.brdf(
+                comps.object,
+                comps.point,
+                -photon.direction,
+                comps.eyev,
+                comps.normalv,
+                comps.time,
+            );
+            total = total + brdf * photon.power;
+        }
+        total * (1.0 / (PI * self.radius * self.radius))
+    }
+}
+
+// Sorts the photons into a kd-tree, splitting each range along the axis where the photons are most spread out
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [f64::NEG_INFINITY; 3];
+    for photon in photons.iter() {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(coordinate(photon.position, axis));
+            max[axis] = max[axis].max(coordinate(photon.position, axis));
+        }
+    }
+    let axis = (0..3)
+        .max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b])))
+        .unwrap();
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        coordinate(a.position, axis).total_cmp(&coordinate(b.position, axis))
+    });
+    axes[middle] = axis;
+    let (before, rest) = photons.split_at_mut(middle);
+    let (axes_before, axes_rest) = axes.split_at_mut(middle);
+    build(before, axes_before);
+    build(&mut rest[1..], &mut axes_rest[1..]);
+}
+
+fn coordinate(p: Tuple, axis: usize) -> f64 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+// Random unit vector, every direction equally likely
+fn uniform_sphere_direction(rng: &mut Rng) -> Tuple {
+    let z = 1.0 - 2.0 * rng.next_f64();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let angle = 2.0 * PI * rng.next_f64();
+    vector(r * angle.cos(), r * angle.sin(), z)
+}
+
+#[cfg(test)]
+mod photon_map_tests {
+    use super::*;
+    use crate::{point, Light, Matrix, Shape, Sphere};
+
+    fn photon_at(position: Tuple) -> Photon {
+        Photon {
+            position,
+            direction: vector(0.0, -1.0, 0.0),
+            power: Color::white(),
+        }
+    }
+
+    // Glass ball above a wide floor, lit from above
+    fn glass_world() -> World {
+        let mut floor = Sphere::default();
+        floor.set_transform(
+            Matrix::get_translation_matrix(0.0, -10.0, 0.0)
+                * Matrix::get_scaling_matrix(10.0, 10.0, 10.0),
+        );
+        floor.material.specular = 0.0;
+        let mut glass = Sphere::default();
+        glass.set_transform(Matrix::get_translation_matrix(0.0, 2.0, 0.0));
+        glass.material.transparency = 1.0;
+        glass.material.refractive_index = 1.5;
+        World {
+            light: Light::new(point(0.0, 5.0, 0.0), Color::white()),
+            objects: vec![Box::new(floor), Box::new(glass)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finds_photons_within_radius() {
+        let mut rng = Rng::new(9);
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| {
+                photon_at(point(
+                    rng.next_f64() * 4.0 - 2.0,
+                    rng.next_f64() * 4.0 - 2.0,
+                    rng.next_f64() * 4.0 - 2.0,
+                ))
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone(), 0.5);
+        assert_eq!(map.len(), 500);
+        for center in [
+            point(0.0, 0.0, 0.0),
+            point(1.5, -1.0, 0.3),
+            point(5.0, 5.0, 5.0),
+        ] {
+            let mut expected: Vec<Photon> = photons
+                .iter()
+                .filter(|p| (p.position - center).magnitude() < 0.5)
+                .cloned()
+                .collect();
+            let mut found: Vec<Photon> = map
+                .photons_within(center, 0.5)
+                .into_iter()
+                .cloned()
+                .collect();
+            let by_x = |a: &Photon, b: &Photon| a.position.x.total_cmp(&b.position.x);
+            expected.sort_by(by_x);
+            found.sort_by(by_x);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn empty_map_gives_no_light() {
+        let map = PhotonMap::new(Vec::new(), 0.1);
+        assert!(map.is_empty());
+        assert!(map.photons_within(point(0.0, 0.0, 0.0), 1.0).is_empty());
+    }
+
+    #[test]
+    fn no_caustics_without_mirrors_or_glass() {
+        let mut w = glass_world();
+        w.objects[1].get_material_mut().transparency = 0.0;
+        assert!(PhotonMap::caustics(&w, 2000, 0.1, 0).is_empty());
+    }
+
+    #[test]
+    fn glass_focuses_light_on_floor() {
+        let w = glass_world();
+        let map = PhotonMap::caustics(&w, 20000, 0.1, 0);
+        assert!(!map.is_empty());
+        let floor_hit = |x: f64| {
+            let r = Ray::new(
+                point(x, 1.0, -3.0),
+                (point(x, 0.0, 0.0) - point(x, 1.0, -3.0)).normalize(),
+            );
+            let xs = w.intersect_world(r);
+            let hit = xs.hit().unwrap();
+            hit.prepare_computations(&r, &xs)
+        };
+        // Right under the ball, the focused light is brighter than the light without the ball, 0.9 of diffuse
+        let under_ball = map.radiance(&floor_hit(0.0));
+        assert!(under_ball.red > 0.9, "{:?}", under_ball);
+        // Out of the shadow of the ball, there are no caustics
+        assert_eq!(map.radiance(&floor_hit(3.0)), Color::black());
+    }
+
+    #[test]
+    fn caustics_light_shadow_of_glass() {
+        let mut w = glass_world();
+        let r = Ray::new(point(0.0, 1.0, -3.0), vector(0.0, -1.0, 3.0).normalize());
+        let shadowed = w.color_at(&r);
+        w.caustics = Some(PhotonMap::caustics(&w, 20000, 0.1, 0));
+        assert!(w.color_at(&r).red > shadowed.red + 0.5);
+    }
+}
@@ -1,44 +1,70 @@
+use crate::utils::EPSILON;
 use crate::vector;
 use crate::Intersection;
 use crate::Intersections;
+use crate::Material;
 use crate::Matrix;
+use crate::Motion;
 use crate::Ray;
 use crate::Shape;
 use crate::Tuple;
-use std::f64::EPSILON;
 
 // Default plane is in xz, passing through the origin
+#[derive(Clone, Debug, PartialEq)]
 pub struct Plane {
-    transform: Matrix, // Transformation applied to the plane
+    transform: Matrix,      // Transformation applied to the plane
+    pub material: Material, // Material of the plane
+    motion: Option<Motion>, // Overrides `transform` for a moving plane
 }
 
 impl Plane {
     pub fn new(transform: Matrix) -> Self {
-        Self { transform }
+        Self {
+            transform,
+            material: Material::default(),
+            motion: None,
+        }
     }
 }
 
 impl Shape for Plane {
     fn set_transform(&mut self, t: Matrix) {
         self.transform = t;
+        self.motion = None;
     }
 
     fn get_transform(&self) -> Matrix {
         self.transform
     }
 
-    fn local_intersect(&self, local_ray: Ray) -> Intersections {
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn set_motion(&mut self, motion: Motion) {
+        self.motion = Some(motion);
+    }
+
+    fn get_motion(&self) -> Option<&Motion> {
+        self.motion.as_ref()
+    }
+
+    fn local_intersect(&self, local_ray: Ray) -> Intersections<'_> {
         let mut intersections = Intersections::default();
         if local_ray.direction.y.abs() < EPSILON {
             return intersections;
         }
 
         let t = -local_ray.origin.y / local_ray.direction.y;
-        intersections.push(Intersection::new(t, *self));
+        intersections.push(Intersection::new(t, self));
         intersections
     }
 
-    fn local_normal_at(&self, p: Tuple) -> Tuple {
+    fn local_normal_at(&self, _p: Tuple) -> Tuple {
         vector(0.0, 1.0, 0.0)
     }
 }
@@ -53,6 +79,7 @@ impl Default for Plane {
 mod plane_tests {
     use super::*;
     use crate::point;
+    use std::f64::consts::FRAC_PI_2;
 
     #[test]
     fn normal_is_constant() {
@@ -73,4 +100,34 @@ mod plane_tests {
         let xs = p.local_intersect(r);
         assert!(xs.is_empty())
     }
+
+    #[test]
+    fn intersect_from_above_and_below() {
+        let p = Plane::default();
+        for (origin, direction) in [
+            (point(0.0, 1.0, 0.0), vector(0.0, -1.0, 0.0)),
+            (point(0.0, -1.0, 0.0), vector(0.0, 1.0, 0.0)),
+        ] {
+            let xs = p.local_intersect(Ray::new(origin, direction));
+            assert_eq!(xs.len(), 1);
+            assert_eq!(xs[0].t, 1.0);
+            assert!(std::ptr::addr_eq(xs[0].object, &p));
+        }
+    }
+
+    #[test]
+    fn moving_plane() {
+        // Turns from the floor into a wall facing -z
+        let mut p = Plane::default();
+        p.set_motion(Motion::new(Matrix::I(), Matrix::get_rotation_x_matrix(FRAC_PI_2)).unwrap());
+        let r = Ray::new(point(0.0, 2.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert!(p.intersect(r).is_empty());
+        let xs = p.intersect(r.with_time(1.0));
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 5.0);
+        assert_eq!(
+            p.normal_at_time(point(0.0, 2.0, 0.0), 1.0),
+            vector(0.0, 0.0, 1.0)
+        );
+    }
 }
@@ -0,0 +1,860 @@
+use crate::utils::{invalid_data, invalid_input, save_with};
+use crate::zlib::{zlib_deflate, zlib_inflate, zlib_store, MAX_DEFLATE_RATIO};
+use crate::Canvas;
+use crate::Color;
+use crate::ToneMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Widths and heights of a PNG go from 1 to 2^31 - 1
+const MAX_SIZE: usize = 0x7FFF_FFFF;
+
+// The compressed data is split in IDAT chunks of at most this many bytes
+const MAX_IDAT_LENGTH: usize = 1 << 16;
+
+// Bits per color value
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen, // Keeps smooth gradients from banding, eg.- for further editing
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Store,   // No compression, the fastest to write
+    Deflate, // LZ77 with the fixed Huffman codes of deflate
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PngOptions<'a> {
+    pub bit_depth: BitDepth,
+    // Opacity of each pixel, row by row like `Canvas::data`, from 0.0 (transparent) to 1.0 (opaque)
+    // Without it, the image has no alpha channel
+    pub alpha: Option<&'a [f64]>,
+    pub compression: Compression,
+    pub tone_map: ToneMap, // Applied to the colors before they are clipped, not to the alpha
+}
+
+impl Default for PngOptions<'_> {
+    fn default() -> Self {
+        Self {
+            bit_depth: BitDepth::Eight,
+            alpha: None,
+            compression: Compression::Deflate,
+            tone_map: ToneMap::default(),
+        }
+    }
+}
+
+impl Canvas {
+    // Canvas as a PNG file, with color values clipped between 0.0 and 1.0 like the ppm, after `PngOptions::tone_map`
+    // Panics on an empty canvas, an alpha of the wrong size or a tone map which doesn't `check`,
+    // which `write_png_to` returns as errors
+    pub fn get_png(&self, options: PngOptions) -> Vec<u8> {
+        let mut png = Vec::new();
+        self.write_png_to(&mut png, options)
+            .expect("The canvas and the options should make a valid png");
+        png
+    }
+
+    pub fn write_png_to<W: Write>(&self, writer: &mut W, options: PngOptions) -> io::Result<()> {
+        options.tone_map.check()?;
+        if let Some(alpha) = options.alpha {
+            if alpha.len() != self.data.len() {
+                return Err(invalid_input("alpha should have a value for each pixel"));
+            }
+        }
+        if !(1..=MAX_SIZE).contains(&self.width) || !(1..=MAX_SIZE).contains(&self.height) {
+            return Err(invalid_data("png sizes go from 1 to 2^31 - 1"));
+        }
+        let (width, height) = (self.width as u32, self.height as u32);
+        let depth = match options.bit_depth {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        };
+        // Color type 2 is RGB, 6 is RGB with alpha
+        let color_type = if options.alpha.is_some() { 6 } else { 2 };
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // Bit depth, color type, compression method, filter method, no interlacing
+        header.extend_from_slice(&[depth, color_type, 0, 0, 0]);
+
+        let scanlines = self.png_scanlines(&options);
+        let data = match options.compression {
+            Compression::Store => zlib_store(&scanlines),
+            Compression::Deflate => zlib_deflate(&scanlines),
+        };
+
+        writer.write_all(&SIGNATURE)?;
+        write_chunk(writer, b"IHDR", &header)?;
+        for idat in data.chunks(MAX_IDAT_LENGTH) {
+            write_chunk(writer, b"IDAT", idat)?;
+        }
+        write_chunk(writer, b"IEND", &[])
+    }
+
+    pub fn save_png(&self, file_path: &str, options: PngOptions) -> io::Result<()> {
+        options.tone_map.check()?;
+        save_with(file_path, |writer| self.write_png_to(writer, options))
+    }
+
+    // Read a PNG file from the `file_path` into a canvas, dropping its alpha
+    pub fn read_png(file_path: &str) -> io::Result<Canvas> {
+        Canvas::from_png(&fs::read(file_path)?)
+    }
+
+    // Convert png data to canvas, samples are scaled to 0.0..=1.0 as they are, without undoing their gamma
+    pub fn from_png(data: &[u8]) -> io::Result<Canvas> {
+        Ok(Canvas::from_png_with_alpha(data)?.0)
+    }
+
+    // Like `from_png`, with the opacity of each pixel, row by row, when the image has an alpha channel
+    // or a transparent color. Colors aren't premultiplied by it
+    // Supports every color type, bit depth and interlacing of the standard, ancillary chunks are skipped
+    pub fn from_png_with_alpha(data: &[u8]) -> io::Result<(Canvas, Option<Vec<f64>>)> {
+        if data.len() < 8 || data[..8] != SIGNATURE {
+            return Err(invalid_data("not a PNG file"));
+        }
+        let mut header = None;
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut transparency: Option<Vec<u8>> = None;
+        let mut compressed = Vec::new();
+        let mut i = 8;
+        loop {
+            let length = read_u32(data, i)? as usize;
+            let kind = data
+                .get(i + 4..i + 8)
+                .ok_or_else(|| invalid_data("chunk is cut short"))?;
+            let body = data
+                .get(i + 8..i + 8 + length)
+                .ok_or_else(|| invalid_data("chunk is cut short"))?;
+            let crc = read_u32(data, i + 8 + length)?;
+            if crc != crc32_update(crc32_update(0xFFFF_FFFF, kind), body) ^ 0xFFFF_FFFF {
+                return Err(invalid_data("chunk checksum doesn't match"));
+            }
+            match kind {
+                b"IHDR" => header = Some(PngHeader::parse(body)?),
+                b"PLTE" => palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                b"tRNS" => transparency = Some(body.to_vec()),
+                b"IDAT" => compressed.extend_from_slice(body),
+                b"IEND" => break,
+                // Unknown chunks which the image can't be shown without
+                _ if kind[0] & 0x20 == 0 => return Err(invalid_data("unsupported critical chunk")),
+                _ => {}
+            }
+            i += 12 + length;
+        }
+        let header = header.ok_or_else(|| invalid_data("IHDR chunk is missing"))?;
+        if header.color_type == 3 && palette.is_empty() {
+            return Err(invalid_data("PLTE chunk is missing"));
+        }
+        // Sizes are checked before anything of the size of the image is allocated
+        let filtered_length = header
+            .filtered_length()
+            .ok_or_else(|| invalid_data("image is too large"))?;
+        if filtered_length > compressed.len().saturating_mul(MAX_DEFLATE_RATIO) {
+            return Err(invalid_data(
+                "image data is too short for the size of the image",
+            ));
+        }
+        let raw = zlib_inflate(&compressed, filtered_length)?;
+        let samples = header.unfilter(&raw)?;
+
+        let (width, height) = (header.width, header.height);
+        let mut canvas = Canvas::new(width, height);
+        let has_alpha = matches!(header.color_type, 4 | 6) || transparency.is_some();
+        let mut alpha = vec![1.0; if has_alpha { width * height } else { 0 }];
+        let max = ((1u32 << header.bit_depth) - 1) as f64;
+        let channels = header.channels();
+        for (pixel, values) in samples.chunks_exact(channels).enumerate() {
+            let scale = |v: u16| v as f64 / max;
+            let (color, opacity) = match header.color_type {
+                0 => {
+                    let v = scale(values[0]);
+                    let key = transparency.as_ref().map(|t| color_key(t, 0));
+                    let opaque = key != Some(values[0]);
+                    (Color::new(v, v, v), if opaque { 1.0 } else { 0.0 })
+                }
+                2 => {
+                    let key = transparency
+                        .as_ref()
+                        .map(|t| [color_key(t, 0), color_key(t, 1), color_key(t, 2)]);
+                    let opaque = key.is_none_or(|key| key != values[..3]);
+                    (
+                        Color::new(scale(values[0]), scale(values[1]), scale(values[2])),
+                        if opaque { 1.0 } else { 0.0 },
+                    )
+                }
+                3 => {
+                    let index = values[0] as usize;
+                    let [r, g, b] = *palette
+                        .get(index)
+                        .ok_or_else(|| invalid_data("palette index is out of range"))?;
+                    let a = transparency
+                        .as_ref()
+                        .and_then(|t| t.get(index).copied())
+                        .unwrap_or(255);
+                    let byte = |v: u8| v as f64 / 255.0;
+                    (Color::new(byte(r), byte(g), byte(b)), byte(a))
+                }
+                4 => {
+                    let v = scale(values[0]);
+                    (Color::new(v, v, v), scale(values[1]))
+                }
+                _ => (
+                    Color::new(scale(values[0]), scale(values[1]), scale(values[2])),
+                    scale(values[3]),
+                ),
+            };
+            canvas.data[pixel] = color;
+            if has_alpha {
+                alpha[pixel] = opacity;
+            }
+        }
+        Ok((canvas, has_alpha.then_some(alpha)))
+    }
+
+    // Rows of samples, each starting with the filter type byte
+    // Stored rows aren't filtered, compressed rows take the filter giving the smallest differences
+    fn png_scanlines(&self, options: &PngOptions) -> Vec<u8> {
+        let wide = options.bit_depth == BitDepth::Sixteen;
+        let channels = if options.alpha.is_some() { 4 } else { 3 };
+        let bytes_per_pixel = channels * if wide { 2 } else { 1 };
+        let row_length = self.width * bytes_per_pixel;
+
+        let mut scanlines = Vec::with_capacity((row_length + 1) * self.height);
+        let mut previous = vec![0; row_length];
+        let mut row = Vec::with_capacity(row_length);
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                let Color { red, green, blue } = options.tone_map.apply_color(self.pixel_at(x, y));
+                let alpha = options.alpha.map(|alpha| alpha[y * self.width + x]);
+                for value in [red, green, blue].into_iter().chain(alpha) {
+                    let value = value.clamp(0.0, 1.0);
+                    if wide {
+                        let sample = (value * 65535.0).round() as u16;
+                        row.extend_from_slice(&sample.to_be_bytes());
+                    } else {
+                        row.push((value * 255.0).round() as u8);
+                    }
+                }
+            }
+            let filter = match options.compression {
+                Compression::Store => Filter::None,
+                Compression::Deflate => best_filter(&row, &previous, bytes_per_pixel),
+            };
+            scanlines.push(filter as u8);
+            let start = scanlines.len();
+            scanlines.extend_from_slice(&row);
+            filter.apply(&mut scanlines[start..], &row, &previous, bytes_per_pixel);
+            std::mem::swap(&mut previous, &mut row);
+        }
+        scanlines
+    }
+}
+
+// Ways a PNG row can be stored as differences to the bytes before it, which compress better
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Filter {
+    None = 0,
+    Sub = 1,     // Difference to the same byte of the pixel on the left
+    Up = 2,      // Difference to the same byte of the row above
+    Average = 3, // Difference to the average of the left and the above
+    Paeth = 4, // Difference to the closest of the left, the above and the above left to left + above - above left
+}
+
+impl Filter {
+    fn from_byte(byte: u8) -> Option<Filter> {
+        match byte {
+            0 => Some(Filter::None),
+            1 => Some(Filter::Sub),
+            2 => Some(Filter::Up),
+            3 => Some(Filter::Average),
+            4 => Some(Filter::Paeth),
+            _ => None,
+        }
+    }
+
+    // Byte of the row and its neighbours, the left, the above and the above left, which the filter predicts from
+    fn predict(self, left: u8, above: u8, above_left: u8) -> u8 {
+        match self {
+            Filter::None => 0,
+            Filter::Sub => left,
+            Filter::Up => above,
+            Filter::Average => ((left as u16 + above as u16) / 2) as u8,
+            Filter::Paeth => paeth(left, above, above_left),
+        }
+    }
+
+    // Writes the filtered `row` into `out`, `previous` is the unfiltered row above
+    fn apply(self, out: &mut [u8], row: &[u8], previous: &[u8], bytes_per_pixel: usize) {
+        for i in 0..row.len() {
+            let left = if i >= bytes_per_pixel {
+                row[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let above_left = if i >= bytes_per_pixel {
+                previous[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            out[i] = row[i].wrapping_sub(self.predict(left, previous[i], above_left));
+        }
+    }
+
+    // Undoes the filter of `row` in place, `previous` is the already unfiltered row above
+    fn unapply(self, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) {
+        for i in 0..row.len() {
+            let left = if i >= bytes_per_pixel {
+                row[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let above_left = if i >= bytes_per_pixel {
+                previous[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            row[i] = row[i].wrapping_add(self.predict(left, previous[i], above_left));
+        }
+    }
+}
+
+fn paeth(left: u8, above: u8, above_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - above_left as i16;
+    let to_left = (estimate - left as i16).abs();
+    let to_above = (estimate - above as i16).abs();
+    let to_above_left = (estimate - above_left as i16).abs();
+    if to_left <= to_above && to_left <= to_above_left {
+        left
+    } else if to_above <= to_above_left {
+        above
+    } else {
+        above_left
+    }
+}
+
+// Adam7 interlacing passes, as (first column, first row, column step, row step)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn parse(body: &[u8]) -> io::Result<PngHeader> {
+        if body.len() != 13 {
+            return Err(invalid_data("IHDR chunk has the wrong size"));
+        }
+        let header = PngHeader {
+            width: read_u32(body, 0)? as usize,
+            height: read_u32(body, 4)? as usize,
+            bit_depth: body[8],
+            color_type: body[9],
+            interlaced: body[12] == 1,
+        };
+        let valid_depth = match header.color_type {
+            0 => [1, 2, 4, 8, 16].contains(&header.bit_depth),
+            3 => [1, 2, 4, 8].contains(&header.bit_depth),
+            2 | 4 | 6 => [8, 16].contains(&header.bit_depth),
+            _ => false,
+        };
+        if !valid_depth || body[10] != 0 || body[11] != 0 || body[12] > 1 {
+            return Err(invalid_data("unsupported image type"));
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid_data("image is empty"));
+        }
+        Ok(header)
+    }
+
+    // Samples of each pixel
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // Passes of the image which have pixels, as (first column, first row, column step, row step,
+    // width, height), the whole image when it isn't interlaced
+    fn passes(&self) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+        let passes = if self.interlaced {
+            ADAM7.to_vec()
+        } else {
+            vec![(0, 0, 1, 1)]
+        };
+        passes
+            .into_iter()
+            .map(|(x0, y0, dx, dy)| {
+                let pass_width = self.width.saturating_sub(x0).div_ceil(dx);
+                let pass_height = self.height.saturating_sub(y0).div_ceil(dy);
+                (x0, y0, dx, dy, pass_width, pass_height)
+            })
+            .filter(|&(.., pass_width, pass_height)| pass_width > 0 && pass_height > 0)
+            .collect()
+    }
+
+    // Bytes of the decompressed image data, every row of every pass starts with its filter type byte
+    // None when it doesn't fit in a usize
+    fn filtered_length(&self) -> Option<usize> {
+        self.passes()
+            .iter()
+            .try_fold(0usize, |total, &(.., pass_width, pass_height)| {
+                let row_length = pass_width.checked_mul(self.bits_per_pixel())?.div_ceil(8);
+                total.checked_add((row_length + 1).checked_mul(pass_height)?)
+            })
+    }
+
+    // Undoes the filters of the decompressed rows, and of the passes of interlaced images,
+    // and returns the samples of all the pixels, row by row
+    fn unfilter(&self, raw: &[u8]) -> io::Result<Vec<u16>> {
+        let channels = self.channels();
+        let bits_per_pixel = self.bits_per_pixel();
+        // Filters work on whole bytes, pixels of less than a byte use the byte before
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+        if self
+            .filtered_length()
+            .is_none_or(|length| raw.len() < length)
+        {
+            return Err(invalid_data("image data is cut short"));
+        }
+        let sample_count = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .ok_or_else(|| invalid_data("image is too large"))?;
+        let mut samples = vec![0; sample_count];
+
+        let mut position = 0;
+        for (x0, y0, dx, dy, pass_width, pass_height) in self.passes() {
+            let row_length = (pass_width * bits_per_pixel).div_ceil(8);
+            let mut previous = vec![0; row_length];
+            for pass_y in 0..pass_height {
+                let line = raw
+                    .get(position..position + 1 + row_length)
+                    .ok_or_else(|| invalid_data("image data is cut short"))?;
+                position += 1 + row_length;
+                let filter =
+                    Filter::from_byte(line[0]).ok_or_else(|| invalid_data("unknown filter"))?;
+                let mut row = line[1..].to_vec();
+                filter.unapply(&mut row, &previous, bytes_per_pixel);
+
+                let y = y0 + pass_y * dy;
+                for pass_x in 0..pass_width {
+                    let x = x0 + pass_x * dx;
+                    for c in 0..channels {
+                        samples[(y * self.width + x) * channels + c] =
+                            sample(&row, pass_x * channels + c, self.bit_depth);
+                    }
+                }
+                previous = row;
+            }
+        }
+        Ok(samples)
+    }
+}
+
+// Sample number `index` of a row, packed from the most significant bit when smaller than a byte
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            ((row[bit / 8] >> shift) & ((1 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+// Sample of the transparent color of gray or RGB images, stored in 16 bits whatever the bit depth
+fn color_key(transparency: &[u8], index: usize) -> u16 {
+    match transparency.get(index * 2..index * 2 + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => u16::MAX,
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid_data("PNG file is cut short"))
+}
+
+// Filter with the smallest sum of differences, taken as signed bytes, the usual guess of what compresses best
+fn best_filter(row: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Filter {
+    let mut out = vec![0; row.len()];
+    let mut best = (Filter::None, u64::MAX);
+    for filter in [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ] {
+        filter.apply(&mut out, row, previous, bytes_per_pixel);
+        let cost = out.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+        if cost < best.1 {
+            best = (filter, cost);
+        }
+    }
+    best.0
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32_update(crc32_update(0xFFFF_FFFF, kind), data) ^ 0xFFFF_FFFF;
+    writer.write_all(&crc.to_be_bytes())
+}
+
+// CRC-32 of PNG chunks, start with 0xFFFFFFFF and flip all the bits at the end
+pub(crate) fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod png_tests {
+    use super::*;
+    use crate::zlib::adler32;
+    use crate::{Encoding, ToneMapping};
+
+    fn gradient(width: usize, height: usize) -> Canvas {
+        let mut c = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+                c.write_pixel(x, y, Color::new(u, v, 1.0 - u * v));
+            }
+        }
+        c
+    }
+
+    // Chunks of a PNG file, as (type, data)
+    fn png_chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut i = 8;
+        while i < png.len() {
+            let length = u32::from_be_bytes(png[i..i + 4].try_into().unwrap()) as usize;
+            let kind = String::from_utf8(png[i + 4..i + 8].to_vec()).unwrap();
+            let data = png[i + 8..i + 8 + length].to_vec();
+            let crc = u32::from_be_bytes(png[i + 8 + length..i + 12 + length].try_into().unwrap());
+            assert_eq!(
+                crc,
+                crc32_update(0xFFFF_FFFF, &png[i + 4..i + 8 + length]) ^ 0xFFFF_FFFF
+            );
+            chunks.push((kind, data));
+            i += 12 + length;
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(
+            crc32_update(0xFFFF_FFFF, b"IEND") ^ 0xFFFF_FFFF,
+            0xAE42_6082
+        );
+        assert_eq!(
+            crc32_update(0xFFFF_FFFF, b"123456789") ^ 0xFFFF_FFFF,
+            0xCBF4_3926
+        );
+    }
+
+    #[test]
+    fn png_chunks_and_header() {
+        let png = gradient(3, 2).get_png(PngOptions::default());
+        let chunks = png_chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+
+        let alpha = vec![1.0; 6];
+        let options = PngOptions {
+            bit_depth: BitDepth::Sixteen,
+            alpha: Some(&alpha),
+            ..Default::default()
+        };
+        let chunks = png_chunks(&gradient(3, 2).get_png(options));
+        assert_eq!(chunks[0].1[8..10], [16, 6]);
+    }
+
+    #[test]
+    fn stored_png_keeps_samples() {
+        let mut c = Canvas::new(2, 1);
+        c.write_pixel(0, 0, Color::new(1.5, 0.5, -1.0));
+        c.write_pixel(1, 0, Color::new(0.2, 0.4, 0.6));
+        let alpha = [0.0, 1.0];
+        let options = PngOptions {
+            alpha: Some(&alpha),
+            compression: Compression::Store,
+            ..Default::default()
+        };
+        let chunks = png_chunks(&c.get_png(options));
+        let scanlines = [0, 255, 128, 0, 0, 51, 102, 153, 255];
+        let mut expected = vec![0x78, 0x01, 1, 9, 0, 0xF6, 0xFF];
+        expected.extend_from_slice(&scanlines);
+        expected.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+        assert_eq!(chunks[1].1, expected);
+
+        let options = PngOptions {
+            bit_depth: BitDepth::Sixteen,
+            compression: Compression::Store,
+            ..Default::default()
+        };
+        let chunks = png_chunks(&c.get_png(options));
+        assert_eq!(chunks[1].1[7..14], [0, 255, 255, 128, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tone_map_is_applied_to_colors() {
+        let mut c = Canvas::new(1, 1);
+        c.fill(Color::new(0.18, 2.0, 0.0));
+        let alpha = [0.5];
+        let options = PngOptions {
+            alpha: Some(&alpha),
+            compression: Compression::Store,
+            tone_map: ToneMap::new(0.0, ToneMapping::Clip, Encoding::Srgb).unwrap(),
+            ..Default::default()
+        };
+        let chunks = png_chunks(&c.get_png(options));
+        assert_eq!(chunks[1].1[8..12], [118, 255, 0, 128]);
+    }
+
+    #[test]
+    fn alpha_of_wrong_size_is_an_error() {
+        let alpha = [1.0; 3];
+        let options = PngOptions {
+            alpha: Some(&alpha),
+            ..Default::default()
+        };
+        assert!(gradient(2, 2)
+            .write_png_to(&mut Vec::new(), options)
+            .is_err());
+    }
+
+    #[test]
+    fn empty_or_oversized_canvas_is_an_error() {
+        let options = PngOptions::default();
+        for (width, height) in [(0, 0), (3, 0), (0, 3), (MAX_SIZE + 1, 1)] {
+            // Only the sizes are checked, so the data can stay empty
+            let c = Canvas {
+                width,
+                height,
+                data: Vec::new(),
+            };
+            let error = c.write_png_to(&mut Vec::new(), options).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn big_images_take_several_data_chunks() {
+        let c = gradient(200, 200);
+        let options = PngOptions {
+            compression: Compression::Store,
+            ..Default::default()
+        };
+        let png = c.get_png(options);
+        let chunks = png_chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IDAT", "IEND"]);
+        assert_eq!(chunks[1].1.len(), MAX_IDAT_LENGTH);
+
+        let mut data = chunks[1].1.clone();
+        data.extend_from_slice(&chunks[2].1);
+        assert_eq!(data, zlib_store(&c.png_scanlines(&options)));
+    }
+
+    #[test]
+    fn deflate_compresses_smooth_images() {
+        let c = gradient(64, 64);
+        let stored = c.get_png(PngOptions {
+            compression: Compression::Store,
+            ..Default::default()
+        });
+        let compressed = c.get_png(PngOptions::default());
+        assert!(compressed.len() * 4 < stored.len());
+    }
+
+    #[test]
+    fn filtering_rows() {
+        let previous = [10, 20, 30, 200, 100, 0];
+        let row = [5, 250, 7, 9, 0, 255];
+        let filtered = |filter: Filter| {
+            let mut out = [0; 6];
+            filter.apply(&mut out, &row, &previous, 3);
+            out
+        };
+        assert_eq!(filtered(Filter::None), row);
+        assert_eq!(filtered(Filter::Sub), [5, 250, 7, 4, 6, 248]);
+        assert_eq!(filtered(Filter::Up), [251, 230, 233, 65, 156, 255]);
+        assert_eq!(filtered(Filter::Average), [0, 240, 248, 163, 81, 252]);
+        assert_eq!(paeth(5, 200, 10), 200);
+        assert_eq!(paeth(100, 20, 30), 100);
+    }
+
+    #[test]
+    fn unfiltering_rows() {
+        let previous = [10, 20, 30, 200, 100, 0];
+        let row = [5, 250, 7, 9, 0, 255];
+        for filter in [
+            Filter::None,
+            Filter::Sub,
+            Filter::Up,
+            Filter::Average,
+            Filter::Paeth,
+        ] {
+            let mut out = [0; 6];
+            filter.apply(&mut out, &row, &previous, 3);
+            filter.unapply(&mut out, &previous, 3);
+            assert_eq!(out, row);
+        }
+        assert_eq!(Filter::from_byte(4), Some(Filter::Paeth));
+        assert_eq!(Filter::from_byte(5), None);
+    }
+
+    #[test]
+    fn reading_written_png() {
+        let c = gradient(13, 7);
+        let alpha: Vec<f64> = (0..13 * 7).map(|i| i as f64 / 90.0).collect();
+        for compression in [Compression::Store, Compression::Deflate] {
+            for bit_depth in [BitDepth::Eight, BitDepth::Sixteen] {
+                let max = match bit_depth {
+                    BitDepth::Eight => 255.0,
+                    BitDepth::Sixteen => 65535.0,
+                };
+                let quantized = |v: f64| (v * max).round() / max;
+                let png = c.get_png(PngOptions {
+                    bit_depth,
+                    compression,
+                    ..Default::default()
+                });
+                let (read, read_alpha) = Canvas::from_png_with_alpha(&png).unwrap();
+                assert_eq!((read.width, read.height), (13, 7));
+                assert!(read_alpha.is_none());
+                for (a, b) in read.data.iter().zip(&c.data) {
+                    assert_eq!(a.red, quantized(b.red));
+                    assert_eq!(a.blue, quantized(b.blue));
+                }
+
+                let png = c.get_png(PngOptions {
+                    bit_depth,
+                    compression,
+                    alpha: Some(&alpha),
+                    ..Default::default()
+                });
+                let (read, read_alpha) = Canvas::from_png_with_alpha(&png).unwrap();
+                assert_eq!(
+                    read.pixel_at(12, 6).green,
+                    quantized(c.pixel_at(12, 6).green)
+                );
+                let read_alpha = read_alpha.unwrap();
+                assert_eq!(read_alpha[45], quantized(alpha[45]));
+            }
+        }
+    }
+
+    #[test]
+    fn reading_png_of_several_data_chunks() {
+        let c = gradient(200, 200);
+        let png = c.get_png(PngOptions {
+            compression: Compression::Store,
+            ..Default::default()
+        });
+        let read = Canvas::from_png(&png).unwrap();
+        let in_one_chunk = Canvas::from_png(&c.get_png(PngOptions::default())).unwrap();
+        assert_eq!(read.data, in_one_chunk.data);
+    }
+
+    #[test]
+    fn reading_interlaced_palette_png() {
+        // 5x5, 2 bit palette of red, green, blue and white, with blue half transparent and Adam7 interlacing
+        // Pixel (x, y) is the color number (x + 2y) % 4
+        let png = [
+            137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 5, 0, 0, 0, 5,
+            2, 3, 0, 0, 1, 135, 6, 254, 224, 0, 0, 0, 12, 80, 76, 84, 69, 255, 0, 0, 0, 255, 0, 0,
+            0, 255, 255, 255, 255, 251, 0, 96, 246, 0, 0, 0, 3, 116, 82, 78, 83, 255, 255, 128, 58,
+            114, 142, 97, 0, 0, 0, 22, 73, 68, 65, 84, 120, 218, 99, 96, 0, 131, 6, 32, 84, 96, 40,
+            0, 195, 141, 13, 64, 4, 0, 36, 223, 4, 211, 65, 94, 154, 156, 0, 0, 0, 0, 73, 69, 78,
+            68, 174, 66, 96, 130,
+        ];
+        let palette = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+        let (canvas, alpha) = Canvas::from_png_with_alpha(&png).unwrap();
+        let alpha = alpha.unwrap();
+        for y in 0..5 {
+            for x in 0..5 {
+                let index = (x + 2 * y) % 4;
+                assert_eq!(canvas.pixel_at(x, y), palette[index]);
+                let expected = if index == 2 { 128.0 / 255.0 } else { 1.0 };
+                assert_eq!(alpha[y * 5 + x], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn reading_broken_png() {
+        let png = gradient(4, 4).get_png(PngOptions::default());
+        assert!(Canvas::from_png(&png).is_ok());
+        assert!(Canvas::from_png(&png[1..]).is_err());
+        assert!(Canvas::from_png(&png[..png.len() - 20]).is_err());
+        let mut corrupted = png.clone();
+        corrupted[40] ^= 1;
+        assert!(Canvas::from_png(&corrupted).is_err());
+        // Image data can't be larger than what the header says
+        let resized = |width: u32, height: u32| {
+            let mut png = png.clone();
+            png[16..20].copy_from_slice(&width.to_be_bytes());
+            png[20..24].copy_from_slice(&height.to_be_bytes());
+            let crc = crc32_update(0xFFFF_FFFF, &png[12..29]) ^ 0xFFFF_FFFF;
+            png[29..33].copy_from_slice(&crc.to_be_bytes());
+            png
+        };
+        assert!(Canvas::from_png(&resized(4, 5)).is_err());
+        // Huge sizes are rejected before anything of their size is allocated
+        assert!(Canvas::from_png(&resized(65535, 65535)).is_err());
+        assert!(Canvas::from_png(&resized(0x7FFF_FFFF, 0x7FFF_FFFF)).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::Canvas;
+use crate::Color;
+use std::f64::consts::PI;
+
+// How pixels are blended when resizing a canvas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResizeFilter {
+    // Average of the pixels under each new pixel, nearest pixel when enlarging
+    Box,
+    // Linear interpolation, between the 2x2 nearest pixels when enlarging
+    Bilinear,
+    // Windowed sinc with 3 lobes, the sharpest, with a slight ringing around hard edges
+    Lanczos,
+}
+
+impl ResizeFilter {
+    // Half of the width of the kernel, in pixels of the canvas it is applied to
+    fn radius(&self) -> f64 {
+        match self {
+            ResizeFilter::Box => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, t: f64) -> f64 {
+        match self {
+            ResizeFilter::Box => {
+                if (-0.5..0.5).contains(&t) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ResizeFilter::Bilinear => (1.0 - t.abs()).max(0.0),
+            ResizeFilter::Lanczos => {
+                if t.abs() < 3.0 {
+                    sinc(t) * sinc(t / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(t: f64) -> f64 {
+    if t.abs() < 1e-8 {
+        1.0
+    } else {
+        (PI * t).sin() / (PI * t)
+    }
+}
+
+// Pixels of a row or a column blended into one new pixel, from `start` on
+struct Taps {
+    start: usize,
+    weights: Vec<f64>,
+}
+
+impl Taps {
+    // Weights of the `kernel` centered at `center`, pixels past the edges are left out and the rest weigh more
+    fn new(center: f64, radius: f64, size: usize, kernel: impl Fn(f64) -> f64) -> Taps {
+        let first = (center - radius).floor().max(0.0) as usize;
+        let last = ((center + radius).ceil() as usize).min(size - 1);
+        let mut weights: Vec<f64> = (first..=last).map(|i| kernel(i as f64 - center)).collect();
+        let total: f64 = weights.iter().sum();
+        if total.abs() > 1e-12 {
+            weights.iter_mut().for_each(|w| *w /= total);
+        } else {
+            // Nearest pixel, when the kernel misses every pixel
+            let nearest = (center.round().max(0.0) as usize).min(size - 1);
+            weights = (first..=last)
+                .map(|i| if i == nearest { 1.0 } else { 0.0 })
+                .collect();
+        }
+        Taps {
+            start: first,
+            weights,
+        }
+    }
+
+    fn apply(&self, pixels: impl Fn(usize) -> Color) -> Color {
+        self.weights
+            .iter()
+            .enumerate()
+            .fold(Color::black(), |total, (i, &w)| {
+                total + pixels(self.start + i) * w
+            })
+    }
+}
+
+// Taps to resize `size` pixels to `new_size` pixels
+fn resize_taps(size: usize, new_size: usize, filter: ResizeFilter) -> Vec<Taps> {
+    let scale = size as f64 / new_size as f64;
+    // When shrinking, the kernel is stretched to cover all the pixels going into one
+    let stretch = scale.max(1.0);
+    (0..new_size)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale - 0.5;
+            Taps::new(center, filter.radius() * stretch, size, |t| {
+                filter.weight(t / stretch)
+            })
+        })
+        .collect()
+}
+
+// Gaussian taps of standard deviation `sigma` for each of `size` pixels
+fn gaussian_taps(size: usize, sigma: f64) -> Vec<Taps> {
+    let radius = (3.0 * sigma).ceil();
+    (0..size)
+        .map(|i| {
+            Taps::new(i as f64, radius, size, |t| {
+                (-t * t / (2.0 * sigma * sigma)).exp()
+            })
+        })
+        .collect()
+}
+
+impl Canvas {
+    // Applies `columns` along each row then `rows` along each column, giving a canvas of their sizes
+    fn separable(&self, columns: &[Taps], rows: &[Taps]) -> Canvas {
+        let mut wide = Canvas::new(columns.len(), self.height);
+        for y in 0..self.height {
+            for (x, taps) in columns.iter().enumerate() {
+                wide.data[y * wide.width + x] = taps.apply(|i| self.data[y * self.width + i]);
+            }
+        }
+        let mut canvas = Canvas::new(columns.len(), rows.len());
+        for (y, taps) in rows.iter().enumerate() {
+            for x in 0..canvas.width {
+                canvas.data[y * canvas.width + x] = taps.apply(|i| wide.data[i * wide.width + x]);
+            }
+        }
+        canvas
+    }
+
+    // Canvas scaled to `width` x `height`, cut parts of a canvas with `crop`
+    pub fn resize(&self, width: usize, height: usize, filter: ResizeFilter) -> Canvas {
+        if self.width == 0 || self.height == 0 || width == 0 || height == 0 {
+            return Canvas::new(width, height);
+        }
+        self.separable(
+            &resize_taps(self.width, width, filter),
+            &resize_taps(self.height, height, filter),
+        )
+    }
+
+    // Mirrors left and right
+    pub fn flip_horizontal(&self) -> Canvas {
+        let mut canvas = self.clone();
+        for row in 0..self.height {
+            canvas[row].reverse();
+        }
+        canvas
+    }
+
+    // Mirrors top and bottom
+    pub fn flip_vertical(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height);
+        for row in 0..self.height {
+            canvas[row].copy_from_slice(&self[self.height - 1 - row]);
+        }
+        canvas
+    }
+
+    // Blurs with a gaussian of standard deviation `sigma` pixels, the edges aren't darkened
+    pub fn gaussian_blur(&self, sigma: f64) -> Canvas {
+        if sigma <= 0.0 || self.width == 0 || self.height == 0 {
+            return self.clone();
+        }
+        self.separable(
+            &gaussian_taps(self.width, sigma),
+            &gaussian_taps(self.height, sigma),
+        )
+    }
+
+    // Glow around over bright pixels: the part of the light of each pixel above a luminance of `threshold`
+    // is blurred by `sigma` pixels and added back `strength` times
+    // Meant for renders before tone mapping, whose colors go above 1.0
+    pub fn bloom(&self, threshold: f64, sigma: f64, strength: f64) -> Canvas {
+        let mut bright = self.clone();
+        for color in bright.data.iter_mut() {
+            let luminance = color.luminance();
+            *color = if luminance > threshold {
+                *color * ((luminance - threshold) / luminance)
+            } else {
+                Color::black()
+            };
+        }
+        let glow = bright.gaussian_blur(sigma);
+        let mut canvas = self.clone();
+        for (color, glow) in canvas.data.iter_mut().zip(&glow.data) {
+            *color = *color + *glow * strength;
+        }
+        canvas
+    }
+
+    // Darkens towards the corners, by `strength` at the corners, 0.0 leaves the canvas as it is and
+    // 1.0 makes the corners black. Falls off with the square of the distance from the center
+    pub fn vignette(&self, strength: f64) -> Canvas {
+        let mut canvas = self.clone();
+        let (half_width, half_height) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+        let corner = (half_width * half_width + half_height * half_height).max(1e-12);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let (dx, dy) = (
+                    col as f64 + 0.5 - half_width,
+                    row as f64 + 0.5 - half_height,
+                );
+                let factor = (1.0 - strength * (dx * dx + dy * dy) / corner).max(0.0);
+                canvas.data[row * self.width + col] = self.pixel_at(col, row) * factor;
+            }
+        }
+        canvas
+    }
+}
+
+#[cfg(test)]
+mod post_process_tests {
+    use super::*;
+    use crate::test_fixtures::numbered_canvas;
+
+    #[test]
+    fn shrinking_with_box_filter_averages_pixels() {
+        let c = numbered_canvas(4, 4).resize(2, 1, ResizeFilter::Box);
+        assert_eq!((c.width, c.height), (2, 1));
+        assert_eq!(c.pixel_at(0, 0), Color::new(0.5, 1.5, 0.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(2.5, 1.5, 0.0));
+    }
+
+    #[test]
+    fn enlarging_with_box_filter_repeats_pixels() {
+        let c = numbered_canvas(2, 1).resize(4, 2, ResizeFilter::Box);
+        assert_eq!(c.pixel_at(0, 1), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(2, 0), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn enlarging_with_bilinear_filter_interpolates() {
+        let c = numbered_canvas(2, 1).resize(4, 1, ResizeFilter::Bilinear);
+        let reds: Vec<f64> = c.data.iter().map(|c| c.red).collect();
+        assert_eq!(reds, [0.0, 0.25, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn resizing_keeps_flat_colors() {
+        let mut flat = Canvas::new(7, 5);
+        flat.fill(Color::new(0.2, 0.4, 0.6));
+        for filter in [
+            ResizeFilter::Box,
+            ResizeFilter::Bilinear,
+            ResizeFilter::Lanczos,
+        ] {
+            for (width, height) in [(3, 2), (16, 9), (7, 5)] {
+                let c = flat.resize(width, height, filter);
+                assert!(c.data.iter().all(|&p| p == Color::new(0.2, 0.4, 0.6)));
+            }
+        }
+        // Same size is the same canvas
+        let c = numbered_canvas(5, 3);
+        assert_eq!(c.resize(5, 3, ResizeFilter::Lanczos).data, c.data);
+    }
+
+    #[test]
+    fn flipping() {
+        let c = numbered_canvas(3, 2);
+        assert_eq!(
+            c.flip_horizontal().pixel_at(0, 1),
+            Color::new(2.0, 1.0, 0.0)
+        );
+        assert_eq!(c.flip_vertical().pixel_at(0, 1), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(c.flip_vertical().flip_vertical().data, c.data);
+    }
+
+    #[test]
+    fn blurring_spreads_a_point() {
+        let mut c = Canvas::new(15, 15);
+        c.write_pixel(7, 7, Color::white());
+        let blurred = c.gaussian_blur(1.0);
+        let total = blurred
+            .data
+            .iter()
+            .fold(Color::black(), |total, &p| total + p);
+        assert_eq!(total, Color::white());
+        let center = blurred.pixel_at(7, 7).red;
+        assert!(center < 1.0);
+        assert!(blurred.pixel_at(8, 7).red < center);
+        assert_eq!(blurred.pixel_at(8, 7), blurred.pixel_at(7, 6));
+        // Edges aren't darkened
+        let mut flat = Canvas::new(5, 5);
+        flat.fill(Color::white());
+        assert_eq!(flat.gaussian_blur(2.0).pixel_at(0, 0), Color::white());
+    }
+
+    #[test]
+    fn bloom_only_spreads_bright_pixels() {
+        let mut c = Canvas::new(9, 1);
+        c.fill(Color::new(0.5, 0.5, 0.5));
+        c.write_pixel(4, 0, Color::new(5.0, 5.0, 5.0));
+        let bloomed = c.bloom(1.0, 1.0, 1.0);
+        assert!(bloomed.pixel_at(3, 0).red > 0.5);
+        assert_eq!(bloomed.pixel_at(0, 0), Color::new(0.5, 0.5, 0.5));
+        let mut dim = Canvas::new(3, 3);
+        dim.fill(Color::new(0.9, 0.9, 0.9));
+        assert_eq!(dim.bloom(1.0, 1.0, 1.0).data, dim.data);
+    }
+
+    #[test]
+    fn vignette_darkens_corners() {
+        let mut c = Canvas::new(11, 11);
+        c.fill(Color::white());
+        let v = c.vignette(0.5);
+        let center = v.pixel_at(5, 5).red;
+        let corner = v.pixel_at(0, 0).red;
+        assert_eq!(center, 1.0);
+        assert!(corner < v.pixel_at(0, 5).red);
+        assert!((corner - 0.5).abs() < 0.1);
+        assert_eq!(c.vignette(0.0).data, c.data);
+    }
+}
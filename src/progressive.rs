@@ -0,0 +1,246 @@
+use crate::random::Rng;
+use crate::Camera;
+use crate::Canvas;
+use crate::Color;
+use crate::PathTracer;
+use crate::World;
+use std::time::{Duration, Instant};
+
+// When `ProgressiveRender::run` stops refining, whichever comes first
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StopCondition {
+    pub max_samples: usize,            // Samples per pixel
+    pub time_budget: Option<Duration>, // Checked after every pass, so the last pass may go over it
+    pub noise_threshold: Option<f64>,  // Stops once `ProgressiveRender::noise` is below it
+}
+
+impl StopCondition {
+    pub fn new(max_samples: usize) -> Self {
+        Self {
+            max_samples,
+            time_budget: None,
+            noise_threshold: None,
+        }
+    }
+}
+
+// Why `ProgressiveRender::run` stopped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Samples,
+    Time,
+    Noise,
+}
+
+// Renders the image in passes of one sample per pixel, adding them up in a float accumulation buffer
+// The average so far can be looked at after every pass, a rough image comes quickly and gets smoother
+// Pixels jitter inside themselves and over the lens and the shutter like `Camera::render` with many samples,
+// with `World::color_at` or with the path tracer when given (its `samples_per_pixel` is not used)
+pub struct ProgressiveRender<'a> {
+    camera: &'a Camera,
+    world: &'a World,
+    path_tracer: Option<PathTracer>,
+    sum: Vec<Color>,          // Sum of the samples of each pixel
+    sum_of_squares: Vec<f64>, // Sum of the squared luminance of the samples of each pixel, for the noise
+    samples: usize,           // Samples of every pixel so far
+}
+
+impl<'a> ProgressiveRender<'a> {
+    pub fn new(camera: &'a Camera, world: &'a World, path_tracer: Option<PathTracer>) -> Self {
+        let pixels = camera.get_hsize() as usize * camera.get_vsize() as usize;
+        Self {
+            camera,
+            world,
+            path_tracer,
+            sum: vec![Color::black(); pixels],
+            sum_of_squares: vec![0.0; pixels],
+            samples: 0,
+        }
+    }
+
+    pub fn get_samples(&self) -> usize {
+        self.samples
+    }
+
+    // Adds one more sample to every pixel
+    pub fn pass(&mut self) {
+        let seed = match &self.path_tracer {
+            Some(tracer) => tracer.seed,
+            None => self.camera.seed,
+        };
+        // Every pass gets its own random numbers
+        let seed = Rng::new(seed.wrapping_add(self.samples as u64)).next_u64();
+        let width = self.camera.get_hsize() as usize;
+        for (i, (sum, sum_of_squares)) in self
+            .sum
+            .iter_mut()
+            .zip(self.sum_of_squares.iter_mut())
+            .enumerate()
+        {
+            let (x, y) = (i % width, i / width);
+            let color = self.camera.sample_pixel(
+                x,
+                y,
+                1,
+                seed,
+                |x, y, rng| self.camera.ray_through_lens(x, y, rng),
+                |ray, rng| match &self.path_tracer {
+                    Some(tracer) => tracer.color_at(self.world, ray, rng),
+                    None => self.world.color_at(ray),
+                },
+            );
+            *sum = *sum + color;
+            *sum_of_squares += color.luminance() * color.luminance();
+        }
+        self.samples += 1;
+    }
+
+    // Average of the samples so far, black before the first pass
+    pub fn snapshot(&self) -> Canvas {
+        let mut canvas = Canvas::new(
+            self.camera.get_hsize() as usize,
+            self.camera.get_vsize() as usize,
+        );
+        let scale = 1.0 / self.samples.max(1) as f64;
+        for (pixel, sum) in canvas.data.iter_mut().zip(&self.sum) {
+            *pixel = *sum * scale;
+        }
+        canvas
+    }
+
+    // Estimate of how far the pixels are from their final luminance, the standard error of the mean
+    // luminance averaged over the pixels. Infinite before the second pass
+    pub fn noise(&self) -> f64 {
+        if self.samples < 2 || self.sum.is_empty() {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mut total = 0.0;
+        for (sum, sum_of_squares) in self.sum.iter().zip(&self.sum_of_squares) {
+            let mean = sum.luminance() / n;
+            let variance = ((sum_of_squares - n * mean * mean) / (n - 1.0)).max(0.0);
+            total += (variance / n).sqrt();
+        }
+        total / self.sum.len() as f64
+    }
+
+    // Renders passes until one of the stop conditions is met, calling `on_pass` with the snapshot after each
+    pub fn run(
+        &mut self,
+        stop: StopCondition,
+        mut on_pass: impl FnMut(&ProgressiveRender, &Canvas),
+    ) -> StopReason {
+        let start = Instant::now();
+        loop {
+            if self.samples >= stop.max_samples {
+                return StopReason::Samples;
+            }
+            self.pass();
+            on_pass(self, &self.snapshot());
+            if stop
+                .noise_threshold
+                .is_some_and(|threshold| self.noise() < threshold)
+            {
+                return StopReason::Noise;
+            }
+            if stop
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget)
+            {
+                return StopReason::Time;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod progressive_tests {
+    use super::*;
+    use crate::test_fixtures::camera_facing_origin;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn camera() -> Camera {
+        zoomed_camera(FRAC_PI_2)
+    }
+
+    fn zoomed_camera(field_of_view: f64) -> Camera {
+        camera_facing_origin(7, 7)
+            .field_of_view(field_of_view)
+            .build()
+    }
+
+    #[test]
+    fn snapshot_is_black_before_first_pass() {
+        let (c, w) = (camera(), World::default());
+        let render = ProgressiveRender::new(&c, &w, None);
+        assert_eq!(render.get_samples(), 0);
+        assert_eq!(render.snapshot().pixel_at(3, 3), Color::black());
+        assert_eq!(render.noise(), f64::INFINITY);
+    }
+
+    #[test]
+    fn passes_average_towards_the_image() {
+        let w = World::default();
+        // Pixels cover a tiny part of the sphere, which looks about the same everywhere in them
+        let c = zoomed_camera(0.05);
+        let mut render = ProgressiveRender::new(&c, &w, None);
+        render.pass();
+        render.pass();
+        assert_eq!(render.get_samples(), 2);
+        let center = render.snapshot().pixel_at(3, 3);
+        let expected = c.render(&w).pixel_at(3, 3);
+        assert!((center.red - expected.red).abs() < 0.01);
+        // Rays of the corner pixel of the wide view all miss the spheres
+        let c = camera();
+        let mut render = ProgressiveRender::new(&c, &w, None);
+        render.pass();
+        assert_eq!(render.snapshot().pixel_at(0, 0), Color::black());
+    }
+
+    #[test]
+    fn stops_at_sample_count() {
+        let (c, w) = (camera(), World::default());
+        let mut render = ProgressiveRender::new(&c, &w, None);
+        let mut snapshots = 0;
+        let reason = render.run(StopCondition::new(3), |progress, snapshot| {
+            snapshots += 1;
+            assert_eq!(progress.get_samples(), snapshots);
+            assert_eq!(snapshot.width, 7);
+        });
+        assert_eq!(reason, StopReason::Samples);
+        assert_eq!(snapshots, 3);
+    }
+
+    #[test]
+    fn stops_on_time_budget() {
+        let (c, w) = (camera(), World::default());
+        let mut render = ProgressiveRender::new(&c, &w, None);
+        let stop = StopCondition {
+            time_budget: Some(Duration::ZERO),
+            ..StopCondition::new(100)
+        };
+        assert_eq!(render.run(stop, |_, _| {}), StopReason::Time);
+        assert_eq!(render.get_samples(), 1);
+    }
+
+    #[test]
+    fn noise_goes_down_and_stops_the_render() {
+        let (c, w) = (camera(), World::default());
+        let mut render = ProgressiveRender::new(&c, &w, Some(PathTracer::new(1)));
+        render.pass();
+        render.pass();
+        let early = render.noise();
+        for _ in 0..30 {
+            render.pass();
+        }
+        assert!(render.noise() < early);
+
+        let mut render = ProgressiveRender::new(&c, &w, Some(PathTracer::new(1)));
+        let stop = StopCondition {
+            noise_threshold: Some(early * 0.5),
+            ..StopCondition::new(10000)
+        };
+        assert_eq!(render.run(stop, |_, _| {}), StopReason::Noise);
+        assert!(render.get_samples() < 10000);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::Tuple;
+
+// Small seeded pseudo random number generator (SplitMix64)
+// Not suitable for cryptography, but fast and good enough for sampling
+// The same seed always gives the same sequence, so that renders are reproducible
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // Seed based on the position of a point, for sampling which should be stable for a point in space
+    pub fn from_point(p: Tuple) -> Self {
+        let seed = p.x.to_bits() ^ p.y.to_bits().rotate_left(21) ^ p.z.to_bits().rotate_left(42);
+        Self::new(seed)
+    }
+
+    // Seed for the pixel (x, y) of a render seeded with `seed`
+    // Neighbouring pixels get unrelated sequences, so that their noise doesn't form patterns
+    pub fn from_pixel(seed: u64, x: usize, y: usize) -> Self {
+        let mut mixer = Self::new(seed ^ ((x as u64) << 32) ^ y as u64);
+        Self::new(mixer.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        // Use the top 53 bits as that is the precision of f64
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::*;
+    use crate::point;
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_sequences() {
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(2);
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn floats_are_between_zero_and_one() {
+        let mut rng = Rng::new(7);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let x = rng.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            sum += x;
+        }
+        // Mean of uniform distribution is 0.5
+        assert!((sum / 10000.0 - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn seeding_from_point() {
+        let mut a = Rng::from_point(point(1.0, 2.0, 3.0));
+        let mut b = Rng::from_point(point(1.0, 2.0, 3.0));
+        let mut c = Rng::from_point(point(3.0, 2.0, 1.0));
+        let x = a.next_u64();
+        assert_eq!(x, b.next_u64());
+        assert_ne!(x, c.next_u64());
+    }
+
+    #[test]
+    fn seeding_from_pixel() {
+        let x = Rng::from_pixel(5, 1, 2).next_u64();
+        assert_eq!(x, Rng::from_pixel(5, 1, 2).next_u64());
+        assert_ne!(x, Rng::from_pixel(5, 2, 1).next_u64());
+        assert_ne!(x, Rng::from_pixel(6, 1, 2).next_u64());
+    }
+}
@@ -4,11 +4,21 @@ use crate::{Matrix, Tuple};
 pub struct Ray {
     pub origin: Tuple,    // Is a point
     pub direction: Tuple, // Is a vector
+    pub time: f64, // When the ray is shot, moving objects and cameras are placed as they were at that time
 }
 
 impl Ray {
     pub fn new(origin: Tuple, direction: Tuple) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+
+    // Same ray shot at another `time`
+    pub fn with_time(self, time: f64) -> Self {
+        Self { time, ..self }
     }
 
     // Computes new position of ray after time `t`
@@ -21,6 +31,7 @@ impl Ray {
         Self {
             origin: m * self.origin,
             direction: m * self.direction,
+            time: self.time,
         }
     }
 }
@@ -67,4 +78,20 @@ mod ray_tests {
         assert_eq!(r2.origin, point(2.0, 6.0, 12.0));
         assert_eq!(r2.direction, vector(0.0, 3.0, 0.0));
     }
+
+    #[test]
+    fn rays_are_shot_at_time_zero_by_default() {
+        let r = Ray::new(point(1.0, 2.0, 3.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(r.time, 0.0);
+        let r = r.with_time(0.5);
+        assert_eq!(r.time, 0.5);
+        assert_eq!(r.origin, point(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn transforming_ray_keeps_time() {
+        let r = Ray::new(point(1.0, 2.0, 3.0), vector(0.0, 1.0, 0.0)).with_time(0.7);
+        let r2 = r.transform(Matrix::get_translation_matrix(3.0, 4.0, 5.0));
+        assert_eq!(r2.time, 0.7);
+    }
 }
@@ -0,0 +1,524 @@
+use crate::utils::invalid_data;
+use crate::Camera;
+use crate::Canvas;
+use crate::Color;
+use crate::Matrix;
+use crate::Motion;
+use crate::PathTracer;
+use crate::Projection;
+use crate::Region;
+use crate::World;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const JOB_HEADER: &str = "ray_tracer job";
+const TILE_HEADER: &str = "ray_tracer tile";
+
+// One tile of a render, which can be rendered on its own by another process or machine
+// Jobs are written to a directory shared by the machines (eg.- over NFS), workers pick them up with `work`,
+// and `merge` puts the rendered tiles back together:
+//
+// let jobs = RenderJob::split("spheres", &camera, None, 64);
+// RenderJob::write_all(dir, &jobs)?;      // once
+// RenderJob::work(dir, scene_by_name)?;   // on every machine, as many processes as there are cores
+// let canvas = RenderJob::merge(dir)?;    // once all the tiles are done
+//
+// Worlds can't be written to files, so, the job only names the scene, and the worker builds it by that name
+#[derive(Clone, Debug)]
+pub struct RenderJob {
+    pub scene: String,
+    pub camera: Camera, // Camera of the whole image, its `seed` is the seed of the job
+    pub path_tracer: Option<PathTracer>, // Renders with `World::color_at` when missing
+    pub region: Region, // Part of the image of the camera rendered by the job
+}
+
+impl RenderJob {
+    pub fn new(
+        scene: &str,
+        camera: &Camera,
+        path_tracer: Option<PathTracer>,
+        region: Region,
+    ) -> Self {
+        Self {
+            scene: scene.to_string(),
+            camera: camera.clone(),
+            path_tracer,
+            region,
+        }
+    }
+
+    // Jobs for the tiles of the whole image, `tile_size` pixels wide and high
+    pub fn split(
+        scene: &str,
+        camera: &Camera,
+        path_tracer: Option<PathTracer>,
+        tile_size: usize,
+    ) -> Vec<RenderJob> {
+        let full = camera.full_region();
+        Region::tiles(full.width, full.height, tile_size)
+            .into_iter()
+            .map(|region| RenderJob::new(scene, camera, path_tracer, region))
+            .collect()
+    }
+
+    // Renders the tile, pixels get the same rays as in a render of the whole image
+    pub fn render(&self, world: &World) -> Canvas {
+        match &self.path_tracer {
+            Some(tracer) => self
+                .camera
+                .render_path_traced_region(world, tracer, self.region),
+            None => self.camera.render_region(world, self.region),
+        }
+    }
+
+    // Job as text, one setting per line, numbers are written exactly
+    pub fn to_job_string(&self) -> String {
+        let c = &self.camera;
+        let mut lines = vec![
+            JOB_HEADER.to_string(),
+            format!("scene {}", self.scene),
+            format!("size {} {}", c.get_hsize(), c.get_vsize()),
+            format!("field_of_view {}", c.get_field_of_view()),
+            format!("transform {}", matrix_to_string(&c.transform)),
+            format!("aperture {}", c.aperture),
+            format!("focal_distance {}", c.focal_distance),
+            format!("aperture_blades {}", c.aperture_blades),
+            format!("samples {}", c.samples),
+            format!("seed {}", c.seed),
+            format!("shutter {} {}", c.shutter_open, c.shutter_close),
+            match c.projection {
+                Projection::Perspective => "projection perspective".to_string(),
+                Projection::Orthographic { width } => format!("projection orthographic {}", width),
+                Projection::Equirectangular => "projection equirectangular".to_string(),
+                Projection::Fisheye { field_of_view } => {
+                    format!("projection fisheye {}", field_of_view)
+                }
+            },
+        ];
+        if let Some(motion) = &c.motion {
+            for (time, transform) in motion.get_keyframes() {
+                lines.push(format!(
+                    "keyframe {} {}",
+                    time,
+                    matrix_to_string(&transform)
+                ));
+            }
+        }
+        if let Some(tracer) = &self.path_tracer {
+            lines.push(format!(
+                "path_tracer {} {} {}",
+                tracer.samples_per_pixel, tracer.min_bounces, tracer.seed
+            ));
+        }
+        let r = self.region;
+        lines.push(format!("region {} {} {} {}", r.x, r.y, r.width, r.height));
+        lines.join("\n") + "\n"
+    }
+
+    // Inverse of `to_job_string`
+    pub fn from_job_string(text: &str) -> io::Result<RenderJob> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(JOB_HEADER) {
+            return Err(invalid_data("not a render job"));
+        }
+        let mut scene = None;
+        let mut size = None;
+        let mut field_of_view = None;
+        let mut region = None;
+        let mut camera = Camera::new(1, 1, 1.0);
+        let mut keyframes = Vec::new();
+        let mut path_tracer = None;
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let values: Vec<&str> = rest.split_whitespace().collect();
+            match key {
+                "scene" => scene = Some(rest.to_string()),
+                "size" => size = Some((number(&values, 0)?, number(&values, 1)?)),
+                "field_of_view" => field_of_view = Some(number(&values, 0)?),
+                "transform" => camera.transform = matrix(&values, 0)?,
+                "aperture" => camera.aperture = number(&values, 0)?,
+                "focal_distance" => camera.focal_distance = number(&values, 0)?,
+                "aperture_blades" => camera.aperture_blades = number(&values, 0)?,
+                "samples" => camera.samples = number(&values, 0)?,
+                "seed" => camera.seed = number(&values, 0)?,
+                "shutter" => {
+                    camera.shutter_open = number(&values, 0)?;
+                    camera.shutter_close = number(&values, 1)?;
+                }
+                "projection" => {
+                    camera.projection = match values.first() {
+                        Some(&"perspective") => Projection::Perspective,
+                        Some(&"orthographic") => Projection::Orthographic {
+                            width: number(&values, 1)?,
+                        },
+                        Some(&"equirectangular") => Projection::Equirectangular,
+                        Some(&"fisheye") => Projection::Fisheye {
+                            field_of_view: number(&values, 1)?,
+                        },
+                        _ => return Err(invalid_data("unknown projection")),
+                    }
+                }
+                "keyframe" => keyframes.push((number(&values, 0)?, matrix(&values, 1)?)),
+                "path_tracer" => {
+                    let mut tracer = PathTracer::new(number(&values, 0)?);
+                    tracer.min_bounces = number(&values, 1)?;
+                    tracer.seed = number(&values, 2)?;
+                    path_tracer = Some(tracer);
+                }
+                "region" => {
+                    region = Some(Region::new(
+                        number(&values, 0)?,
+                        number(&values, 1)?,
+                        number(&values, 2)?,
+                        number(&values, 3)?,
+                    ))
+                }
+                _ => return Err(invalid_data("unknown setting")),
+            }
+        }
+        let (Some(scene), Some((hsize, vsize)), Some(field_of_view), Some(region)) =
+            (scene, size, field_of_view, region)
+        else {
+            return Err(invalid_data(
+                "scene, size, field_of_view or region is missing",
+            ));
+        };
+        if region.clip(hsize as usize, vsize as usize) != region {
+            return Err(invalid_data("region is outside of the image"));
+        }
+        camera.set_size(hsize, vsize);
+        camera.set_field_of_view(field_of_view);
+        if !keyframes.is_empty() {
+            camera.motion = Some(
+                Motion::from_keyframes(keyframes)
+                    .ok_or_else(|| invalid_data("keyframes can't be interpolated"))?,
+            );
+        }
+        Ok(RenderJob {
+            scene,
+            camera,
+            path_tracer,
+            region,
+        })
+    }
+
+    pub fn read(path: &Path) -> io::Result<RenderJob> {
+        RenderJob::from_job_string(&fs::read_to_string(path)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_job_string())
+    }
+
+    // Writes the jobs into the directory as `tile_00000.job`, `tile_00001.job`, ... and returns their paths
+    pub fn write_all(dir: &Path, jobs: &[RenderJob]) -> io::Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir)?;
+        let mut paths = Vec::new();
+        for (i, job) in jobs.iter().enumerate() {
+            let path = dir.join(format!("tile_{:05}.job", i));
+            job.write(&path)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    // Paths of the job files in the directory, in order
+    pub fn job_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "job") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    // Where the rendered tile of the job file is written, next to it
+    pub fn tile_path(job_path: &Path) -> PathBuf {
+        job_path.with_extension("tile")
+    }
+
+    // Renders the jobs of the directory which no other worker has taken, and returns how many it rendered
+    // A worker takes a job by creating its `.lock` file, which fails if it is there already, so, any number
+    // of workers can share the directory. Tiles are written to a temporary file and renamed when complete
+    // If a worker dies, remove the `.lock` files of the jobs without a `.tile` file to render them again
+    // A job which fails is released, its `.lock` file is removed before returning the error
+    // `scene` builds the world named by a job, jobs of unknown scenes are an error
+    pub fn work(dir: &Path, scene: impl Fn(&str) -> Option<World>) -> io::Result<usize> {
+        let mut rendered = 0;
+        let mut worlds: Vec<(String, World)> = Vec::new();
+        for job_path in RenderJob::job_paths(dir)? {
+            let tile_path = RenderJob::tile_path(&job_path);
+            if tile_path.exists() {
+                continue;
+            }
+            let lock_path = job_path.with_extension("lock");
+            let lock = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path);
+            match lock {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+
+            if let Err(e) = RenderJob::render_file(&job_path, &scene, &mut worlds) {
+                let _ = fs::remove_file(job_path.with_extension("partial"));
+                let _ = fs::remove_file(&lock_path);
+                return Err(e);
+            }
+            rendered += 1;
+        }
+        Ok(rendered)
+    }
+
+    // Renders the job file into its tile file, `worlds` keeps the scenes built so far
+    fn render_file(
+        job_path: &Path,
+        scene: impl Fn(&str) -> Option<World>,
+        worlds: &mut Vec<(String, World)>,
+    ) -> io::Result<()> {
+        let job = RenderJob::read(job_path)?;
+        if !worlds.iter().any(|(name, _)| *name == job.scene) {
+            let world = scene(&job.scene).ok_or_else(|| invalid_data("unknown scene"))?;
+            worlds.push((job.scene.clone(), world));
+        }
+        let world = &worlds
+            .iter()
+            .find(|(name, _)| *name == job.scene)
+            .unwrap()
+            .1;
+        let canvas = job.render(world);
+
+        let partial_path = job_path.with_extension("partial");
+        fs::write(&partial_path, tile_to_string(&canvas))?;
+        fs::rename(&partial_path, RenderJob::tile_path(job_path))
+    }
+
+    // Stitches the rendered tiles of the directory into the whole image
+    // Fails if there are no jobs, if the jobs are parts of images of different sizes, if a tile isn't
+    // rendered yet, or if it doesn't have the size of the region of its job
+    pub fn merge(dir: &Path) -> io::Result<Canvas> {
+        let mut parts = Vec::new();
+        let mut full = None;
+        for job_path in RenderJob::job_paths(dir)? {
+            let job = RenderJob::read(&job_path)?;
+            if *full.get_or_insert(job.camera.full_region()) != job.camera.full_region() {
+                return Err(invalid_data("jobs are parts of images of different sizes"));
+            }
+            let tile = tile_from_string(&fs::read_to_string(RenderJob::tile_path(&job_path))?)?;
+            if (tile.width, tile.height) != (job.region.width, job.region.height) {
+                return Err(invalid_data("tile doesn't match the region of its job"));
+            }
+            parts.push((job.region, tile));
+        }
+        let full = full.ok_or_else(|| invalid_data("no render jobs in the directory"))?;
+        Ok(Canvas::merge(full.width, full.height, &parts))
+    }
+}
+
+// Rendered tile as text, with the colors written exactly, unlike PPM which rounds them to 8 bits
+fn tile_to_string(canvas: &Canvas) -> String {
+    let mut text = format!("{}\n{} {}\n", TILE_HEADER, canvas.width, canvas.height);
+    for color in &canvas.data {
+        text += &format!("{} {} {}\n", color.red, color.green, color.blue);
+    }
+    text
+}
+
+fn tile_from_string(text: &str) -> io::Result<Canvas> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some(TILE_HEADER) {
+        return Err(invalid_data("not a rendered tile"));
+    }
+    let size: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+    let mut canvas = Canvas::new(number(&size, 0)?, number(&size, 1)?);
+    for i in 0..canvas.data.len() {
+        let rgb: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+        canvas.data[i] = Color::new(number(&rgb, 0)?, number(&rgb, 1)?, number(&rgb, 2)?);
+    }
+    Ok(canvas)
+}
+
+fn matrix_to_string(m: &Matrix) -> String {
+    let mut values = Vec::new();
+    for row in 0..4 {
+        for col in 0..4 {
+            values.push(m[row][col].to_string());
+        }
+    }
+    values.join(" ")
+}
+
+// Matrix from 16 values, row by row, starting at `start`
+fn matrix(values: &[&str], start: usize) -> io::Result<Matrix> {
+    let mut m = Matrix::new();
+    for row in 0..4 {
+        for col in 0..4 {
+            m[row][col] = number(values, start + row * 4 + col)?;
+        }
+    }
+    Ok(m)
+}
+
+fn number<T: std::str::FromStr>(values: &[&str], index: usize) -> io::Result<T> {
+    values
+        .get(index)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid_data("missing or invalid number"))
+}
+
+#[cfg(test)]
+mod render_job_tests {
+    use super::*;
+    use crate::test_fixtures::camera_facing_origin;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn camera() -> Camera {
+        camera_facing_origin(11, 7).field_of_view(FRAC_PI_2).build()
+    }
+
+    fn scene(name: &str) -> Option<World> {
+        (name == "default").then(World::default)
+    }
+
+    // Empty directory of its own for each test
+    fn job_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ray_tracer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn splitting_covers_the_image() {
+        let jobs = RenderJob::split("default", &camera(), None, 4);
+        assert_eq!(jobs.len(), 6);
+        assert_eq!(jobs[5].region, Region::new(8, 4, 3, 3));
+        let pixels: usize = jobs.iter().map(|j| j.region.width * j.region.height).sum();
+        assert_eq!(pixels, 77);
+    }
+
+    #[test]
+    fn job_survives_writing_and_reading() {
+        let mut c = camera();
+        c.aperture = 0.25;
+        c.focal_distance = 4.5;
+        c.aperture_blades = 6;
+        c.samples = 3;
+        c.seed = 42;
+        c.shutter_close = 0.5;
+        c.projection = Projection::Fisheye { field_of_view: 3.0 };
+        c.motion = Motion::new(
+            c.transform,
+            Matrix::get_translation_matrix(0.1, 0.0, 0.0) * c.transform,
+        );
+        let mut tracer = PathTracer::new(8);
+        tracer.seed = 5;
+        let job = RenderJob::new("my scene", &c, Some(tracer), Region::new(1, 2, 3, 4));
+
+        let read = RenderJob::from_job_string(&job.to_job_string()).unwrap();
+        assert_eq!(read.scene, "my scene");
+        assert_eq!(read.region, job.region);
+        assert_eq!(read.path_tracer, Some(tracer));
+        assert_eq!(read.camera.get_hsize(), 11);
+        assert_eq!(read.camera.get_vsize(), 7);
+        assert_eq!(read.camera.get_pixel_size(), c.get_pixel_size());
+        assert_eq!(read.camera.transform, c.transform);
+        assert_eq!(read.camera.motion, c.motion);
+        assert_eq!(read.camera.projection, c.projection);
+        assert_eq!(
+            (read.camera.aperture, read.camera.focal_distance),
+            (0.25, 4.5)
+        );
+        assert_eq!((read.camera.samples, read.camera.seed), (3, 42));
+        assert_eq!(read.camera.aperture_blades, 6);
+        assert_eq!(read.camera.shutter_close, 0.5);
+    }
+
+    #[test]
+    fn invalid_jobs_are_rejected() {
+        assert!(RenderJob::from_job_string("P3\n1 1\n255\n").is_err());
+        let job = RenderJob::new("default", &camera(), None, Region::new(0, 0, 1, 1));
+        let text = job.to_job_string();
+        let without_region = text.replace("region 0 0 1 1\n", "");
+        assert!(RenderJob::from_job_string(&without_region).is_err());
+        let bad_number = text.replace("samples 1", "samples one");
+        assert!(RenderJob::from_job_string(&bad_number).is_err());
+        let outside = text.replace("region 0 0 1 1", "region 10 0 2 1");
+        assert!(RenderJob::from_job_string(&outside).is_err());
+    }
+
+    #[test]
+    fn workers_render_jobs_that_merge_into_the_full_image() {
+        let dir = job_dir("farm");
+        let mut c = camera();
+        c.samples = 2;
+        let jobs = RenderJob::split("default", &c, None, 4);
+        RenderJob::write_all(&dir, &jobs).unwrap();
+        assert!(RenderJob::merge(&dir).is_err());
+
+        // Another worker took the first job already
+        fs::write(dir.join("tile_00000.lock"), "").unwrap();
+        assert_eq!(RenderJob::work(&dir, scene).unwrap(), 5);
+        assert_eq!(RenderJob::work(&dir, scene).unwrap(), 0);
+        assert!(RenderJob::merge(&dir).is_err());
+
+        fs::remove_file(dir.join("tile_00000.lock")).unwrap();
+        assert_eq!(RenderJob::work(&dir, scene).unwrap(), 1);
+        let merged = RenderJob::merge(&dir).unwrap();
+        assert_eq!(merged.data, c.render(&World::default()).data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_scene_is_an_error() {
+        let dir = job_dir("unknown_scene");
+        let jobs = RenderJob::split("missing", &camera(), None, 16);
+        RenderJob::write_all(&dir, &jobs).unwrap();
+        assert!(RenderJob::work(&dir, scene).is_err());
+        // The job is released, to render it once the scene is there
+        assert!(!dir.join("tile_00000.lock").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_tile_releases_its_job() {
+        let dir = job_dir("failed_tile");
+        let jobs = RenderJob::split("default", &camera(), None, 16);
+        RenderJob::write_all(&dir, &jobs).unwrap();
+        // The tile can't be written where a directory is in the way
+        fs::create_dir(dir.join("tile_00000.partial")).unwrap();
+        assert!(RenderJob::work(&dir, scene).is_err());
+        assert!(!dir.join("tile_00000.lock").exists());
+        fs::remove_dir(dir.join("tile_00000.partial")).unwrap();
+        assert_eq!(RenderJob::work(&dir, scene).unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merging_needs_jobs_of_one_image() {
+        let dir = job_dir("merge_checks");
+        fs::create_dir_all(&dir).unwrap();
+        assert!(RenderJob::merge(&dir).is_err());
+
+        let mut jobs = RenderJob::split("default", &camera(), None, 8);
+        jobs[1].camera.set_size(12, 7);
+        RenderJob::write_all(&dir, &jobs).unwrap();
+        assert_eq!(RenderJob::work(&dir, scene).unwrap(), 2);
+        let error = RenderJob::merge(&dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "jobs are parts of images of different sizes"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
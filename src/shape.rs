@@ -1,45 +1,94 @@
+use crate::Intersection;
 use crate::Intersections;
 use crate::Material;
 use crate::Matrix;
+use crate::Motion;
 use crate::Ray;
+use crate::Tangent;
 use crate::Tuple;
+use std::fmt::Debug;
 
-pub trait Shape {
+pub trait Shape: Debug {
+    // Also stops the shape from moving, if it had a motion
     fn set_transform(&mut self, m: Matrix);
     // fn set_local_ray(&mut self, local_ray: Ray);
     fn get_transform(&self) -> Matrix;
+    fn get_material(&self) -> &Material;
+    fn get_material_mut(&mut self) -> &mut Material;
+
+    // Make the shape move, used for motion blur. The motion replaces the transformation
+    fn set_motion(&mut self, motion: Motion);
+    fn get_motion(&self) -> Option<&Motion>;
+
+    // Transformation at the `time`, only differs from `get_transform` for moving shapes
+    fn transform_at(&self, time: f64) -> Matrix {
+        match self.get_motion() {
+            Some(motion) => motion.transform_at(time),
+            None => self.get_transform(),
+        }
+    }
 
-    fn intersect(&self, ray: Ray) -> Intersections {
+    fn intersect(&self, ray: Ray) -> Intersections<'_> {
         // Transform the ray to the object space coordinates of the shape
         // This means applying inverse transformation of the shape to the ray
-        let local_ray = ray.transform(self.get_transform().inverse());
+        // Moving shapes are placed where they are at the time of the ray
+        let local_ray = ray.transform(self.transform_at(ray.time).inverse());
         // self.set_local_ray(local_ray);
         self.local_intersect(local_ray)
     }
-    fn local_intersect(&self, local_ray: Ray) -> Intersections;
+    fn local_intersect(&self, local_ray: Ray) -> Intersections<'_>;
 
     // Find normal of the shape at the world point `p`
     // Basically transform the point to the object space, find normal at that point
     // and then, transform it back to the world space
     // Derivation is given in the chapter 6 README
+    // Moving shapes are taken at time 0.0, use `normal_at_time` for the other times
     fn normal_at(&self, p: Tuple) -> Tuple {
-        let local_point = self.get_transform().inverse() * p;
+        self.normal_at_time(p, 0.0)
+    }
+
+    // Normal at the world point `p`, with moving shapes placed where they are at the `time`
+    fn normal_at_time(&self, p: Tuple, time: f64) -> Tuple {
+        let transform = self.transform_at(time);
+        let local_point = transform.inverse() * p;
         let local_normal = self.local_normal_at(local_point);
-        let mut world_normal = self.get_transform().inverse().transpose() * local_normal;
+        let mut world_normal = transform.inverse().transpose() * local_normal;
         // This is needed as we are multiplying and transposing the complete transformation matrix
         // it can lead to weird `w` values
         world_normal.w = 0.0;
         world_normal.normalize()
     }
     fn local_normal_at(&self, p: Tuple) -> Tuple;
+
+    // Normal at the world point `p` of the `hit`, for shapes whose normal depends on more than the point,
+    // eg.- triangles with a normal at each vertex
+    fn normal_at_hit(&self, p: Tuple, _hit: &Intersection, time: f64) -> Tuple {
+        self.normal_at_time(p, time)
+    }
+
+    // Center and radius of a sphere containing the shape at the `time`, in world space,
+    // which lights are sampled through. `None` for unbounded shapes like planes
+    fn bounding_sphere(&self, _time: f64) -> Option<(Tuple, f64)> {
+        None
+    }
+
+    // Texture coordinates and world space tangent at the `hit`, which normal maps need
+    // `None` for shapes without texture coordinates, where normal maps are ignored
+    fn tangent_at_hit(&self, _hit: &Intersection, _time: f64) -> Option<((f64, f64), Tangent)> {
+        None
+    }
 }
 
+#[cfg(test)]
+#[derive(Debug)]
 struct TestShape {
-    transform: Matrix, // Transformation matrix
+    transform: Matrix,  // Transformation matrix
     material: Material, // Shape's material
-                       // saved_ray: Ray,     // Store the transformed ray
+    motion: Option<Motion>,
+    // saved_ray: Ray,     // Store the transformed ray
 }
 
+#[cfg(test)]
 impl Shape for TestShape {
     fn get_transform(&self) -> Matrix {
         self.transform
@@ -47,13 +96,30 @@ impl Shape for TestShape {
 
     fn set_transform(&mut self, m: Matrix) {
         self.transform = m;
+        self.motion = None;
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn set_motion(&mut self, motion: Motion) {
+        self.motion = Some(motion);
+    }
+
+    fn get_motion(&self) -> Option<&Motion> {
+        self.motion.as_ref()
     }
 
     // fn set_local_ray(&mut self, local_ray: Ray) {
     //     self.saved_ray = local_ray;
     // }
 
-    fn local_intersect(&self, _local_ray: Ray) -> Intersections {
+    fn local_intersect(&self, _local_ray: Ray) -> Intersections<'_> {
         Intersections::new()
     }
 
@@ -64,11 +130,13 @@ impl Shape for TestShape {
     }
 }
 
+#[cfg(test)]
 impl Default for TestShape {
     fn default() -> Self {
         Self {
             transform: Matrix::I(),
             material: Material::default(),
+            motion: None,
             // saved_ray: Ray::new(
             //     Tuple {
             //         x: 0.0,
@@ -122,7 +190,7 @@ mod testshape_tests {
             ambient: 1.0,
             ..Default::default()
         };
-        s.material = m;
+        s.material = m.clone();
         assert_eq!(m, s.material);
     }
 
@@ -162,4 +230,22 @@ mod testshape_tests {
         let n = s.normal_at(point(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2));
         assert_eq!(n, vector(0.0, 0.97014, -0.24254));
     }
+
+    #[test]
+    fn setting_transformation_stops_motion() {
+        let mut s = TestShape::default();
+        s.set_motion(
+            Motion::new(Matrix::I(), Matrix::get_translation_matrix(2.0, 0.0, 0.0)).unwrap(),
+        );
+        assert_eq!(
+            s.transform_at(0.5),
+            Matrix::get_translation_matrix(1.0, 0.0, 0.0)
+        );
+        s.set_transform(Matrix::get_scaling_matrix(2.0, 2.0, 2.0));
+        assert_eq!(s.get_motion(), None);
+        assert_eq!(
+            s.transform_at(0.5),
+            Matrix::get_scaling_matrix(2.0, 2.0, 2.0)
+        );
+    }
 }
@@ -1,16 +1,19 @@
+use crate::utils::Compare;
 use crate::Material;
+use crate::Motion;
 use crate::Ray;
 use crate::Shape;
-use crate::{point, Matrix, Tuple};
+use crate::{point, vector, Matrix, Tuple};
 use crate::{Intersection, Intersections};
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Sphere {
     // TODO: add `id` to it as described in the book
     center: Tuple,
     radius: f64,
     transform: Matrix,      // Transformation matrix
     pub material: Material, // Material of the sphere
+    motion: Option<Motion>, // Overrides `transform` for a moving sphere
 }
 
 impl Sphere {
@@ -20,6 +23,7 @@ impl Sphere {
             radius,
             transform,
             material,
+            motion: None,
         }
     }
 }
@@ -27,15 +31,60 @@ impl Sphere {
 impl Shape for Sphere {
     fn set_transform(&mut self, t: Matrix) {
         self.transform = t;
+        self.motion = None;
     }
 
     fn get_transform(&self) -> Matrix {
         self.transform
     }
 
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn set_motion(&mut self, motion: Motion) {
+        self.motion = Some(motion);
+    }
+
+    fn get_motion(&self) -> Option<&Motion> {
+        self.motion.as_ref()
+    }
+
+    // Exact for spheres which are only moved, rotated and evenly scaled, a bit larger for the stretched ones
+    fn bounding_sphere(&self, time: f64) -> Option<(Tuple, f64)> {
+        let transform = self.transform_at(time);
+        let axes = [
+            vector(1.0, 0.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+            vector(0.0, 0.0, 1.0),
+        ]
+        .map(|axis| transform * axis);
+        let lengths = axes.map(|axis| axis.magnitude());
+        let longest = lengths.iter().fold(0.0, |a: f64, &b| a.max(b));
+        let even = lengths.iter().all(|&length| length.eq(longest))
+            && axes[0].dot(&axes[1]).eq(0.0)
+            && axes[0].dot(&axes[2]).eq(0.0)
+            && axes[1].dot(&axes[2]).eq(0.0);
+        let radius = if even {
+            longest
+        } else {
+            // The Frobenius norm is never smaller than how much the transformation can stretch a vector
+            lengths
+                .iter()
+                .map(|length| length * length)
+                .sum::<f64>()
+                .sqrt()
+        };
+        Some((transform * self.center, radius))
+    }
+
     // Returns the time(s) at which the `ray` intersects the sphere
     // Ray is assumed to be in object space
-    fn local_intersect(&self, ray: Ray) -> Intersections {
+    fn local_intersect(&self, ray: Ray) -> Intersections<'_> {
         let sphere_to_ray = ray.origin - self.center;
         let a = ray.direction.dot(&ray.direction);
         let b = 2.0 * ray.direction.dot(&sphere_to_ray);
@@ -46,8 +95,8 @@ impl Shape for Sphere {
         if discriminant >= 0.0 {
             let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
             let t2 = (-b + discriminant.sqrt()) / (2.0 * a);
-            intersections.push(Intersection::new(t1, *self));
-            intersections.push(Intersection::new(t2, *self));
+            intersections.push(Intersection::new(t1, self));
+            intersections.push(Intersection::new(t2, self));
         }
         intersections
     }
@@ -75,7 +124,6 @@ impl Default for Sphere {
 #[cfg(test)]
 mod sphere_tests {
     use super::*;
-    use crate::vector;
 
     #[test]
     fn sphere_ray_intersection1() {
@@ -131,8 +179,8 @@ mod sphere_tests {
         let s: Sphere = Default::default();
         let xs = s.local_intersect(r);
         assert_eq!(xs.len(), 2);
-        assert_eq!(xs[0].object, Sphere::default());
-        assert_eq!(xs[1].object, Sphere::default());
+        assert!(std::ptr::addr_eq(xs[0].object, &s));
+        assert!(std::ptr::addr_eq(xs[1].object, &s));
     }
 
     #[test]
@@ -171,4 +219,57 @@ mod sphere_tests {
         let n = s.local_normal_at(point(val, val, val));
         assert_eq!(n, n.normalize());
     }
+
+    #[test]
+    fn bounding_spheres() {
+        let mut s = Sphere::default();
+        s.set_transform(
+            Matrix::get_translation_matrix(1.0, 2.0, 3.0)
+                * Matrix::get_rotation_y_matrix(0.5)
+                * Matrix::get_scaling_matrix(2.0, 2.0, 2.0),
+        );
+        let (center, radius) = s.bounding_sphere(0.0).unwrap();
+        assert_eq!(center, point(1.0, 2.0, 3.0));
+        assert!(radius.eq(2.0));
+        // Stretched spheres are inside a bigger sphere
+        s.set_transform(Matrix::get_scaling_matrix(1.0, 3.0, 1.0));
+        let (_, radius) = s.bounding_sphere(0.0).unwrap();
+        assert!(radius >= 3.0);
+    }
+
+    fn moving_sphere() -> Sphere {
+        let mut s = Sphere::default();
+        s.set_motion(
+            Motion::new(Matrix::I(), Matrix::get_translation_matrix(5.0, 0.0, 0.0)).unwrap(),
+        );
+        s
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_ray_time() {
+        let s = moving_sphere();
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(s.intersect(r).len(), 2);
+        assert_eq!(s.intersect(r.with_time(1.0)).len(), 0);
+        assert_eq!(
+            s.intersect(Ray::new(point(5.0, 0.0, -5.0), vector(0.0, 0.0, 1.0)).with_time(1.0))
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn normal_of_moving_sphere() {
+        let s = moving_sphere();
+        let n = s.normal_at_time(point(5.0, 1.0, 0.0), 1.0);
+        assert_eq!(n, vector(0.0, 1.0, 0.0));
+        assert_eq!(s.normal_at(point(1.0, 0.0, 0.0)), vector(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn bounding_sphere_of_moving_sphere() {
+        let s = moving_sphere();
+        let (center, _) = s.bounding_sphere(0.5).unwrap();
+        assert_eq!(center, point(2.5, 0.0, 0.0));
+    }
 }
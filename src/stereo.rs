@@ -0,0 +1,238 @@
+use crate::utils::invalid_input;
+use crate::Camera;
+use crate::Canvas;
+use crate::Color;
+use crate::Projection;
+use crate::Ray;
+use crate::World;
+use std::io;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+// How the images of both eyes are put in a single canvas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    // Left eye image on the left half, right eye image on the right half, for VR headsets and 3D TVs
+    SideBySide,
+    // Red channel from the left eye, green and blue from the right eye, for red-cyan glasses
+    Anaglyph,
+}
+
+// Two perspective cameras side by side, like a pair of eyes
+// The eyes look in parallel directions with their views shifted (off-axis), so that both images line up
+// at the `convergence` distance. Objects there appear at the depth of the screen, closer objects pop out of it
+// Uses the resolution, transformation, `samples`, `seed` and shutter of `camera`, which is between the eyes
+// Depth of field of the camera is not used, each eye is a pinhole
+pub struct StereoCamera {
+    camera: Camera, // Always has a perspective projection, so it can't be changed once in the rig
+    pub interocular: f64, // Distance between the eyes, about 0.065 for a person in a scene in meters
+    pub convergence: f64, // Distance in front of the camera where the images of both eyes meet
+}
+
+impl StereoCamera {
+    // Fails when the camera doesn't use `Projection::Perspective`, the other projections ignore where
+    // the eyes are, so, both eyes would see the same image
+    pub fn new(camera: Camera, interocular: f64, convergence: f64) -> io::Result<Self> {
+        if camera.projection != Projection::Perspective {
+            return Err(invalid_input(
+                "stereo cameras need a perspective projection, with the others both eyes see the same image",
+            ));
+        }
+        Ok(Self {
+            camera,
+            interocular,
+            convergence,
+        })
+    }
+
+    pub fn get_camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    // Ray of the eye through the position (x, y) of its canvas, measured in pixels from the top left corner
+    pub fn ray_for_position(&self, eye: Eye, x: f64, y: f64, time: f64) -> Ray {
+        // +x is on the left in camera space
+        let offset = match eye {
+            Eye::Left => self.interocular / 2.0,
+            Eye::Right => -self.interocular / 2.0,
+        };
+        self.camera
+            .ray_from_lens(x, y, offset, 0.0, self.convergence, time)
+    }
+
+    // Renders the image seen by one eye
+    pub fn render_eye(&self, world: &World, eye: Eye) -> Canvas {
+        let camera = &self.camera;
+        let (width, height) = (camera.get_hsize() as usize, camera.get_vsize() as usize);
+        let pinhole = camera.samples <= 1 && camera.shutter_close <= camera.shutter_open;
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if pinhole {
+                    let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+                    world.color_at(&self.ray_for_position(eye, x, y, camera.shutter_open))
+                } else {
+                    camera.sample_pixel(
+                        x,
+                        y,
+                        camera.samples,
+                        camera.seed,
+                        |x, y, rng| self.ray_for_position(eye, x, y, camera.sample_time(rng)),
+                        |ray, _| world.color_at(ray),
+                    )
+                };
+                canvas.write_pixel(x, y, color);
+            }
+        }
+        canvas
+    }
+
+    // Renders both eyes into one canvas
+    pub fn render(&self, world: &World, layout: StereoLayout) -> Canvas {
+        let left = self.render_eye(world, Eye::Left);
+        let right = self.render_eye(world, Eye::Right);
+        match layout {
+            StereoLayout::SideBySide => side_by_side(&left, &right),
+            StereoLayout::Anaglyph => anaglyph(&left, &right),
+        }
+    }
+}
+
+// Canvas twice as wide, with `left` on the left half and `right` on the right half
+// Both canvases must have the same height
+pub fn side_by_side(left: &Canvas, right: &Canvas) -> Canvas {
+    assert_eq!(
+        left.height, right.height,
+        "Canvases put side by side must have the same height"
+    );
+    let mut canvas = Canvas::new(left.width + right.width, left.height);
+    for y in 0..left.height {
+        for x in 0..left.width {
+            canvas.write_pixel(x, y, left.pixel_at(x, y));
+        }
+    }
+    for y in 0..right.height {
+        for x in 0..right.width {
+            canvas.write_pixel(left.width + x, y, right.pixel_at(x, y));
+        }
+    }
+    canvas
+}
+
+// Red-cyan anaglyph of two canvases of the same size
+pub fn anaglyph(left: &Canvas, right: &Canvas) -> Canvas {
+    assert_eq!(
+        (left.width, left.height),
+        (right.width, right.height),
+        "Canvases of an anaglyph must have the same size"
+    );
+    let mut canvas = Canvas::new(left.width, left.height);
+    for y in 0..left.height {
+        for x in 0..left.width {
+            let (l, r) = (left.pixel_at(x, y), right.pixel_at(x, y));
+            canvas.write_pixel(x, y, Color::new(l.red, r.green, r.blue));
+        }
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod stereo_tests {
+    use super::*;
+    use crate::point;
+    use crate::test_fixtures::camera_facing_origin;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn rig() -> StereoCamera {
+        let camera = camera_facing_origin(11, 11)
+            .field_of_view(FRAC_PI_2)
+            .build();
+        StereoCamera::new(camera, 0.5, 4.0).unwrap()
+    }
+
+    #[test]
+    fn eyes_are_apart_and_meet_at_convergence() {
+        let rig = rig();
+        let left = rig.ray_for_position(Eye::Left, 3.2, 6.1, 0.0);
+        let right = rig.ray_for_position(Eye::Right, 3.2, 6.1, 0.0);
+        // Looking towards +z, the left eye is towards -x
+        assert_eq!(left.origin, point(-0.25, 0.0, -5.0));
+        assert_eq!(right.origin, point(0.25, 0.0, -5.0));
+        // Both rays reach the same point at the convergence plane, z = -1
+        let at_plane = |r: Ray| r.position((-1.0 - r.origin.z) / r.direction.z);
+        assert_eq!(at_plane(left), at_plane(right));
+        // Eyes keep looking straight ahead, only the view is shifted
+        let left_center = rig.ray_for_position(Eye::Left, 5.5, 5.5, 0.0);
+        assert_eq!(at_plane(left_center), point(-0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn eyes_see_objects_shifted() {
+        let w = World::default();
+        let mut rig = rig();
+        // Spheres are in front of the convergence distance, so, they pop out of the screen
+        rig.interocular = 2.0;
+        rig.convergence = 10.0;
+        let left = rig.render_eye(&w, Eye::Left);
+        let right = rig.render_eye(&w, Eye::Right);
+        let first_lit = |canvas: &Canvas| {
+            (0..11)
+                .position(|x| canvas.pixel_at(x, 5) != Color::black())
+                .unwrap()
+        };
+        // Closer objects are further to the right for the left eye
+        assert!(first_lit(&left) > first_lit(&right));
+    }
+
+    #[test]
+    fn side_by_side_layout() {
+        let w = World::default();
+        let rig = rig();
+        let canvas = rig.render(&w, StereoLayout::SideBySide);
+        assert_eq!((canvas.width, canvas.height), (22, 11));
+        assert_eq!(
+            canvas.pixel_at(5, 5),
+            rig.render_eye(&w, Eye::Left).pixel_at(5, 5)
+        );
+        assert_eq!(
+            canvas.pixel_at(16, 5),
+            rig.render_eye(&w, Eye::Right).pixel_at(5, 5)
+        );
+    }
+
+    #[test]
+    fn anaglyph_takes_red_from_left_and_cyan_from_right() {
+        let mut left = Canvas::new(1, 1);
+        left.fill(Color::new(0.1, 0.2, 0.3));
+        let mut right = Canvas::new(1, 1);
+        right.fill(Color::new(0.4, 0.5, 0.6));
+        assert_eq!(
+            anaglyph(&left, &right).pixel_at(0, 0),
+            Color::new(0.1, 0.5, 0.6)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "same size")]
+    fn anaglyph_of_different_sizes() {
+        anaglyph(&Canvas::new(2, 1), &Canvas::new(1, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "same height")]
+    fn side_by_side_of_different_heights() {
+        side_by_side(&Canvas::new(1, 2), &Canvas::new(1, 1));
+    }
+
+    #[test]
+    fn stereo_needs_perspective_projection() {
+        let mut camera = Camera::new(4, 2, FRAC_PI_2);
+        camera.projection = Projection::Equirectangular;
+        let error = StereoCamera::new(camera, 0.065, 2.0).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
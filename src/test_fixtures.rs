@@ -0,0 +1,22 @@
+// Images and cameras shared by the tests of several modules
+use crate::{point, vector, Camera, CameraBuilder, Canvas, Color};
+
+// Canvas whose pixels tell where they are, the red of each is its column and the green its row
+pub(crate) fn numbered_canvas(width: usize, height: usize) -> Canvas {
+    let mut c = Canvas::new(width, height);
+    for row in 0..height {
+        for col in 0..width {
+            c.write_pixel(col, row, Color::new(col as f64, row as f64, 0.0));
+        }
+    }
+    c
+}
+
+// Camera 5 units in front of the origin looking at it, like at the spheres of `World::default()`
+pub(crate) fn camera_facing_origin(hsize: u32, vsize: u32) -> CameraBuilder {
+    Camera::builder(hsize, vsize).look_at(
+        point(0.0, 0.0, -5.0),
+        point(0.0, 0.0, 0.0),
+        vector(0.0, 1.0, 0.0),
+    )
+}
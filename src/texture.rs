@@ -0,0 +1,391 @@
+use crate::Canvas;
+use crate::Color;
+use crate::Matrix;
+use crate::Pattern;
+use crate::Tuple;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+// A 2D pattern addressed by the `u` and `v` coordinates, both are between 0 and 1
+// `u` goes from left to right and `v` goes from bottom to top
+pub trait UVPattern: Debug {
+    fn uv_pattern_at(&self, u: f64, v: f64) -> Color;
+}
+
+// How an image texture is sampled when (u, v) does not fall exactly on a pixel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,  // Take the color of the closest pixel
+    Bilinear, // Blend the 4 surrounding pixels based on the distance from them
+}
+
+// UV pattern which takes its colors from a canvas, eg.- an earth map loaded from a PPM file
+#[derive(Debug)]
+pub struct UVImage {
+    pub canvas: Canvas,
+    pub filter: TextureFilter,
+}
+
+impl UVImage {
+    pub fn new(canvas: Canvas, filter: TextureFilter) -> Self {
+        Self { canvas, filter }
+    }
+}
+
+impl UVPattern for UVImage {
+    fn uv_pattern_at(&self, u: f64, v: f64) -> Color {
+        // Canvas rows go from top to bottom, so, flip `v`
+        let x = u.clamp(0.0, 1.0) * (self.canvas.width - 1) as f64;
+        let y = (1.0 - v.clamp(0.0, 1.0)) * (self.canvas.height - 1) as f64;
+
+        match self.filter {
+            TextureFilter::Nearest => self.canvas.pixel_at(x.round() as usize, y.round() as usize),
+            TextureFilter::Bilinear => {
+                let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+                let x1 = (x0 + 1).min(self.canvas.width - 1);
+                let y1 = (y0 + 1).min(self.canvas.height - 1);
+                let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+
+                let top =
+                    self.canvas.pixel_at(x0, y0) * (1.0 - tx) + self.canvas.pixel_at(x1, y0) * tx;
+                let bottom =
+                    self.canvas.pixel_at(x0, y1) * (1.0 - tx) + self.canvas.pixel_at(x1, y1) * tx;
+                top * (1.0 - ty) + bottom * ty
+            }
+        }
+    }
+}
+
+// Ways to convert a 3D point on the surface of a shape to the (u, v) coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UVMapping {
+    Spherical,   // Wrap around a unit sphere, like an earth map
+    Planar,      // Repeat over the xz plane every 1 unit
+    Cylindrical, // Wrap around the y axis and repeat along it every 1 unit
+}
+
+impl UVMapping {
+    pub fn map(&self, p: Tuple) -> (f64, f64) {
+        match self {
+            UVMapping::Spherical => spherical_map(p),
+            UVMapping::Planar => planar_map(p),
+            UVMapping::Cylindrical => cylindrical_map(p),
+        }
+    }
+}
+
+// `u` is the angle around the y axis and `v` is the angle from the south pole
+// Works for any point as only its direction from the origin matters
+pub fn spherical_map(p: Tuple) -> (f64, f64) {
+    let theta = p.x.atan2(p.z); // -pi to pi, increases clockwise when viewed from above
+    let radius = (p.x * p.x + p.y * p.y + p.z * p.z).sqrt();
+    let phi = (p.y / radius).acos(); // 0 to pi
+
+    let raw_u = theta / (2.0 * PI); // -0.5 to 0.5
+    let u = 1.0 - (raw_u + 0.5); // Fix the direction so that it increases counter-clockwise
+    let v = 1.0 - phi / PI; // Make the south pole 0
+    (u, v)
+}
+
+pub fn planar_map(p: Tuple) -> (f64, f64) {
+    (p.x.rem_euclid(1.0), p.z.rem_euclid(1.0))
+}
+
+pub fn cylindrical_map(p: Tuple) -> (f64, f64) {
+    let theta = p.x.atan2(p.z);
+    let raw_u = theta / (2.0 * PI);
+    let u = 1.0 - (raw_u + 0.5);
+    (u, p.y.rem_euclid(1.0))
+}
+
+// A pattern that wraps a UV pattern around a shape using one of the mappings
+#[derive(Debug)]
+pub struct TextureMap {
+    uv_pattern: Box<dyn UVPattern>,
+    mapping: UVMapping,
+    transform: Matrix,
+}
+
+impl TextureMap {
+    pub fn new(uv_pattern: Box<dyn UVPattern>, mapping: UVMapping) -> Self {
+        Self {
+            uv_pattern,
+            mapping,
+            transform: Matrix::I(),
+        }
+    }
+}
+
+impl Pattern for TextureMap {
+    fn set_transform(&mut self, m: Matrix) {
+        self.transform = m;
+    }
+
+    fn get_transform(&self) -> Matrix {
+        self.transform
+    }
+
+    fn local_pattern_at(&self, p: Tuple) -> Color {
+        let (u, v) = self.mapping.map(p);
+        self.uv_pattern.uv_pattern_at(u, v)
+    }
+}
+
+// Faces of an axis aligned cube centered at the origin, also used as the index of the face in `CubeMap`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CubeFace {
+    Left,  // -x
+    Front, // +z
+    Right, // +x
+    Back,  // -z
+    Up,    // +y
+    Down,  // -y
+}
+
+impl CubeFace {
+    // The face is decided by the component of the point with the largest magnitude
+    pub fn from_point(p: Tuple) -> Self {
+        let coord = p.x.abs().max(p.y.abs()).max(p.z.abs());
+        if coord == p.x {
+            CubeFace::Right
+        } else if coord == -p.x {
+            CubeFace::Left
+        } else if coord == p.y {
+            CubeFace::Up
+        } else if coord == -p.y {
+            CubeFace::Down
+        } else if coord == p.z {
+            CubeFace::Front
+        } else {
+            CubeFace::Back
+        }
+    }
+
+    // (u, v) of the point on this face of a cube from -1 to 1 on every axis
+    // Each face is seen from outside the cube, eg.- `Up` is seen from above with `Back` at the top
+    pub fn uv(&self, p: Tuple) -> (f64, f64) {
+        match self {
+            CubeFace::Front => (
+                (p.x + 1.0).rem_euclid(2.0) / 2.0,
+                (p.y + 1.0).rem_euclid(2.0) / 2.0,
+            ),
+            CubeFace::Back => (
+                (1.0 - p.x).rem_euclid(2.0) / 2.0,
+                (p.y + 1.0).rem_euclid(2.0) / 2.0,
+            ),
+            CubeFace::Left => (
+                (p.z + 1.0).rem_euclid(2.0) / 2.0,
+                (p.y + 1.0).rem_euclid(2.0) / 2.0,
+            ),
+            CubeFace::Right => (
+                (1.0 - p.z).rem_euclid(2.0) / 2.0,
+                (p.y + 1.0).rem_euclid(2.0) / 2.0,
+            ),
+            CubeFace::Up => (
+                (p.x + 1.0).rem_euclid(2.0) / 2.0,
+                (1.0 - p.z).rem_euclid(2.0) / 2.0,
+            ),
+            CubeFace::Down => (
+                (p.x + 1.0).rem_euclid(2.0) / 2.0,
+                (p.z + 1.0).rem_euclid(2.0) / 2.0,
+            ),
+        }
+    }
+}
+
+// A pattern with a separate UV pattern for every face of a cube, eg.- the 6 images of a skybox
+// Points which are not on a cube are projected on it from the origin
+#[derive(Debug)]
+pub struct CubeMap {
+    faces: [Box<dyn UVPattern>; 6], // In the order of `CubeFace`
+    transform: Matrix,
+}
+
+impl CubeMap {
+    pub fn new(
+        left: Box<dyn UVPattern>,
+        front: Box<dyn UVPattern>,
+        right: Box<dyn UVPattern>,
+        back: Box<dyn UVPattern>,
+        up: Box<dyn UVPattern>,
+        down: Box<dyn UVPattern>,
+    ) -> Self {
+        Self {
+            faces: [left, front, right, back, up, down],
+            transform: Matrix::I(),
+        }
+    }
+}
+
+impl Pattern for CubeMap {
+    fn set_transform(&mut self, m: Matrix) {
+        self.transform = m;
+    }
+
+    fn get_transform(&self) -> Matrix {
+        self.transform
+    }
+
+    fn local_pattern_at(&self, p: Tuple) -> Color {
+        let face = CubeFace::from_point(p);
+        // Scale the point on to the cube so that the face uv is between 0 and 1
+        let coord = p.x.abs().max(p.y.abs()).max(p.z.abs());
+        let (u, v) = face.uv(p / coord);
+        self.faces[face as usize].uv_pattern_at(u, v)
+    }
+}
+
+#[cfg(test)]
+mod texture_tests {
+    use super::*;
+    use crate::{point, Compare};
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    // 2x2 canvas with a different color at each pixel
+    fn quad_canvas() -> Canvas {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.write_pixel(0, 0, Color::red());
+        canvas.write_pixel(1, 0, Color::green());
+        canvas.write_pixel(0, 1, Color::blue());
+        canvas.write_pixel(1, 1, Color::white());
+        canvas
+    }
+
+    fn solid(color: Color) -> Box<dyn UVPattern> {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.fill(color);
+        Box::new(UVImage::new(canvas, TextureFilter::Nearest))
+    }
+
+    fn assert_uv(uv: (f64, f64), expected: (f64, f64)) {
+        assert!(uv.0.eq(expected.0), "u: {} != {}", uv.0, expected.0);
+        assert!(uv.1.eq(expected.1), "v: {} != {}", uv.1, expected.1);
+    }
+
+    #[test]
+    fn spherical_mapping_on_3d_point() {
+        assert_uv(spherical_map(point(0.0, 0.0, -1.0)), (0.0, 0.5));
+        assert_uv(spherical_map(point(1.0, 0.0, 0.0)), (0.25, 0.5));
+        assert_uv(spherical_map(point(0.0, 0.0, 1.0)), (0.5, 0.5));
+        assert_uv(spherical_map(point(-1.0, 0.0, 0.0)), (0.75, 0.5));
+        assert_uv(spherical_map(point(0.0, 1.0, 0.0)), (0.5, 1.0));
+        assert_uv(spherical_map(point(0.0, -1.0, 0.0)), (0.5, 0.0));
+        assert_uv(
+            spherical_map(point(FRAC_1_SQRT_2, FRAC_1_SQRT_2, 0.0)),
+            (0.25, 0.75),
+        );
+    }
+
+    #[test]
+    fn planar_mapping_on_3d_point() {
+        assert_uv(planar_map(point(0.25, 0.0, 0.5)), (0.25, 0.5));
+        assert_uv(planar_map(point(0.25, 0.0, -0.25)), (0.25, 0.75));
+        assert_uv(planar_map(point(0.25, 0.5, -0.25)), (0.25, 0.75));
+        assert_uv(planar_map(point(1.25, 0.0, 0.5)), (0.25, 0.5));
+        assert_uv(planar_map(point(0.25, 0.0, -1.75)), (0.25, 0.25));
+        assert_uv(planar_map(point(1.0, 0.0, -1.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn cylindrical_mapping_on_3d_point() {
+        assert_uv(cylindrical_map(point(0.0, 0.0, -1.0)), (0.0, 0.0));
+        assert_uv(cylindrical_map(point(0.0, 0.5, -1.0)), (0.0, 0.5));
+        assert_uv(cylindrical_map(point(0.0, 1.0, -1.0)), (0.0, 0.0));
+        assert_uv(cylindrical_map(point(1.0, 0.5, 0.0)), (0.25, 0.5));
+        assert_uv(cylindrical_map(point(0.0, -0.25, 1.0)), (0.5, 0.75));
+        assert_uv(cylindrical_map(point(-1.0, 1.25, 0.0)), (0.75, 0.25));
+    }
+
+    #[test]
+    fn identifying_face_of_cube_from_point() {
+        assert_eq!(
+            CubeFace::from_point(point(-1.0, 0.5, -0.25)),
+            CubeFace::Left
+        );
+        assert_eq!(
+            CubeFace::from_point(point(1.1, -0.75, 0.8)),
+            CubeFace::Right
+        );
+        assert_eq!(CubeFace::from_point(point(0.1, 0.6, 0.9)), CubeFace::Front);
+        assert_eq!(CubeFace::from_point(point(-0.7, 0.0, -2.0)), CubeFace::Back);
+        assert_eq!(CubeFace::from_point(point(0.5, 1.0, 0.9)), CubeFace::Up);
+        assert_eq!(CubeFace::from_point(point(-0.2, -1.3, 1.1)), CubeFace::Down);
+    }
+
+    #[test]
+    fn uv_mapping_of_cube_faces() {
+        assert_uv(CubeFace::Front.uv(point(-0.5, 0.5, 1.0)), (0.25, 0.75));
+        assert_uv(CubeFace::Front.uv(point(0.5, -0.5, 1.0)), (0.75, 0.25));
+        assert_uv(CubeFace::Back.uv(point(0.5, 0.5, -1.0)), (0.25, 0.75));
+        assert_uv(CubeFace::Back.uv(point(-0.5, -0.5, -1.0)), (0.75, 0.25));
+        assert_uv(CubeFace::Left.uv(point(-1.0, 0.5, -0.5)), (0.25, 0.75));
+        assert_uv(CubeFace::Left.uv(point(-1.0, -0.5, 0.5)), (0.75, 0.25));
+        assert_uv(CubeFace::Right.uv(point(1.0, 0.5, 0.5)), (0.25, 0.75));
+        assert_uv(CubeFace::Right.uv(point(1.0, -0.5, -0.5)), (0.75, 0.25));
+        assert_uv(CubeFace::Up.uv(point(-0.5, 1.0, -0.5)), (0.25, 0.75));
+        assert_uv(CubeFace::Up.uv(point(0.5, 1.0, 0.5)), (0.75, 0.25));
+        assert_uv(CubeFace::Down.uv(point(-0.5, -1.0, 0.5)), (0.25, 0.75));
+        assert_uv(CubeFace::Down.uv(point(0.5, -1.0, -0.5)), (0.75, 0.25));
+    }
+
+    #[test]
+    fn nearest_filter_picks_closest_pixel() {
+        let image = UVImage::new(quad_canvas(), TextureFilter::Nearest);
+        assert_eq!(image.uv_pattern_at(0.0, 1.0), Color::red());
+        assert_eq!(image.uv_pattern_at(0.9, 0.9), Color::green());
+        assert_eq!(image.uv_pattern_at(0.2, 0.3), Color::blue());
+        assert_eq!(image.uv_pattern_at(1.0, 0.0), Color::white());
+    }
+
+    #[test]
+    fn bilinear_filter_blends_surrounding_pixels() {
+        let image = UVImage::new(quad_canvas(), TextureFilter::Bilinear);
+        assert_eq!(image.uv_pattern_at(0.0, 1.0), Color::red());
+        assert_eq!(image.uv_pattern_at(0.5, 1.0), Color::new(0.5, 0.5, 0.0));
+        assert_eq!(image.uv_pattern_at(0.0, 0.5), Color::new(0.5, 0.0, 0.5));
+        assert_eq!(image.uv_pattern_at(0.5, 0.5), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(image.uv_pattern_at(1.0, 0.0), Color::white());
+    }
+
+    #[test]
+    fn texture_map_with_spherical_mapping() {
+        let image = UVImage::new(quad_canvas(), TextureFilter::Nearest);
+        let texture = TextureMap::new(Box::new(image), UVMapping::Spherical);
+        // Top half of the sphere uses the top row of the canvas
+        assert_eq!(
+            texture.local_pattern_at(point(0.0, 0.9, -0.1)),
+            Color::red()
+        );
+        assert_eq!(
+            texture.local_pattern_at(point(0.0, -0.9, 0.1)),
+            Color::white()
+        );
+    }
+
+    #[test]
+    fn cube_map_uses_pattern_of_each_face() {
+        let cube = CubeMap::new(
+            solid(Color::red()),
+            solid(Color::green()),
+            solid(Color::blue()),
+            solid(Color::white()),
+            solid(Color::new(1.0, 1.0, 0.0)),
+            solid(Color::new(0.0, 1.0, 1.0)),
+        );
+        assert_eq!(cube.local_pattern_at(point(-1.0, 0.5, -0.25)), Color::red());
+        assert_eq!(cube.local_pattern_at(point(0.1, 0.6, 0.9)), Color::green());
+        assert_eq!(cube.local_pattern_at(point(1.1, -0.75, 0.8)), Color::blue());
+        assert_eq!(
+            cube.local_pattern_at(point(-0.7, 0.0, -2.0)),
+            Color::white()
+        );
+        assert_eq!(
+            cube.local_pattern_at(point(0.5, 1.0, 0.9)),
+            Color::new(1.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            cube.local_pattern_at(point(-0.2, -1.3, 1.1)),
+            Color::new(0.0, 1.0, 1.0)
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::utils::invalid_input;
+use crate::Canvas;
+use crate::Color;
+use std::io;
+
+// How colors brighter than 1.0 are squeezed into what a screen can show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapping {
+    // Colors are left as they are, and clipped by 8 bit formats
+    Clip,
+    // L / (1 + L) of the luminance, keeps the hue, never gets to white
+    // Channels of saturated colors can still go above 1.0
+    Reinhard,
+    // Reinhard which reaches white at the luminance `white`, which has to be positive
+    ReinhardExtended { white: f64 },
+    // Fit of the filmic curve of ACES by Krzysztof Narkowicz, a film like contrast that desaturates highlights
+    AcesFilmic,
+}
+
+// How the tone mapped linear values are encoded for the file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    Linear,     // As the ray tracer computes them, looks too dark on screens
+    Srgb,       // The curve of the sRGB standard, what screens and most viewers expect
+    Gamma(f64), // Simple power curve, value^(1 / gamma), eg.- 2.2, has to be positive
+}
+
+// Post process between the float colors of a render and an 8 or 16 bit image: exposure,
+// then tone mapping, then encoding. The default leaves the colors as they are
+// The image writers check the settings before using them, as the fields can be set directly
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMap {
+    pub exposure: f64, // In stops, every stop doubles the light
+    pub tone_mapping: ToneMapping,
+    pub encoding: Encoding,
+}
+
+impl ToneMap {
+    // Fails with `InvalidInput` on settings which can't map colors, see `check`
+    pub fn new(exposure: f64, tone_mapping: ToneMapping, encoding: Encoding) -> io::Result<Self> {
+        let tone_map = Self {
+            exposure,
+            tone_mapping,
+            encoding,
+        };
+        tone_map.check()?;
+        Ok(tone_map)
+    }
+
+    // The exposure has to be finite, and the white of extended Reinhard and the gamma positive
+    pub fn check(&self) -> io::Result<()> {
+        if !self.exposure.is_finite() {
+            return Err(invalid_input("exposure has to be finite"));
+        }
+        if let ToneMapping::ReinhardExtended { white } = self.tone_mapping {
+            if white.is_nan() || white <= 0.0 {
+                return Err(invalid_input(
+                    "white of extended Reinhard has to be positive",
+                ));
+            }
+        }
+        if let Encoding::Gamma(gamma) = self.encoding {
+            if gamma.is_nan() || gamma <= 0.0 {
+                return Err(invalid_input("gamma has to be positive"));
+            }
+        }
+        Ok(())
+    }
+
+    // Color to write to the image
+    pub fn apply_color(&self, color: Color) -> Color {
+        let color = color * 2f64.powf(self.exposure);
+        let color = match self.tone_mapping {
+            ToneMapping::Clip => color,
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapping::ReinhardExtended { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapping::AcesFilmic => {
+                Color::new(aces(color.red), aces(color.green), aces(color.blue))
+            }
+        };
+        let encode = |value: f64| match self.encoding {
+            Encoding::Linear => value,
+            Encoding::Srgb => srgb_encode(value),
+            Encoding::Gamma(gamma) => value.max(0.0).powf(1.0 / gamma),
+        };
+        Color::new(encode(color.red), encode(color.green), encode(color.blue))
+    }
+
+    pub fn apply(&self, canvas: &Canvas) -> Canvas {
+        let mut mapped = canvas.clone();
+        for color in mapped.data.iter_mut() {
+            *color = self.apply_color(*color);
+        }
+        mapped
+    }
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapping: ToneMapping::Clip,
+            encoding: Encoding::Linear,
+        }
+    }
+}
+
+// Color with its luminance changed by `curve`, keeping its hue
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::black();
+    }
+    color * (curve(luminance) / luminance)
+}
+
+fn aces(x: f64) -> f64 {
+    let x = x.max(0.0);
+    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+// Linear value to the sRGB curve, with its linear part near black
+pub fn srgb_encode(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Inverse of `srgb_encode`, eg.- for textures painted on screen
+pub fn srgb_decode(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tone_map_tests {
+    use super::*;
+    use crate::Compare;
+
+    #[test]
+    fn default_leaves_colors_alone() {
+        let c = Color::new(2.0, 0.5, -0.1);
+        assert_eq!(ToneMap::default().apply_color(c), c);
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tone_map = ToneMap::new(2.0, ToneMapping::Clip, Encoding::Linear).unwrap();
+        assert_eq!(
+            tone_map.apply_color(Color::new(0.1, 0.2, 0.25)),
+            Color::new(0.4, 0.8, 1.0)
+        );
+        let tone_map = ToneMap::new(-1.0, ToneMapping::Clip, Encoding::Linear).unwrap();
+        assert_eq!(
+            tone_map.apply_color(Color::white()),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn reinhard_keeps_hue() {
+        let tone_map = ToneMap::new(0.0, ToneMapping::Reinhard, Encoding::Linear).unwrap();
+        let bright = Color::new(8.0, 4.0, 2.0);
+        let c = tone_map.apply_color(bright);
+        assert!((c.red / c.green).eq(2.0));
+        let l = bright.luminance();
+        assert!(c.luminance().eq(l / (1.0 + l)));
+        assert_eq!(tone_map.apply_color(Color::black()), Color::black());
+    }
+
+    #[test]
+    fn extended_reinhard_reaches_white() {
+        let white = 4.0;
+        let tone_map = ToneMap::new(
+            0.0,
+            ToneMapping::ReinhardExtended { white },
+            Encoding::Linear,
+        )
+        .unwrap();
+        let c = tone_map.apply_color(Color::new(white, white, white));
+        assert_eq!(c, Color::white());
+    }
+
+    #[test]
+    fn settings_which_cant_map_colors_are_errors() {
+        for white in [0.0, -1.0, f64::NAN] {
+            let tone_map = ToneMap::new(
+                0.0,
+                ToneMapping::ReinhardExtended { white },
+                Encoding::Linear,
+            );
+            assert_eq!(tone_map.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(ToneMap::new(0.0, ToneMapping::Clip, Encoding::Gamma(0.0)).is_err());
+        assert!(ToneMap::new(f64::INFINITY, ToneMapping::Clip, Encoding::Linear).is_err());
+
+        let tone_map = ToneMap {
+            tone_mapping: ToneMapping::ReinhardExtended { white: 0.0 },
+            ..Default::default()
+        };
+        assert!(tone_map.check().is_err());
+        assert!(ToneMap::default().check().is_ok());
+    }
+
+    #[test]
+    fn aces_curve() {
+        let tone_map = ToneMap::new(0.0, ToneMapping::AcesFilmic, Encoding::Linear).unwrap();
+        assert_eq!(tone_map.apply_color(Color::black()), Color::black());
+        let c = tone_map.apply_color(Color::new(0.18, 1.0, 100.0));
+        assert!(c.red > 0.2 && c.red < 0.3);
+        assert!(c.green > 0.75 && c.green < 0.85);
+        assert!(c.blue.eq(1.0));
+    }
+
+    #[test]
+    fn srgb_curve() {
+        assert!(srgb_encode(0.0).eq(0.0));
+        assert!(srgb_encode(1.0).eq(1.0));
+        assert!(srgb_encode(0.002).eq(0.02584));
+        assert!(srgb_encode(0.18).eq(0.46135));
+        for v in [0.001, 0.2, 0.7] {
+            assert!(srgb_decode(srgb_encode(v)).eq(v));
+        }
+        let tone_map = ToneMap::new(0.0, ToneMapping::Clip, Encoding::Gamma(2.0)).unwrap();
+        assert_eq!(
+            tone_map.apply_color(Color::new(0.25, 0.0, 1.0)),
+            Color::new(0.5, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn tone_mapping_a_canvas() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(1, 0, Color::new(0.18, 0.18, 0.18));
+        let tone_map = ToneMap::new(1.0, ToneMapping::Clip, Encoding::Srgb).unwrap();
+        let mapped = tone_map.apply(&canvas);
+        assert_eq!(mapped.pixel_at(0, 0), Color::black());
+        let v = srgb_encode(0.36);
+        assert_eq!(mapped.pixel_at(1, 0), Color::new(v, v, v));
+    }
+}
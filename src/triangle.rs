@@ -0,0 +1,358 @@
+use crate::utils::EPSILON;
+use crate::vertex_tangents;
+use crate::Material;
+use crate::Matrix;
+use crate::Motion;
+use crate::Ray;
+use crate::Shape;
+use crate::Tangent;
+use crate::Tuple;
+use crate::{Intersection, Intersections};
+
+// Corner of a triangle, everything is in object space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: Tuple,
+    pub normal: Tuple, // Unit normal, interpolated over the triangle for smooth shading
+    pub uv: (f64, f64), // Texture coordinates, looked up by normal maps
+    pub tangent: Tangent, // Direction of increasing `u`, see `vertex_tangents`
+}
+
+// Triangle, on its own or as a part of a mesh, see `Triangle::mesh`
+// Intersections with it store the barycentric coordinates of the hit in (u, v), the weights of the second and
+// third vertex
+#[derive(Clone, Debug, PartialEq)]
+pub struct Triangle {
+    vertices: [Vertex; 3],
+    e1: Tuple,              // Edge from the first vertex to the second
+    e2: Tuple,              // Edge from the first vertex to the third
+    normal: Tuple,          // Normal of the flat triangle
+    transform: Matrix,      // Transformation matrix
+    pub material: Material, // Material of the triangle
+    motion: Option<Motion>, // Overrides `transform` for a moving triangle
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vertex; 3]) -> Self {
+        let e1 = vertices[1].position - vertices[0].position;
+        let e2 = vertices[2].position - vertices[0].position;
+        Self {
+            vertices,
+            e1,
+            e2,
+            normal: e2.cross(&e1).normalize(),
+            transform: Matrix::I(),
+            motion: None,
+            material: Material::default(),
+        }
+    }
+
+    // Flat triangle, with the texture coordinates (0, 0), (1, 0) and (0, 1) at its corners
+    pub fn flat(p1: Tuple, p2: Tuple, p3: Tuple) -> Self {
+        let positions = [p1, p2, p3];
+        let normal = (p3 - p1).cross(&(p2 - p1)).normalize();
+        let uvs = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+        let tangents = vertex_tangents(&positions, &[normal; 3], &uvs, &[[0, 1, 2]]);
+        Self::new([0, 1, 2].map(|i| Vertex {
+            position: positions[i],
+            normal,
+            uv: uvs[i],
+            tangent: tangents[i],
+        }))
+    }
+
+    // Triangles of a mesh, sharing the `material`, with the tangents of the vertices computed by `vertex_tangents`
+    //
+    // * `positions`: position of every vertex
+    // * `normals`: unit normal of every vertex
+    // * `uvs`: texture coordinates (u, v) of every vertex
+    // * `triangles`: indices of the 3 vertices of every triangle, counter clockwise seen from the front
+    pub fn mesh(
+        positions: &[Tuple],
+        normals: &[Tuple],
+        uvs: &[(f64, f64)],
+        triangles: &[[usize; 3]],
+        material: Material,
+    ) -> Vec<Triangle> {
+        let tangents = vertex_tangents(positions, normals, uvs, triangles);
+        triangles
+            .iter()
+            .map(|indices| {
+                let mut triangle = Triangle::new(indices.map(|i| Vertex {
+                    position: positions[i],
+                    normal: normals[i],
+                    uv: uvs[i],
+                    tangent: tangents[i],
+                }));
+                triangle.material = material.clone();
+                triangle
+            })
+            .collect()
+    }
+
+    pub fn get_vertices(&self) -> &[Vertex; 3] {
+        &self.vertices
+    }
+
+    // Value at the barycentric coordinates (u, v) of the hit, from the values at the vertices
+    fn interpolate<T>(&self, hit: &Intersection, value: impl Fn(&Vertex) -> T) -> T
+    where
+        T: std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T>,
+    {
+        value(&self.vertices[0]) * (1.0 - hit.u - hit.v)
+            + value(&self.vertices[1]) * hit.u
+            + value(&self.vertices[2]) * hit.v
+    }
+}
+
+impl Shape for Triangle {
+    fn set_transform(&mut self, t: Matrix) {
+        self.transform = t;
+        self.motion = None;
+    }
+
+    fn get_transform(&self) -> Matrix {
+        self.transform
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn set_motion(&mut self, motion: Motion) {
+        self.motion = Some(motion);
+    }
+
+    fn get_motion(&self) -> Option<&Motion> {
+        self.motion.as_ref()
+    }
+
+    // Sphere around the centroid, through the furthest corner
+    fn bounding_sphere(&self, time: f64) -> Option<(Tuple, f64)> {
+        let transform = self.transform_at(time);
+        let corners = self.vertices.map(|vertex| transform * vertex.position);
+        let center = (corners[0] + corners[1] + corners[2]) * (1.0 / 3.0);
+        let radius = corners
+            .iter()
+            .map(|&corner| (corner - center).magnitude())
+            .fold(0.0, f64::max);
+        Some((center, radius))
+    }
+
+    // Möller–Trumbore algorithm, ray is assumed to be in object space
+    fn local_intersect(&self, ray: Ray) -> Intersections<'_> {
+        let mut intersections = Intersections::new();
+        let dir_cross_e2 = ray.direction.cross(&self.e2);
+        let det = self.e1.dot(&dir_cross_e2);
+        if det.abs() < EPSILON {
+            // Ray is parallel to the triangle
+            return intersections;
+        }
+        let f = 1.0 / det;
+        let p1_to_origin = ray.origin - self.vertices[0].position;
+        let u = f * p1_to_origin.dot(&dir_cross_e2);
+        if !(0.0..=1.0).contains(&u) {
+            return intersections;
+        }
+        let origin_cross_e1 = p1_to_origin.cross(&self.e1);
+        let v = f * ray.direction.dot(&origin_cross_e1);
+        if v < 0.0 || u + v > 1.0 {
+            return intersections;
+        }
+        let t = f * self.e2.dot(&origin_cross_e1);
+        intersections.push(Intersection::new_with_uv(t, self, u, v));
+        intersections
+    }
+
+    fn local_normal_at(&self, _p: Tuple) -> Tuple {
+        self.normal
+    }
+
+    // Normal interpolated from the normals of the vertices
+    fn normal_at_hit(&self, _p: Tuple, hit: &Intersection, time: f64) -> Tuple {
+        let local_normal = self.interpolate(hit, |vertex| vertex.normal);
+        let mut world_normal = self.transform_at(time).inverse().transpose() * local_normal;
+        world_normal.w = 0.0;
+        world_normal.normalize()
+    }
+
+    fn tangent_at_hit(&self, hit: &Intersection, time: f64) -> Option<((f64, f64), Tangent)> {
+        let u = self.interpolate(hit, |vertex| vertex.uv.0);
+        let v = self.interpolate(hit, |vertex| vertex.uv.1);
+        // Tangents are along the surface, so, unlike normals, they are transformed like any vector
+        let transform = self.transform_at(time);
+        let mut vector = transform * self.interpolate(hit, |vertex| vertex.tangent.vector);
+        vector.w = 0.0;
+        // A mirroring transformation flips the cross product of the normal and the tangent
+        let mirrored = if transform.determinant() < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let tangent = Tangent {
+            vector: vector.normalize(),
+            handedness: self.vertices[0].tangent.handedness * mirrored,
+        };
+        Some(((u, v), tangent))
+    }
+}
+
+#[cfg(test)]
+mod triangle_tests {
+    use super::*;
+    use crate::{point, vector, Color, Light, NormalMap, UVPattern, World};
+    use std::f64::consts::FRAC_1_SQRT_2;
+    use std::rc::Rc;
+
+    fn triangle() -> Triangle {
+        Triangle::flat(
+            point(0.0, 1.0, 0.0),
+            point(-1.0, 0.0, 0.0),
+            point(1.0, 0.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn constructing_a_triangle() {
+        let t = triangle();
+        assert_eq!(t.e1, vector(-1.0, -1.0, 0.0));
+        assert_eq!(t.e2, vector(1.0, -1.0, 0.0));
+        assert_eq!(t.normal, vector(0.0, 0.0, -1.0));
+        assert_eq!(t.local_normal_at(point(0.0, 0.5, 0.0)), t.normal);
+    }
+
+    #[test]
+    fn ray_misses_a_triangle() {
+        let t = triangle();
+        // Parallel to the triangle, then past each of its edges
+        for (origin, direction) in [
+            (point(0.0, -1.0, -2.0), vector(0.0, 1.0, 0.0)),
+            (point(1.0, 1.0, -2.0), vector(0.0, 0.0, 1.0)),
+            (point(-1.0, 1.0, -2.0), vector(0.0, 0.0, 1.0)),
+            (point(0.0, -1.0, -2.0), vector(0.0, 0.0, 1.0)),
+        ] {
+            assert!(t.local_intersect(Ray::new(origin, direction)).is_empty());
+        }
+    }
+
+    #[test]
+    fn ray_strikes_a_triangle() {
+        let t = triangle();
+        let r = Ray::new(point(0.0, 0.5, -2.0), vector(0.0, 0.0, 1.0));
+        let xs = t.local_intersect(r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 2.0);
+        assert_eq!((xs[0].u, xs[0].v), (0.25, 0.25));
+    }
+
+    #[test]
+    fn bounding_sphere_contains_the_corners() {
+        let mut t = triangle();
+        t.set_transform(Matrix::get_translation_matrix(0.0, 0.0, 5.0));
+        let (center, radius) = t.bounding_sphere(0.0).unwrap();
+        assert_eq!(center, point(0.0, 1.0 / 3.0, 5.0));
+        for corner in [
+            point(0.0, 1.0, 5.0),
+            point(-1.0, 0.0, 5.0),
+            point(1.0, 0.0, 5.0),
+        ] {
+            assert!((corner - center).magnitude() <= radius + EPSILON);
+        }
+    }
+
+    #[test]
+    fn moving_triangle() {
+        let mut t = triangle();
+        t.set_motion(
+            Motion::new(Matrix::I(), Matrix::get_translation_matrix(0.0, 0.0, 5.0)).unwrap(),
+        );
+        let r = Ray::new(point(0.0, 0.5, -2.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(t.intersect(r)[0].t, 2.0);
+        assert_eq!(t.intersect(r.with_time(1.0))[0].t, 7.0);
+        let (center, _) = t.bounding_sphere(1.0).unwrap();
+        assert_eq!(center, point(0.0, 1.0 / 3.0, 5.0));
+    }
+
+    #[test]
+    fn smooth_triangle_interpolates_normals() {
+        let normals = [
+            vector(0.0, 1.0, 0.0),
+            vector(-1.0, 0.0, 0.0),
+            vector(1.0, 0.0, 0.0),
+        ];
+        let positions = [
+            point(0.0, 1.0, 0.0),
+            point(-1.0, 0.0, 0.0),
+            point(1.0, 0.0, 0.0),
+        ];
+        let uvs = [(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)];
+        let mesh = Triangle::mesh(
+            &positions,
+            &normals,
+            &uvs,
+            &[[0, 1, 2]],
+            Material::default(),
+        );
+        let i = Intersection::new_with_uv(1.0, &mesh[0], 0.45, 0.25);
+        let n = mesh[0].normal_at_hit(point(0.0, 0.0, 0.0), &i, 0.0);
+        assert_eq!(n, vector(-0.5547, 0.83205, 0.0));
+        let ((u, v), _) = mesh[0].tangent_at_hit(&i, 0.0).unwrap();
+        assert!((u - 0.4).abs() < 1e-12);
+        assert!((v - 0.3).abs() < 1e-12);
+    }
+
+    // Normal map of a single color, tilting every normal the same way
+    #[derive(Debug)]
+    struct Tilt(Color);
+
+    impl UVPattern for Tilt {
+        fn uv_pattern_at(&self, _u: f64, _v: f64) -> Color {
+            self.0
+        }
+    }
+
+    #[test]
+    fn normal_map_changes_shading_of_a_triangle() {
+        let mut t = triangle();
+        t.material.normal_map = Some(Rc::new(NormalMap::new(Box::new(Tilt(Color::new(
+            1.0, 0.5, 0.5,
+        ))))));
+        let r = Ray::new(point(0.0, 0.5, -2.0), vector(0.0, 0.0, 1.0));
+        let xs = t.intersect(r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        // Red tilts the normal fully along the tangent, the direction of increasing `u`
+        assert_eq!(comps.normalv, vector(-FRAC_1_SQRT_2, -FRAC_1_SQRT_2, 0.0));
+
+        let mut flat = triangle();
+        flat.material.normal_map = Some(Rc::new(NormalMap::new(Box::new(Tilt(Color::new(
+            0.5, 0.5, 1.0,
+        ))))));
+        let xs = flat.intersect(r);
+        assert_eq!(
+            xs[0].prepare_computations(&r, &xs).normalv,
+            vector(0.0, 0.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn triangles_in_a_world_are_shaded_with_their_normal_map() {
+        let mut t = triangle();
+        t.set_transform(Matrix::get_scaling_matrix(4.0, 4.0, 1.0));
+        let mut w = World {
+            light: Light::new(point(0.0, 0.0, -10.0), Color::white()),
+            objects: vec![Box::new(t)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 1.0, -5.0), vector(0.0, 0.0, 1.0));
+        let plain = w.color_at(&r);
+        w.objects[0].get_material_mut().normal_map = Some(Rc::new(NormalMap::new(Box::new(Tilt(
+            Color::new(0.85, 0.5, 0.85),
+        )))));
+        let mapped = w.color_at(&r);
+        assert!(mapped.red < plain.red);
+    }
+}
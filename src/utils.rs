@@ -1,4 +1,7 @@
 // Utility functions or structs or traits
+use crate::Tuple;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
 pub const EPSILON: f64 = 1.0e-5;
 
@@ -17,6 +20,36 @@ impl Compare for f64 {
     }
 }
 
+// Some unit vector perpendicular to the unit vector `n`, to build a basis around a normal
+pub(crate) fn any_perpendicular(n: Tuple) -> Tuple {
+    let axis = if n.x.abs() < 0.9 {
+        Tuple::new(1.0, 0.0, 0.0, 0.0)
+    } else {
+        Tuple::new(0.0, 1.0, 0.0, 0.0)
+    };
+    n.cross(&axis).normalize()
+}
+
+// Error of an argument which can't be used, eg.- a setting which doesn't make sense
+pub(crate) fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+// Error of a file or data which doesn't follow its format
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Creates the file at `file_path` and writes it through a buffer with `write`, eg.- `Canvas::write_p6_to`
+pub(crate) fn save_with(
+    file_path: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write(&mut writer)?;
+    writer.flush()
+}
+
 #[cfg(test)]
 mod utility_test {
     use super::*;
@@ -0,0 +1,334 @@
+use crate::noise::fractal_noise;
+use crate::Color;
+use crate::Ray;
+use crate::Shape;
+use crate::Tuple;
+use crate::World;
+use std::f64::consts::PI;
+
+// Henyey-Greenstein phase function, how much of the light travelling along one direction is scattered
+// into another direction, per steradian, where `cos_theta` is the cosine of the angle between them
+// `g` goes from -1.0 (light bounces back) over 0.0 (every direction alike) to 1.0 (light keeps going forward)
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
+
+// How the amount of the medium changes inside the volume, 1.0 is as given by the coefficients of the medium
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Density {
+    Homogeneous,
+    // Perlin noise in the object space of the boundary, eg.- clouds or patchy smoke
+    // `scale` is about the size of a puff, more `octaves` add finer details
+    Noise { scale: f64, octaves: u32 },
+}
+
+// Something light travels through, like smoke, mist or murky water
+// Coefficients are per unit of distance, eg.- an absorption of 1.0 lets through e^-1 of the light after 1 unit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Medium {
+    pub absorption: f64, // Light turned into heat
+    pub scattering: f64, // Light bounced to another direction, it makes the medium glow when lit
+    pub anisotropy: f64, // `g` of the Henyey-Greenstein phase function
+    pub density: Density,
+}
+
+impl Medium {
+    pub fn new(absorption: f64, scattering: f64, anisotropy: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+            anisotropy,
+            density: Density::Homogeneous,
+        }
+    }
+}
+
+// Medium filling a shape, light passes through the boundary unchanged, and the shape itself isn't drawn
+// The boundary should be closed and convex, like a sphere, the medium fills it from its first to its last hit
+// Rays are marched through the volume in `steps` steps, adding the light of `World::light` scattered towards the eye
+// (single scattering). The light reaching each step is dimmed by the volumes on its way, including this one,
+// and objects between the light and the volume cast shadows in it, which gives light shafts
+#[derive(Debug)]
+pub struct Volume {
+    pub boundary: Box<dyn Shape>,
+    pub medium: Medium,
+    pub steps: usize,
+}
+
+impl Volume {
+    pub fn new(boundary: Box<dyn Shape>, medium: Medium) -> Self {
+        Self {
+            boundary,
+            medium,
+            steps: 32,
+        }
+    }
+
+    // Part of the ray inside the volume, in `t` of the ray, up to `max_t`
+    pub fn segment(&self, ray: &Ray, max_t: f64) -> Option<(f64, f64)> {
+        let xs = self.boundary.intersect(*ray);
+        if xs.len() < 2 {
+            return None;
+        }
+        let (first, last) = (0..xs.len()).fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), i| {
+            (a.min(xs[i].t), b.max(xs[i].t))
+        });
+        let start = first.max(0.0);
+        let end = last.min(max_t);
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    // Fraction of the light which goes through the volume along the ray, up to `max_t`
+    pub fn transmittance(&self, ray: &Ray, max_t: f64) -> f64 {
+        let Some((start, end)) = self.segment(ray, max_t) else {
+            return 1.0;
+        };
+        let extinction = self.medium.absorption + self.medium.scattering;
+        let length = (end - start) * ray.direction.magnitude();
+        let density = match self.medium.density {
+            Density::Homogeneous => 1.0,
+            // Average density, marched like `attenuate`
+            Density::Noise { .. } => {
+                let steps = self.steps.max(1);
+                let dt = (end - start) / steps as f64;
+                (0..steps)
+                    .map(|step| {
+                        self.density_at(ray.position(start + (step as f64 + 0.5) * dt), ray.time)
+                    })
+                    .sum::<f64>()
+                    / steps as f64
+            }
+        };
+        (-extinction * density * length).exp()
+    }
+
+    // Amount of the medium at the world point `p`, at the `time`
+    fn density_at(&self, p: Tuple, time: f64) -> f64 {
+        match self.medium.density {
+            Density::Homogeneous => 1.0,
+            Density::Noise { scale, octaves } => {
+                let object_point = self.boundary.transform_at(time).inverse() * p;
+                // Noise is about -1 to 1, mapped to about 0 to 1
+                (fractal_noise(object_point * (1.0 / scale), octaves) * 0.5 + 0.5).max(0.0)
+            }
+        }
+    }
+
+    // `color` seen through the volume along the ray, from `start` to `end` of `segment`
+    // Dimmed by the medium in between, plus the light scattered towards the eye
+    pub fn attenuate(&self, world: &World, ray: &Ray, start: f64, end: f64, color: Color) -> Color {
+        let steps = self.steps.max(1);
+        let dt = (end - start) / steps as f64;
+        let distance = dt * ray.direction.magnitude();
+        let eye_direction = -ray.direction.normalize();
+        let extinction = self.medium.absorption + self.medium.scattering;
+
+        let mut transmittance = 1.0;
+        let mut scattered = Color::black();
+        for step in 0..steps {
+            let p = ray.position(start + (step as f64 + 0.5) * dt);
+            let density = self.density_at(p, ray.time);
+            if self.medium.scattering > 0.0 && !world.is_shadowed_at(p, ray.time) {
+                // Ray towards the light, which reaches it at t = 1
+                let to_light = Ray::new(p, world.light.position - p).with_time(ray.time);
+                let light_transmittance = world.volume_transmittance(&to_light, 1.0);
+                let light_direction = (p - world.light.position).normalize();
+                let phase =
+                    henyey_greenstein(light_direction.dot(&eye_direction), self.medium.anisotropy);
+                // Light of intensity 1 gives PI of irradiance, like `Material::lighting` for a white matte surface
+                let factor = transmittance
+                    * light_transmittance
+                    * self.medium.scattering
+                    * density
+                    * phase
+                    * PI
+                    * distance;
+                scattered = scattered + world.light.intensity * factor;
+            }
+            transmittance *= (-extinction * density * distance).exp();
+        }
+        color * transmittance + scattered
+    }
+}
+
+// Haze over the whole scene, objects fade into `color` the further away they are
+// The background is left as it is, as it usually shows the sky the haze is lit by
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub color: Color,
+    pub density: f64, // Extinction per unit of distance, e^-density of the object is seen after 1 unit
+}
+
+impl Fog {
+    pub fn new(color: Color, density: f64) -> Self {
+        Self { color, density }
+    }
+
+    // `color` of an object seen at `distance`
+    pub fn apply(&self, color: Color, distance: f64) -> Color {
+        let transmittance = (-self.density * distance).exp();
+        color * transmittance + self.color * (1.0 - transmittance)
+    }
+}
+
+#[cfg(test)]
+mod volume_tests {
+    use super::*;
+    use crate::{point, vector, Background, Compare, Light, Matrix, Sphere};
+
+    fn world_with(volume: Volume, light: Light) -> World {
+        World {
+            light,
+            objects: vec![],
+            background: Background::Solid(Color::white()),
+            volumes: vec![volume],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn phase_function_integrates_to_one() {
+        for g in [-0.5, 0.0, 0.3, 0.8] {
+            let steps = 10000;
+            let mut total = 0.0;
+            for i in 0..steps {
+                let theta = (i as f64 + 0.5) / steps as f64 * PI;
+                let d_omega = 2.0 * PI * theta.sin() * PI / steps as f64;
+                total += henyey_greenstein(theta.cos(), g) * d_omega;
+            }
+            assert!((total - 1.0).abs() < 1e-3, "{} {}", g, total);
+        }
+    }
+
+    #[test]
+    fn isotropic_phase_function() {
+        assert!(henyey_greenstein(0.3, 0.0).eq(1.0 / (4.0 * PI)));
+        assert!(henyey_greenstein(1.0, 0.7) > henyey_greenstein(-1.0, 0.7));
+    }
+
+    #[test]
+    fn segment_is_clipped_by_ray_and_hit() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(1.0, 0.0, 0.0));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(v.segment(&r, f64::INFINITY), Some((4.0, 6.0)));
+        assert_eq!(v.segment(&r, 5.0), Some((4.0, 5.0)));
+        assert_eq!(v.segment(&r, 3.0), None);
+        let inside = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(v.segment(&inside, f64::INFINITY), Some((0.0, 1.0)));
+        let miss = Ray::new(point(0.0, 2.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(v.segment(&miss, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn absorbing_volume_dims_what_is_behind() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(1.0, 0.0, 0.0));
+        let w = world_with(v, Light::new(point(0.0, 10.0, 0.0), Color::white()));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let c = w.color_at(&r);
+        let expected = (-2.0_f64).exp();
+        assert_eq!(c, Color::new(expected, expected, expected));
+    }
+
+    #[test]
+    fn scattering_volume_glows_in_light() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(0.0, 0.5, 0.0));
+        let mut w = world_with(v, Light::new(point(0.0, 10.0, 0.0), Color::white()));
+        w.background = Background::Solid(Color::black());
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let lit = w.color_at(&r);
+        assert!(lit.red > 0.0);
+
+        // Blocking the light leaves the volume dark, which gives light shafts around objects
+        let mut blocker = Sphere::default();
+        blocker.set_transform(
+            Matrix::get_translation_matrix(0.0, 5.0, 0.0)
+                * Matrix::get_scaling_matrix(3.0, 0.5, 3.0),
+        );
+        w.objects.push(Box::new(blocker));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(w.color_at(&r), Color::black());
+    }
+
+    #[test]
+    fn transmittance_through_volume() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(0.5, 0.5, 0.0));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert!(v.transmittance(&r, f64::INFINITY).eq((-2.0_f64).exp()));
+        assert!(v.transmittance(&r, 5.0).eq((-1.0_f64).exp()));
+        let miss = Ray::new(point(0.0, 2.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(v.transmittance(&miss, f64::INFINITY), 1.0);
+    }
+
+    #[test]
+    fn dense_volume_shadows_itself() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(0.0, 2.0, 0.0));
+        let mut w = world_with(v, Light::new(point(0.0, 10.0, 0.0), Color::white()));
+        w.background = Background::Solid(Color::black());
+        // The light reaching the bottom of the volume has gone through the top of it
+        let top = w.color_at(&Ray::new(point(0.0, 0.6, -5.0), vector(0.0, 0.0, 1.0)));
+        let bottom = w.color_at(&Ray::new(point(0.0, -0.6, -5.0), vector(0.0, 0.0, 1.0)));
+        assert!(top.red > bottom.red * 5.0, "{:?} {:?}", top, bottom);
+    }
+
+    #[test]
+    fn forward_scattering_is_brighter_towards_light() {
+        let v = Volume::new(Box::new(Sphere::default()), Medium::new(0.0, 0.5, 0.8));
+        let mut w = world_with(v, Light::new(point(0.0, 0.0, 10.0), Color::white()));
+        w.background = Background::Solid(Color::black());
+        let towards = w.color_at(&Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0)));
+        let away = w.color_at(&Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, -1.0)));
+        assert!(towards.red > away.red * 10.0);
+    }
+
+    #[test]
+    fn noise_density_varies_and_is_not_negative() {
+        let mut medium = Medium::new(1.0, 0.0, 0.0);
+        medium.density = Density::Noise {
+            scale: 0.3,
+            octaves: 2,
+        };
+        let v = Volume::new(Box::new(Sphere::default()), medium);
+        let mut values = Vec::new();
+        for i in 0..50 {
+            let d = v.density_at(point(i as f64 * 0.037 - 0.9, 0.1, 0.2), 0.0);
+            assert!(d >= 0.0);
+            values.push(d);
+        }
+        assert!(values.iter().any(|&d| (d - values[0]).abs() > 0.05));
+    }
+
+    #[test]
+    fn fog_blends_towards_its_color() {
+        let fog = Fog::new(Color::new(0.5, 0.5, 0.5), 0.1);
+        let c = Color::new(1.0, 0.0, 0.0);
+        assert_eq!(fog.apply(c, 0.0), c);
+        let t = (-1.0_f64).exp();
+        assert_eq!(
+            fog.apply(c, 10.0),
+            Color::new(t + 0.5 * (1.0 - t), 0.5 * (1.0 - t), 0.5 * (1.0 - t))
+        );
+    }
+
+    #[test]
+    fn fog_in_world_dims_objects_but_not_background() {
+        let mut w = World {
+            fog: Some(Fog::new(Color::white(), 0.5)),
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let hit_color = w.color_at(&r);
+        w.fog = None;
+        let clear = w.color_at(&r);
+        // Hit at t = 4
+        assert_eq!(hit_color, Fog::new(Color::white(), 0.5).apply(clear, 4.0));
+
+        let miss = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 1.0, 0.0));
+        w.fog = Some(Fog::new(Color::white(), 0.5));
+        assert_eq!(w.color_at(&miss), Color::black());
+    }
+}
@@ -1,21 +1,34 @@
+use crate::random::Rng;
+use crate::Background;
+use crate::EnvironmentLight;
 use crate::Light;
 use crate::Matrix;
+use crate::PhotonMap;
 use crate::Ray;
 use crate::Shape;
 use crate::Sphere;
 use crate::Tuple;
 use crate::{point, Color};
 use crate::{Computation, Intersections};
+use crate::{Fog, Volume};
+
+// How many times a ray can be reflected or refracted, which stops two mirrors from reflecting each other forever
+const MAX_BOUNCES: usize = 5;
 
 pub struct World {
     pub light: Light,
-    pub objects: Vec<Sphere>,
+    pub objects: Vec<Box<dyn Shape>>,
+    pub background: Background, // Color seen by the rays which miss every object
+    pub environment_light: Option<EnvironmentLight>, // Lights the objects from every direction, in addition to `light`
+    pub caustics: Option<PhotonMap>, // Light of `light` focused by mirrors and glass, see `PhotonMap::caustics`
+    pub volumes: Vec<Volume>, // Smoke, mist... which the rays go through, they shouldn't overlap
+    pub fog: Option<Fog>,     // Haze which fades the objects with distance
 }
 
 impl World {
     // Returns the intersections of the ray with all objects in the world
     // sorted by the `t` value
-    fn intersect_world(&self, ray: Ray) -> Intersections {
+    pub(crate) fn intersect_world(&self, ray: Ray) -> Intersections<'_> {
         let mut xs: Intersections = Default::default();
         for obj in &self.objects {
             let obj_xs = obj.intersect(ray);
@@ -26,24 +39,141 @@ impl World {
     }
 
     // Compute the color at the intersection point via computation object
-    fn shade_hit(&self, comps: &Computation) -> Color {
-        let in_shadow = self.is_shadowed(comps.over_point);
-        comps.object.material.lighting(
+    // `remaining` is how many more times the light can be reflected or refracted
+    fn shade_hit(&self, comps: &Computation, remaining: usize) -> Color {
+        let in_shadow = self.is_shadowed_at(comps.over_point, comps.time);
+        let material = comps.object.get_material();
+        let surface = material.lighting(
+            comps.object,
             self.light,
             comps.over_point,
             comps.eyev,
             comps.normalv,
             in_shadow,
-        )
+            comps.time,
+        );
+        let surface = match &self.environment_light {
+            Some(environment_light) => surface + self.shade_environment(environment_light, comps),
+            None => surface,
+        };
+        let surface = match &self.caustics {
+            Some(caustics) => surface + caustics.radiance(comps),
+            None => surface,
+        };
+        let reflected = self.reflected_color(comps, remaining);
+        let refracted = self.refracted_color(comps, remaining);
+
+        // Glass reflects more of the light at grazing angles, the rest goes through
+        if material.reflective > 0.0 && material.transparency > 0.0 {
+            let reflectance = comps.schlick();
+            surface + reflected * reflectance + refracted * (1.0 - reflectance)
+        } else {
+            surface + reflected + refracted
+        }
+    }
+
+    // Estimate the light arriving from the environment by sampling directions from it
+    // Each sampled direction is weighted by the inverse of its probability (Monte Carlo integration)
+    // Sampling is seeded by the hit point, so, the same point always gets the same color
+    fn shade_environment(
+        &self,
+        environment_light: &EnvironmentLight,
+        comps: &Computation,
+    ) -> Color {
+        let mut rng = Rng::from_point(comps.over_point);
+        let mut total = Color::black();
+        for _ in 0..environment_light.samples {
+            let Some(sample) = environment_light.sample(&mut rng) else {
+                return Color::black();
+            };
+            if sample.direction.dot(&comps.normalv) <= 0.0
+                || self.is_occluded(comps.over_point, sample.direction, comps.time)
+            {
+                continue;
+            }
+            let reflected = comps.object.get_material().reflected_radiance(
+                comps.object,
+                sample.radiance,
+                comps.over_point,
+                sample.direction,
+                comps.eyev,
+                comps.normalv,
+                comps.time,
+            );
+            total = total + reflected * (1.0 / sample.pdf);
+        }
+        total * (1.0 / environment_light.samples.max(1) as f64)
     }
 
     // Get the color at the intersection point of the ray
     pub fn color_at(&self, ray: &Ray) -> Color {
+        self.color_at_depth(ray, MAX_BOUNCES)
+    }
+
+    fn color_at_depth(&self, ray: &Ray, remaining: usize) -> Color {
         let xs = self.intersect_world(*ray);
-        match xs.hit() {
+        let (color, hit_t) = match xs.hit() {
             Some(i) => {
-                let comp = i.prepare_computations(ray);
-                self.shade_hit(&comp)
+                let comp = i.prepare_computations(ray, &xs);
+                let color = self.shade_hit(&comp, remaining);
+                let color = match &self.fog {
+                    Some(fog) => fog.apply(color, i.t * ray.direction.magnitude()),
+                    None => color,
+                };
+                (color, i.t)
+            }
+            None => (self.background.color_at(ray.direction), f64::INFINITY),
+        };
+        self.through_volumes(ray, hit_t, color)
+    }
+
+    // `color` seen along the ray up to `max_t`, through the volumes in between, starting with the furthest
+    fn through_volumes(&self, ray: &Ray, max_t: f64, color: Color) -> Color {
+        let mut segments: Vec<(&Volume, f64, f64)> = self
+            .volumes
+            .iter()
+            .filter_map(|volume| {
+                let (start, end) = volume.segment(ray, max_t)?;
+                Some((volume, start, end))
+            })
+            .collect();
+        segments.sort_by(|a, b| b.1.total_cmp(&a.1));
+        segments
+            .into_iter()
+            .fold(color, |color, (volume, start, end)| {
+                volume.attenuate(self, ray, start, end, color)
+            })
+    }
+
+    // Fraction of the light which goes through all the volumes along the ray, up to `max_t`
+    pub fn volume_transmittance(&self, ray: &Ray, max_t: f64) -> f64 {
+        self.volumes
+            .iter()
+            .map(|volume| volume.transmittance(ray, max_t))
+            .product()
+    }
+
+    // Color seen in the mirror at the hit, black for surfaces which aren't reflective
+    // Reflected rays which miss every object see the background
+    pub fn reflected_color(&self, comps: &Computation, remaining: usize) -> Color {
+        let reflective = comps.object.get_material().reflective;
+        if remaining == 0 || reflective == 0.0 {
+            return Color::black();
+        }
+        let reflect_ray = Ray::new(comps.over_point, comps.reflectv).with_time(comps.time);
+        self.color_at_depth(&reflect_ray, remaining - 1) * reflective
+    }
+
+    // Color seen through the hit, black for opaque surfaces and on total internal reflection
+    pub fn refracted_color(&self, comps: &Computation, remaining: usize) -> Color {
+        let transparency = comps.object.get_material().transparency;
+        if remaining == 0 || transparency == 0.0 {
+            return Color::black();
+        }
+        match comps.refracted_direction() {
+            Some(direction) => {
+                let refract_ray = Ray::new(comps.under_point, direction).with_time(comps.time);
+                self.color_at_depth(&refract_ray, remaining - 1) * transparency
             }
             None => Color::black(),
         }
@@ -52,16 +182,27 @@ impl World {
     // Compute whether the point is under a shadow
     // See README for explanation
     pub fn is_shadowed(&self, point: Tuple) -> bool {
+        self.is_shadowed_at(point, 0.0)
+    }
+
+    // Whether the point is under a shadow, with moving objects placed where they are at the `time`
+    pub fn is_shadowed_at(&self, point: Tuple, time: f64) -> bool {
         let v = self.light.position - point;
         let distance = v.magnitude();
         let direction = v.normalize();
 
-        let r = Ray::new(point, direction);
+        let r = Ray::new(point, direction).with_time(time);
         let intersections = self.intersect_world(r);
         let h = intersections.hit();
 
         h.is_some() && (h.unwrap().t < distance)
     }
+
+    // Whether a ray from the point in the `direction` hits anything, like a shadow for a light infinitely far away
+    pub fn is_occluded(&self, point: Tuple, direction: Tuple, time: f64) -> bool {
+        let r = Ray::new(point, direction).with_time(time);
+        self.intersect_world(r).hit().is_some()
+    }
 }
 
 impl Default for World {
@@ -78,7 +219,12 @@ impl Default for World {
 
         Self {
             light,
-            objects: vec![s1, s2],
+            objects: vec![Box::new(s1), Box::new(s2)],
+            background: Background::default(),
+            environment_light: None,
+            caustics: None,
+            volumes: Vec::new(),
+            fog: None,
         }
     }
 }
@@ -88,6 +234,12 @@ mod world_test {
     use super::*;
     use crate::vector;
     use crate::Intersection;
+    use crate::Material;
+    use crate::Plane;
+    use crate::{Canvas, Motion, TextureFilter, TextureMap, UVImage, UVMapping};
+    use std::f64::consts::FRAC_1_SQRT_2;
+    use std::f64::consts::SQRT_2;
+    use std::rc::Rc;
 
     #[test]
     fn check_default_world() {
@@ -103,8 +255,11 @@ mod world_test {
         s2.set_transform(Matrix::get_scaling_matrix(0.5, 0.5, 0.5));
 
         assert_eq!(w.light, light);
-        assert!(w.objects.contains(&s1));
-        assert!(w.objects.contains(&s2));
+        assert_eq!(w.objects.len(), 2);
+        for (object, sphere) in w.objects.iter().zip([s1, s2]) {
+            assert_eq!(object.get_transform(), sphere.get_transform());
+            assert_eq!(object.get_material(), &sphere.material);
+        }
     }
 
     #[test]
@@ -123,10 +278,10 @@ mod world_test {
     fn shading_intersection_from_outside() {
         let w: World = Default::default();
         let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
-        let shape = w.objects[0];
+        let shape = w.objects[0].as_ref();
         let i = Intersection::new(4.0, shape);
-        let comps = i.prepare_computations(&r);
-        let c = w.shade_hit(&comps);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        let c = w.shade_hit(&comps, MAX_BOUNCES);
         assert_eq!(c, Color::new(0.38066, 0.47583, 0.2855));
     }
 
@@ -137,10 +292,10 @@ mod world_test {
             ..Default::default()
         };
         let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
-        let shape = w.objects[1];
+        let shape = w.objects[1].as_ref();
         let i = Intersection::new(0.5, shape);
-        let comps = i.prepare_computations(&r);
-        let c = w.shade_hit(&comps);
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        let c = w.shade_hit(&comps, MAX_BOUNCES);
         assert_eq!(c, Color::new(0.90498, 0.90498, 0.90498));
     }
 
@@ -152,6 +307,21 @@ mod world_test {
         assert_eq!(c, Color::new(0.0, 0.0, 0.0));
     }
 
+    #[test]
+    fn color_at_when_ray_misses_uses_background() {
+        let w = World {
+            background: Background::Gradient {
+                bottom: Color::white(),
+                top: Color::new(0.5, 0.7, 1.0),
+            },
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(w.color_at(&r), Color::new(0.5, 0.7, 1.0));
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(w.color_at(&r), Color::white());
+    }
+
     #[test]
     fn color_at_when_ray_hits() {
         let w = World::default();
@@ -163,9 +333,9 @@ mod world_test {
     #[test]
     fn color_at_when_intersection_behind_ray() {
         let mut w = World::default();
-        let inner = &mut w.objects[1];
-        inner.material.ambient = 1.0;
-        let inner_color = inner.material.color; // Needed due to Rust's borrow checker
+        let inner = w.objects[1].get_material_mut();
+        inner.ambient = 1.0;
+        let inner_color = inner.color; // Needed due to Rust's borrow checker
         let r = Ray::new(point(0.0, 0.0, 0.75), vector(0.0, 0.0, -1.0));
         let c = w.color_at(&r);
         assert_eq!(c, inner_color);
@@ -206,13 +376,365 @@ mod world_test {
         s2.set_transform(Matrix::get_translation_matrix(0.0, 0.0, 10.0));
         let w = World {
             light: Light::new(point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)),
-            objects: vec![s1, s2],
+            objects: vec![Box::new(s1), Box::new(s2)],
+            background: Background::default(),
+            environment_light: None,
+            caustics: None,
+            volumes: Vec::new(),
+            fog: None,
         };
 
         let r = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, 1.0));
-        let i = Intersection::new(4.0, s2);
-        let comps = i.prepare_computations(&r);
-        let c = w.shade_hit(&comps);
+        let i = Intersection::new(4.0, w.objects[1].as_ref());
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        let c = w.shade_hit(&comps, MAX_BOUNCES);
         assert_eq!(c, Color::new(0.1, 0.1, 0.1));
     }
+
+    #[test]
+    fn reflected_color_of_nonreflective_material() {
+        let mut w = World::default();
+        w.objects[1].get_material_mut().ambient = 1.0;
+        let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
+        let i = Intersection::new(1.0, w.objects[1].as_ref());
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        assert_eq!(w.reflected_color(&comps, MAX_BOUNCES), Color::black());
+    }
+
+    #[test]
+    fn reflected_color_of_reflective_material() {
+        let mut mirror = Sphere::default();
+        mirror.material.reflective = 0.5;
+        // Only lit by its ambient color, behind the ray, so seen in the mirror only
+        let mut behind = Sphere::default();
+        behind.set_transform(Matrix::get_translation_matrix(0.0, 0.0, -10.0));
+        behind.material = Material {
+            color: Color::new(1.0, 0.5, 0.0),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            ..Default::default()
+        };
+        let w = World {
+            objects: vec![Box::new(mirror), Box::new(behind)],
+            ..Default::default()
+        };
+
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let xs = w.intersect_world(r);
+        let comps = xs.hit().unwrap().prepare_computations(&r, &xs);
+        assert_eq!(
+            w.reflected_color(&comps, MAX_BOUNCES),
+            Color::new(0.5, 0.25, 0.0)
+        );
+        assert_eq!(w.reflected_color(&comps, 0), Color::black());
+    }
+
+    #[test]
+    fn reflected_color_of_miss_is_background() {
+        let mut mirror = Sphere::default();
+        mirror.material.reflective = 0.5;
+        let w = World {
+            objects: vec![Box::new(mirror)],
+            background: Background::Solid(Color::new(0.2, 0.4, 0.6)),
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let xs = w.intersect_world(r);
+        let comps = xs.hit().unwrap().prepare_computations(&r, &xs);
+        assert_eq!(
+            w.reflected_color(&comps, MAX_BOUNCES),
+            Color::new(0.1, 0.2, 0.3)
+        );
+    }
+
+    #[test]
+    fn color_at_inside_mirror_terminates() {
+        let mut mirror = Sphere::default();
+        mirror.material.reflective = 1.0;
+        let w = World {
+            light: Light::new(point(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)),
+            objects: vec![Box::new(mirror)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        let c = w.color_at(&r);
+        assert!(c.red > 0.0);
+    }
+
+    #[test]
+    fn refracted_color_of_opaque_material() {
+        let w = World::default();
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let xs = w.intersect_world(r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_eq!(w.refracted_color(&comps, MAX_BOUNCES), Color::black());
+    }
+
+    #[test]
+    fn refracted_color_at_maximum_depth() {
+        let mut w = World::default();
+        w.objects[0].get_material_mut().transparency = 1.0;
+        w.objects[0].get_material_mut().refractive_index = 1.5;
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let xs = w.intersect_world(r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_eq!(w.refracted_color(&comps, 0), Color::black());
+    }
+
+    #[test]
+    fn refracted_color_under_total_internal_reflection() {
+        let mut w = World::default();
+        w.objects[0].get_material_mut().transparency = 1.0;
+        w.objects[0].get_material_mut().refractive_index = 1.5;
+        let r = Ray::new(point(0.0, 0.0, FRAC_1_SQRT_2), vector(0.0, 1.0, 0.0));
+        let xs = w.intersect_world(r);
+        let comps = xs[1].prepare_computations(&r, &xs);
+        assert_eq!(w.refracted_color(&comps, MAX_BOUNCES), Color::black());
+    }
+
+    #[test]
+    fn refracted_color_sees_through_glass() {
+        // Light between the spheres, so the glass doesn't shadow the inner sphere
+        let light = Light::new(point(0.0, 0.0, -0.75), Color::new(1.0, 1.0, 1.0));
+        let mut w = World {
+            light,
+            ..Default::default()
+        };
+        w.objects[0].get_material_mut().ambient = 0.0;
+        w.objects[0].get_material_mut().diffuse = 0.0;
+        w.objects[0].get_material_mut().specular = 0.0;
+        w.objects[0].get_material_mut().transparency = 1.0;
+        w.objects[0].get_material_mut().refractive_index = 1.0;
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let xs = w.intersect_world(r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+
+        // Glass which doesn't bend the light is invisible, the inner sphere is seen as if it wasn't there
+        let mut without_glass = World {
+            light,
+            ..Default::default()
+        };
+        without_glass.objects.remove(0);
+        assert_eq!(w.shade_hit(&comps, MAX_BOUNCES), without_glass.color_at(&r));
+    }
+
+    #[test]
+    fn shade_hit_with_reflective_plane() {
+        let mut w = World::default();
+        let mut floor = Plane::new(Matrix::get_translation_matrix(0.0, -1.0, 0.0));
+        floor.material.reflective = 0.5;
+        w.objects.push(Box::new(floor));
+        let r = Ray::new(
+            point(0.0, 0.0, -3.0),
+            vector(0.0, -SQRT_2 / 2.0, SQRT_2 / 2.0),
+        );
+        let i = Intersection::new(SQRT_2, w.objects[2].as_ref());
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        // Slightly off the book's colors as `EPSILON` is smaller here
+        assert_eq!(
+            w.reflected_color(&comps, MAX_BOUNCES),
+            Color::new(0.19033, 0.23792, 0.14275)
+        );
+        assert_eq!(
+            w.shade_hit(&comps, MAX_BOUNCES),
+            Color::new(0.87676, 0.92434, 0.82917)
+        );
+    }
+
+    #[test]
+    fn color_at_between_parallel_mirrors_terminates() {
+        let mut lower = Plane::new(Matrix::get_translation_matrix(0.0, -1.0, 0.0));
+        lower.material.reflective = 1.0;
+        let mut upper = Plane::new(Matrix::get_translation_matrix(0.0, 1.0, 0.0));
+        upper.material.reflective = 1.0;
+        let w = World {
+            light: Light::new(point(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)),
+            objects: vec![Box::new(lower), Box::new(upper)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        let c = w.color_at(&r);
+        assert!(c.red > 0.0);
+    }
+
+    #[test]
+    fn shade_hit_with_transparent_plane() {
+        let mut w = World::default();
+        let mut floor = Plane::new(Matrix::get_translation_matrix(0.0, -1.0, 0.0));
+        floor.material.transparency = 0.5;
+        floor.material.refractive_index = 1.5;
+        let mut ball = Sphere::default();
+        ball.material.color = Color::new(1.0, 0.0, 0.0);
+        ball.material.ambient = 0.5;
+        ball.set_transform(Matrix::get_translation_matrix(0.0, -3.5, -0.5));
+        w.objects.push(Box::new(floor));
+        w.objects.push(Box::new(ball));
+        let r = Ray::new(
+            point(0.0, 0.0, -3.0),
+            vector(0.0, -SQRT_2 / 2.0, SQRT_2 / 2.0),
+        );
+        let i = Intersection::new(SQRT_2, w.objects[2].as_ref());
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        assert_eq!(
+            w.shade_hit(&comps, MAX_BOUNCES),
+            Color::new(0.93642, 0.68642, 0.68642)
+        );
+
+        // Reflective glass mixes the reflection and the refraction by Schlick's approximation
+        w.objects[2].get_material_mut().reflective = 0.5;
+        let i = Intersection::new(SQRT_2, w.objects[2].as_ref());
+        let comps = i.prepare_computations(&r, &Intersections::from(vec![i]));
+        assert_eq!(
+            w.shade_hit(&comps, MAX_BOUNCES),
+            Color::new(0.93391, 0.69643, 0.69243)
+        );
+    }
+
+    #[test]
+    fn occlusion_towards_direction() {
+        let w = World::default();
+        assert!(w.is_occluded(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0), 0.0));
+        assert!(!w.is_occluded(point(0.0, 0.0, -5.0), vector(0.0, 0.0, -1.0), 0.0));
+    }
+
+    #[test]
+    fn shading_with_uniform_environment_light() {
+        let mut s = Sphere::default();
+        s.material.ambient = 0.0;
+        s.material.diffuse = 1.0;
+        s.material.specular = 0.0;
+        let w = World {
+            light: Light::new(point(0.0, 10.0, 0.0), Color::black()),
+            objects: vec![Box::new(s)],
+            environment_light: Some(EnvironmentLight::new(
+                Background::Solid(Color::white()),
+                4000,
+            )),
+            ..Default::default()
+        };
+        // Top of the sphere sees the whole upper hemisphere of the environment
+        let r = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        let c = w.color_at(&r);
+        assert!((c.red - 1.0).abs() < 0.05, "{:?}", c);
+        assert!((c.green - 1.0).abs() < 0.05, "{:?}", c);
+    }
+
+    #[test]
+    fn environment_light_is_blocked_by_objects() {
+        let mut floor = Sphere::default();
+        floor.material.ambient = 0.0;
+        floor.material.specular = 0.0;
+        let mut blocker = Sphere::default();
+        blocker.set_transform(
+            Matrix::get_translation_matrix(0.0, 3.0, 0.0)
+                * Matrix::get_scaling_matrix(1.5, 1.5, 1.5),
+        );
+        let environment_light = EnvironmentLight::new(Background::Solid(Color::white()), 500);
+        let open = World {
+            light: Light::new(point(0.0, 10.0, 0.0), Color::black()),
+            objects: vec![Box::new(floor.clone())],
+            environment_light: Some(environment_light.clone()),
+            ..Default::default()
+        };
+        let blocked = World {
+            light: open.light,
+            objects: vec![Box::new(floor), Box::new(blocker)],
+            environment_light: Some(environment_light),
+            ..Default::default()
+        };
+        // Looking at the top of the floor sphere, right below the blocker
+        let r = Ray::new(point(0.0, 1.2, 0.0), vector(0.0, -1.0, 0.0));
+        assert!(blocked.color_at(&r).red < open.color_at(&r).red * 0.8);
+    }
+
+    #[test]
+    fn environment_lighting_is_reproducible() {
+        let w = World {
+            environment_light: Some(EnvironmentLight::new(
+                Background::Gradient {
+                    bottom: Color::black(),
+                    top: Color::white(),
+                },
+                16,
+            )),
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(w.color_at(&r), w.color_at(&r));
+    }
+
+    #[test]
+    fn emissive_object_glows_without_light() {
+        let mut s = Sphere::default();
+        s.material.ambient = 0.0;
+        s.material.emissive = Color::new(1.0, 0.5, 0.0);
+        let w = World {
+            light: Light::new(point(-10.0, 10.0, -10.0), Color::black()),
+            objects: vec![Box::new(s)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(w.color_at(&r), Color::new(1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn stripes_move_with_moving_sphere() {
+        // Red stripes at x in 0..0.5 of the object space, blue ones at 0.5..1
+        let mut stripes = Canvas::new(2, 1);
+        stripes.write_pixel(0, 0, Color::red());
+        stripes.write_pixel(1, 0, Color::blue());
+        let mut s = Sphere::default();
+        s.material = Material {
+            pattern: Some(Rc::new(TextureMap::new(
+                Box::new(UVImage::new(stripes, TextureFilter::Nearest)),
+                UVMapping::Planar,
+            ))),
+            ambient: 1.0,
+            diffuse: 0.0,
+            specular: 0.0,
+            ..Default::default()
+        };
+        s.set_motion(
+            Motion::new(Matrix::I(), Matrix::get_translation_matrix(5.5, 0.0, 0.0)).unwrap(),
+        );
+        let w = World {
+            objects: vec![Box::new(s)],
+            ..Default::default()
+        };
+        let r = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(w.color_at(&r), Color::red());
+        // At x = 5.7 when the sphere has moved by 5.5, still 0.2 in the object space
+        let r = Ray::new(point(5.7, 0.0, -5.0), vector(0.0, 0.0, 1.0)).with_time(1.0);
+        assert_eq!(w.color_at(&r), Color::red());
+        let r = Ray::new(point(6.2, 0.0, -5.0), vector(0.0, 0.0, 1.0)).with_time(1.0);
+        assert_eq!(w.color_at(&r), Color::blue());
+    }
+
+    #[test]
+    fn reflections_see_moving_objects_at_ray_time() {
+        let mut mirror = Plane::new(Matrix::get_translation_matrix(0.0, -1.0, 0.0));
+        mirror.material.reflective = 1.0;
+        mirror.material.ambient = 0.0;
+        mirror.material.diffuse = 0.0;
+        mirror.material.specular = 0.0;
+        let mut ball = Sphere::default();
+        ball.material.ambient = 1.0;
+        ball.material.color = Color::red();
+        ball.set_motion(
+            Motion::new(
+                Matrix::get_translation_matrix(0.0, 10.0, 1.0),
+                Matrix::get_translation_matrix(0.0, 1.0, 1.0),
+            )
+            .unwrap(),
+        );
+        let w = World {
+            objects: vec![Box::new(mirror), Box::new(ball)],
+            ..Default::default()
+        };
+        // Looking down at the mirror, which reflects the ray through where the ball is at the end of its motion
+        let r = Ray::new(point(0.0, 0.0, -2.0), vector(0.0, -1.0, 1.0).normalize());
+        assert_eq!(w.color_at(&r), Color::black());
+        assert_ne!(w.color_at(&r.with_time(1.0)), Color::black());
+    }
 }
@@ -0,0 +1,529 @@
+use crate::utils::invalid_data;
+use std::io;
+
+const WINDOW_SIZE: usize = 32768; // How far back deflate can refer to earlier bytes
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64; // Earlier positions tried for each match, more compress better but slower
+
+// Checksum at the end of zlib data
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// zlib stream with the data in stored (uncompressed) deflate blocks
+pub(crate) fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        let length = block.len() as u16;
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(!length).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// zlib stream with the data compressed in one deflate block with fixed Huffman codes
+pub(crate) fn zlib_deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new(vec![0x78, 0x9C]);
+    bits.write(1, 1); // Last block
+    bits.write(1, 2); // Fixed Huffman codes
+
+    // Most recent position of each hash of 3 bytes, and the position before with the same hash
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |i: usize, head: &mut [usize], previous: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash3(&data[i..]);
+            previous[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash3(&data[i..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = (0..max_length)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, i - candidate);
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = previous[candidate % WINDOW_SIZE];
+                // Older entries of the ring buffer are overwritten, positions only go backwards
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_length(&mut bits, best_length);
+            write_distance(&mut bits, best_distance);
+            for k in i..i + best_length {
+                insert(k, &mut head, &mut previous);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut bits, data[i] as u16);
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    write_literal(&mut bits, 256); // End of block
+
+    let mut out = bits.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Hash of the first 3 bytes, from 0 to 0x7FFF
+fn hash3(bytes: &[u8]) -> usize {
+    ((bytes[0] as usize) << 10 ^ (bytes[1] as usize) << 5 ^ bytes[2] as usize) & 0x7FFF
+}
+
+// Base lengths of the length codes 257 to 285, and their extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base distances of the distance codes 0 to 29, and their extra bits
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Fixed Huffman code of a literal byte, the end of block (256) or a length code (257 to 287)
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let (code, length) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xC0 + symbol - 280, 8),
+    };
+    bits.write_huffman(code, length);
+}
+
+fn write_length(bits: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal(bits, 257 + index as u16);
+    bits.write(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index],
+    );
+}
+
+fn write_distance(bits: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    bits.write_huffman(index as u16, 5);
+    bits.write(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index],
+    );
+}
+
+// Packs bits into bytes starting from the least significant bit, as deflate does
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        Self {
+            out,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    // Lowest `count` bits of the value, least significant first
+    fn write(&mut self, value: u32, count: u8) {
+        for k in 0..count {
+            self.buffer |= ((value >> k) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn write_huffman(&mut self, code: u16, length: u8) {
+        for k in (0..length).rev() {
+            self.write(((code >> k) & 1) as u32, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+// Most bytes deflate can give for a byte of compressed data, a copy of 258 bytes takes at least 2 bits
+pub(crate) const MAX_DEFLATE_RATIO: usize = 1032;
+
+// Decompresses a zlib stream, checking its header and checksum
+// Streams inflating to more than `max_length` bytes are an error, so a small broken stream can't fill the memory
+pub(crate) fn zlib_inflate(data: &[u8], max_length: usize) -> io::Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(invalid_data("zlib stream is too short"));
+    }
+    let (method, flags) = (data[0], data[1]);
+    if method & 0x0F != 8 || !((method as u16) << 8 | flags as u16).is_multiple_of(31) {
+        return Err(invalid_data("not a deflate zlib stream"));
+    }
+    if flags & 0x20 != 0 {
+        return Err(invalid_data("preset dictionaries are not supported"));
+    }
+    let mut bits = BitReader::new(&data[2..]);
+    let out = inflate(&mut bits, max_length)?;
+    let end = 2 + bits.byte_position();
+    let checksum = data
+        .get(end..end + 4)
+        .ok_or_else(|| invalid_data("zlib checksum is missing"))?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err(invalid_data("zlib checksum doesn't match"));
+    }
+    Ok(out)
+}
+
+// Deflate blocks until the last one
+fn inflate(bits: &mut BitReader, max_length: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align();
+                let length = bits.read(16)?;
+                if bits.read(16)? != !length & 0xFFFF {
+                    return Err(invalid_data("length of stored block is corrupt"));
+                }
+                check_length(&out, length as usize, max_length)?;
+                for _ in 0..length {
+                    out.push(bits.read(8)? as u8);
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(bits, &literals, &distances, &mut out, max_length)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                inflate_block(bits, &literals, &distances, &mut out, max_length)?;
+            }
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// Literals, and lengths with distances back to the bytes to copy, until the end of the block
+fn inflate_block(
+    bits: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+    max_length: usize,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => {
+                check_length(out, 1, max_length)?;
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] as usize + bits.read(LENGTH_EXTRA[index])? as usize;
+                let code = distances.decode(bits)? as usize;
+                if code >= 30 {
+                    return Err(invalid_data("invalid distance code"));
+                }
+                let distance =
+                    DISTANCE_BASE[code] as usize + bits.read(DISTANCE_EXTRA[code])? as usize;
+                if distance > out.len() {
+                    return Err(invalid_data("distance goes before the start of the data"));
+                }
+                check_length(out, length, max_length)?;
+                // The copy can overlap what it writes, eg.- a distance of 1 repeats the last byte
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+            _ => return Err(invalid_data("invalid length code")),
+        }
+    }
+}
+
+fn check_length(out: &[u8], extra: usize, max_length: usize) -> io::Result<()> {
+    if out.len() + extra > max_length {
+        return Err(invalid_data(
+            "zlib stream inflates to more data than expected",
+        ));
+    }
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+// Order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Huffman codes stored at the start of the block, themselves Huffman coded
+fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    let mut code_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid_data("repeat without a previous code length"))?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(invalid_data("code lengths overflow"));
+    }
+    if lengths[256] == 0 {
+        return Err(invalid_data("block has no end code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+// Canonical Huffman code given by the code length of each symbol
+struct Huffman {
+    counts: [u16; 16], // Number of codes of each length
+    symbols: Vec<u16>, // Symbols sorted by code
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::new();
+        for length in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l == length) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    // Reads a code a bit at a time, the codes of each length are consecutive numbers after the shorter ones
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+// Reads bits from the least significant bit of each byte, as deflate packs them
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize, // In bits
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, count: u8) -> io::Result<u32> {
+        let mut value = 0;
+        for k in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| invalid_data("deflate data is cut short"))?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << k;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    // Skips to the start of the next byte
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    // Bytes used so far, counting a started byte
+    fn byte_position(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+}
+
+#[cfg(test)]
+mod zlib_tests {
+    use super::*;
+
+    // Text compressed by the zlib library at level 9, which uses a block with dynamic Huffman codes
+    const DYNAMIC: [u8; 109] = [
+        0x78, 0xda, 0x25, 0xce, 0x41, 0x0e, 0x00, 0x21, 0x08, 0x03, 0xc0, 0xff, 0xf4, 0x44, 0xab,
+        0xa2, 0x86, 0xf0, 0xff, 0x6f, 0xad, 0x0b, 0x27, 0x92, 0x16, 0x26, 0x18, 0x2c, 0x2d, 0x88,
+        0x9d, 0x3b, 0x04, 0xa6, 0x62, 0xe0, 0x24, 0x19, 0x13, 0xca, 0x13, 0x0b, 0x37, 0x3d, 0x1c,
+        0x23, 0x57, 0x6c, 0xd0, 0xde, 0x38, 0x98, 0x2f, 0xba, 0x20, 0x5f, 0x4f, 0xc3, 0xfa, 0xb7,
+        0x49, 0x50, 0xef, 0x98, 0x82, 0x3f, 0x89, 0xa3, 0xd9, 0x59, 0x2e, 0x57, 0xc1, 0xf4, 0x96,
+        0xb9, 0x8b, 0xe6, 0x29, 0x9b, 0xb7, 0x70, 0x59, 0xeb, 0x62, 0xf1, 0x52, 0xfb, 0x1a, 0xed,
+        0x6b, 0xb6, 0xaf, 0x55, 0xbe, 0xbc, 0x7c, 0xed, 0xfe, 0xfb, 0x94, 0xaf, 0xdb, 0xfe, 0x07,
+        0xeb, 0x32, 0x2b, 0x4b,
+    ];
+
+    fn dynamic_text() -> Vec<u8> {
+        (0..30)
+            .map(|i| format!("{}*{}={};", i, i * 7 % 13, i * i * 7 % 13))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn sample_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..70000).map(|i| (i % 251) as u8).collect();
+        data.extend(vec![7; 1000]);
+        data.extend(b"abcabcabcabd".repeat(50));
+        data
+    }
+
+    #[test]
+    fn adler32_checksum() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn stored_and_compressed_data_inflate_back() {
+        let data = sample_data();
+        assert_eq!(zlib_inflate(&zlib_store(&data), data.len()).unwrap(), data);
+        let compressed = zlib_deflate(&data);
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(zlib_inflate(&compressed, data.len()).unwrap(), data);
+        assert_eq!(zlib_inflate(&zlib_store(&[]), 0).unwrap(), Vec::<u8>::new());
+        assert_eq!(
+            zlib_inflate(&zlib_deflate(&[]), 0).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn inflating_dynamic_huffman_codes() {
+        assert_eq!((DYNAMIC[2] >> 1) & 3, 2);
+        assert_eq!(zlib_inflate(&DYNAMIC, usize::MAX).unwrap(), dynamic_text());
+    }
+
+    #[test]
+    fn inflating_past_expected_length_is_an_error() {
+        let zeros = vec![0; 100_000];
+        let compressed = zlib_deflate(&zeros);
+        assert!(compressed.len() < 1000);
+        assert!(zlib_inflate(&compressed, zeros.len() - 1).is_err());
+        assert!(zlib_inflate(&zlib_store(&zeros), zeros.len() - 1).is_err());
+        assert_eq!(zlib_inflate(&compressed, zeros.len()).unwrap(), zeros);
+    }
+
+    #[test]
+    fn corrupt_streams_are_errors() {
+        assert!(zlib_inflate(&[0x78], usize::MAX).is_err());
+        // Wrong header check
+        assert!(zlib_inflate(&[0x78, 0x9D, 3, 0, 0, 0, 0, 1], usize::MAX).is_err());
+        let mut changed = DYNAMIC;
+        changed[60] ^= 0x10;
+        assert!(zlib_inflate(&changed, usize::MAX).is_err());
+        assert!(zlib_inflate(&DYNAMIC[..50], usize::MAX).is_err());
+        let mut checksum = zlib_store(b"data");
+        let last = checksum.len() - 1;
+        checksum[last] ^= 1;
+        assert!(zlib_inflate(&checksum, usize::MAX).is_err());
+    }
+}